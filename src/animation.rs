@@ -0,0 +1,69 @@
+use crate::error::Error;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbaImage};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Duration;
+
+/// Saves frames as an endlessly looping animation, an APNG for a .png file and a GIF for a .gif
+/// file. Every frame has to be the size of the first.
+pub fn save_animation(frames: Vec<(RgbaImage, Duration)>, file: &Path) -> Result<(), Error> {
+    let save_error = |message: String| Error::io(format!("Failed to save animated preview: {}", message));
+
+    let Some((first, _)) = frames.first() else {
+        return Err(save_error("no frames".to_string()));
+    };
+    let (width, height) = first.dimensions();
+    if let Some(index) = frames.iter().position(|(frame, _)| frame.dimensions() != (width, height)) {
+        return Err(Error::validation(format!(
+            "Animated preview needs every frame to be the same size, frame {0} is {1}x{2} instead of {3}x{4}",
+            index + 1,
+            frames[index].0.width(),
+            frames[index].0.height(),
+            width,
+            height
+        )));
+    }
+
+    let writer = File::create(file)
+        .map(BufWriter::new)
+        .map_err(|error| save_error(error.to_string()))?;
+    let extension = file
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+    match extension.as_deref() {
+        Some("gif") => {
+            let mut encoder = GifEncoder::new(writer);
+            encoder.set_repeat(Repeat::Infinite).map_err(|error| save_error(error.to_string()))?;
+            encoder
+                .encode_frames(frames.into_iter().map(|(frame, delay)| {
+                    Frame::from_parts(frame, 0, 0, Delay::from_saturating_duration(delay))
+                }))
+                .map_err(|error| save_error(error.to_string()))
+        }
+        Some("png") => {
+            let mut encoder = png::Encoder::new(writer, width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder
+                .set_animated(frames.len() as u32, 0)
+                .map_err(|error| save_error(error.to_string()))?;
+            let mut writer = encoder.write_header().map_err(|error| save_error(error.to_string()))?;
+            for (frame, delay) in &frames {
+                // the delay is a fraction of two u16, so in milliseconds it goes up to about a minute
+                let milliseconds = u16::try_from(delay.as_millis()).unwrap_or(u16::MAX);
+                writer
+                    .set_frame_delay(milliseconds, 1000)
+                    .and_then(|_| writer.write_image_data(frame.as_raw()))
+                    .map_err(|error| save_error(error.to_string()))?;
+            }
+            writer.finish().map_err(|error| save_error(error.to_string()))
+        }
+        _ => Err(Error::format(format!(
+            "Unsupported animated preview format: {}, use .png or .gif",
+            file.display()
+        ))),
+    }
+}
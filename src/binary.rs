@@ -0,0 +1,202 @@
+use crate::error::Error;
+use crate::poster::{duplicate_pages, Poster, PosterArray};
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 4] = b"2DJB";
+const VERSION: u8 = 1;
+/// Version with a reference before every page image, so pages with the same image store it once.
+const DEDUP_VERSION: u8 = 2;
+
+/// Bits needed to store every index of a palette, index 0 (transparent) included, at most 8.
+fn bits_per_pixel(palette_size: usize) -> u8 {
+    let mut bits = 1;
+    while bits < 8 && (1usize << bits) <= palette_size {
+        bits += 1;
+    }
+    bits
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_string<W: Write>(writer: &mut W, value: &str) -> io::Result<()> {
+    write_u32(writer, value.len() as u32)?;
+    writer.write_all(value.as_bytes())
+}
+
+/// Writes posters in the 2djb format: the palette of every poster and its pixels as packed palette
+/// indices, with little endian numbers. The layout is described in the README. With dedup, pages
+/// with the same image as an earlier one reference it instead of storing it again.
+pub fn write_binary<W: Write>(poster_array: &PosterArray, dedup: bool, mut writer: W) -> io::Result<()> {
+    // 1-based page whose image a page reuses, 0 for pages stored in full
+    let mut references = vec![0u32; poster_array.pages.len()];
+    if dedup {
+        for group in duplicate_pages(poster_array) {
+            for page in &group[1..] {
+                references[*page] = group[0] as u32 + 1;
+            }
+        }
+    }
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[if dedup { DEDUP_VERSION } else { VERSION }])?;
+    write_u32(&mut writer, poster_array.width)?;
+    write_u32(&mut writer, poster_array.height)?;
+    write_string(&mut writer, &poster_array.title)?;
+    write_u32(&mut writer, poster_array.pages.len() as u32)?;
+
+    for (page, reference) in poster_array.pages.iter().zip(references) {
+        write_string(&mut writer, &page.label)?;
+        write_string(&mut writer, &page.tooltip)?;
+        if dedup {
+            write_u32(&mut writer, reference)?;
+            if reference != 0 {
+                continue;
+            }
+        }
+        write_u32(&mut writer, page.width)?;
+        write_u32(&mut writer, page.height)?;
+        writer.write_all(&(page.palette.len() as u16).to_le_bytes())?;
+        for color in &page.palette {
+            write_u32(&mut writer, *color)?;
+        }
+
+        let bits = bits_per_pixel(page.palette.len());
+        writer.write_all(&[bits])?;
+        let mut packed = vec![0u8; (page.pixels.len() * bits as usize).div_ceil(8)];
+        for (index, pixel) in page.pixels.iter().enumerate() {
+            for bit in 0..bits as usize {
+                if pixel >> (bits as usize - 1 - bit) & 1 == 1 {
+                    let position = index * bits as usize + bit;
+                    packed[position / 8] |= 0x80 >> (position % 8);
+                }
+            }
+        }
+        writer.write_all(&packed)?;
+    }
+
+    writer.flush()
+}
+
+struct BinaryReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> BinaryReader<R> {
+    fn bytes(&mut self, length: usize) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        (&mut self.reader)
+            .take(length as u64)
+            .read_to_end(&mut bytes)
+            .map_err(|error| Error::io(format!("Failed to read input file: {}", error)))?;
+        if bytes.len() != length {
+            return Err(Error::decode("Failed to parse 2djb input file: unexpected end of file"));
+        }
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let length = self.u32()? as usize;
+        String::from_utf8(self.bytes(length)?)
+            .map_err(|_| Error::decode("Failed to parse 2djb input file: text is not valid UTF-8"))
+    }
+}
+
+/// Reads posters in the 2djb format written by `write_binary`.
+pub fn read_binary<R: Read>(reader: R) -> Result<PosterArray, Error> {
+    let mut reader = BinaryReader { reader };
+    if reader.bytes(4)? != MAGIC {
+        return Err(Error::decode("Failed to parse 2djb input file: not a 2djb file"));
+    }
+    let version = reader.u8()?;
+    if version != VERSION && version != DEDUP_VERSION {
+        return Err(Error::decode(format!(
+            "Failed to parse 2djb input file: unsupported version {}",
+            version
+        )));
+    }
+
+    let width = reader.u32()?;
+    let height = reader.u32()?;
+    let title = reader.string()?;
+    let page_count = reader.u32()?;
+
+    let mut pages: Vec<Poster> = Vec::new();
+    for index in 0..page_count as usize {
+        let label = reader.string()?;
+        let tooltip = reader.string()?;
+        let reference = if version == DEDUP_VERSION { reader.u32()? as usize } else { 0 };
+        if reference != 0 {
+            let Some(referenced) = pages.get(reference - 1) else {
+                return Err(Error::decode(format!(
+                    "Failed to parse 2djb input file: page {0} reuses page {1}, which doesn't come before it",
+                    index + 1,
+                    reference
+                )));
+            };
+            pages.push(Poster {
+                label,
+                tooltip,
+                palette: referenced.palette.clone(),
+                pixels: referenced.pixels.clone(),
+                width: referenced.width,
+                height: referenced.height,
+            });
+            continue;
+        }
+
+        let page_width = reader.u32()?;
+        let page_height = reader.u32()?;
+        let palette_size = reader.u16()? as usize;
+        let palette = (0..palette_size).map(|_| reader.u32()).collect::<Result<Vec<u32>, Error>>()?;
+
+        let bits = reader.u8()? as usize;
+        if bits == 0 || bits > 8 {
+            return Err(Error::decode(format!(
+                "Failed to parse 2djb input file: unsupported {} bits per pixel",
+                bits
+            )));
+        }
+        let pixel_count = page_width as usize * page_height as usize;
+        let packed = reader.bytes((pixel_count * bits).div_ceil(8))?;
+        let pixels = (0..pixel_count)
+            .map(|index| {
+                (0..bits).fold(0u8, |pixel, bit| {
+                    let position = index * bits + bit;
+                    pixel << 1 | (packed[position / 8] >> (7 - position % 8)) & 1
+                })
+            })
+            .collect();
+
+        pages.push(Poster {
+            label,
+            tooltip,
+            palette,
+            pixels,
+            width: page_width,
+            height: page_height,
+        });
+    }
+
+    Ok(PosterArray {
+        pages,
+        width,
+        height,
+        title,
+    })
+}
@@ -0,0 +1,160 @@
+use crate::error::Error;
+use image::DynamicImage;
+use log::{debug, warn};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Entries that haven't been stored or loaded for this long are removed when a new entry is stored.
+const MAX_ENTRY_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// 64 bit FNV-1a. The keys outlive a single run, so they can't use the std hashers, whose output
+/// may change between Rust releases.
+struct StableHasher(u64);
+
+impl StableHasher {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    /// Writes the length first, so the fields of a key can't run into each other.
+    fn write_field(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+}
+
+/// Builds the cache key of an input file and the parameters it was prepared with. The size and
+/// modification time of the file are part of the key, so changing the file invalidates its entry.
+pub fn cache_key(input: &Path, params: &str) -> Result<String, Error> {
+    let metadata = fs::metadata(input)
+        .map_err(|error| Error::io(format!("Failed to read input file metadata: {}", error)))?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| modified.as_nanos())
+        .unwrap_or_default();
+
+    let mut hasher = StableHasher::new();
+    let input = fs::canonicalize(input).unwrap_or_else(|_| input.to_path_buf());
+    hasher.write_field(input.to_string_lossy().as_bytes());
+    hasher.write_field(&metadata.len().to_le_bytes());
+    hasher.write_field(&modified.to_le_bytes());
+    hasher.write_field(params.as_bytes());
+
+    Ok(format!("{:016x}", hasher.0))
+}
+
+fn entry_path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(key).with_extension("png")
+}
+
+/// Loads a cached image, a missing or unreadable entry is a cache miss. A hit marks the entry as
+/// recently used, so it isn't pruned.
+pub fn load(dir: &Path, key: &str) -> Option<DynamicImage> {
+    let path = entry_path(dir, key);
+    if !path.is_file() {
+        debug!("Cache miss for {}", path.display());
+        return None;
+    }
+
+    match image::open(&path) {
+        Ok(image) => {
+            debug!("Cache hit for {}", path.display());
+            touch(&path);
+            Some(image)
+        }
+        Err(error) => {
+            warn!("Ignoring unreadable cache entry {}: {}", path.display(), error);
+            None
+        }
+    }
+}
+
+/// Stores an image in the cache and prunes stale entries. Failing to do so only prints a warning,
+/// the conversion goes on.
+pub fn store(dir: &Path, key: &str, image: &DynamicImage) {
+    let path = entry_path(dir, key);
+    let result = fs::create_dir_all(dir)
+        .map_err(|error| error.to_string())
+        .and_then(|_| image.save(&path).map_err(|error| error.to_string()));
+
+    match result {
+        Ok(()) => debug!("Cached resized image as {}", path.display()),
+        Err(error) => warn!("Failed to write cache entry {}: {}", path.display(), error),
+    }
+    prune(dir, MAX_ENTRY_AGE);
+}
+
+fn touch(path: &Path) {
+    if let Err(error) = File::options().write(true).open(path).and_then(|file| file.set_modified(SystemTime::now())) {
+        debug!("Failed to mark cache entry {} as used: {}", path.display(), error);
+    }
+}
+
+/// Removes the entries that weren't stored or loaded within max_age. Only png files are looked at,
+/// anything else in the directory is left alone.
+fn prune(dir: &Path, max_age: Duration) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+        if path.extension().is_none_or(|extension| extension != "png") {
+            continue;
+        }
+        let age = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok());
+        if age.is_some_and(|age| age > max_age) {
+            match fs::remove_file(&path) {
+                Ok(()) => debug!("Removed stale cache entry {}", path.display()),
+                Err(error) => warn!("Failed to remove stale cache entry {}: {}", path.display(), error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_hasher_is_fnv1a() {
+        let hash = |bytes: &[u8]| {
+            let mut hasher = StableHasher::new();
+            hasher.write(bytes);
+            hasher.0
+        };
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn prune_removes_only_stale_entries() {
+        let dir = std::env::temp_dir().join(format!("img2poster-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (stale, fresh, other) = (dir.join("stale.png"), dir.join("fresh.png"), dir.join("notes.txt"));
+        for path in [&stale, &fresh, &other] {
+            fs::write(path, b"").unwrap();
+        }
+        let long_ago = SystemTime::now() - 2 * MAX_ENTRY_AGE;
+        for path in [&stale, &other] {
+            File::options().write(true).open(path).unwrap().set_modified(long_ago).unwrap();
+        }
+
+        prune(&dir, MAX_ENTRY_AGE);
+        let remaining = (stale.exists(), fresh.exists(), other.exists());
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(remaining, (false, true, true));
+    }
+}
@@ -0,0 +1,50 @@
+use crate::error::Error;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CONFIG_FILE_NAME: &str = "img2poster.toml";
+
+/// Looks for a config file in the working directory, then in `$XDG_CONFIG_HOME` (or `~/.config`).
+pub fn find_config_file() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+
+    let mut candidates = vec![PathBuf::from(CONFIG_FILE_NAME)];
+    if let Some(config_home) = config_home {
+        candidates.push(config_home.join(CONFIG_FILE_NAME));
+    }
+
+    candidates.into_iter().find(|candidate| candidate.is_file())
+}
+
+/// Reads a config file into the command line arguments it stands for. Keys are the long flag
+/// names, `true` enables a flag, `false` leaves it out.
+pub fn read_config_args(file: &Path) -> Result<Vec<String>, Error> {
+    let text = fs::read_to_string(file)
+        .map_err(|error| Error::io(format!("Failed to read config file {0}: {1}", file.display(), error)))?;
+    let table: toml::Table = text
+        .parse()
+        .map_err(|error| Error::usage(format!("Failed to parse config file {0}: {1}", file.display(), error)))?;
+
+    let mut args = Vec::new();
+    for (key, value) in table {
+        match value {
+            toml::Value::Boolean(true) => args.push(format!("--{}", key)),
+            toml::Value::Boolean(false) => {}
+            toml::Value::String(value) => args.push(format!("--{}={}", key, value)),
+            toml::Value::Integer(value) => args.push(format!("--{}={}", key, value)),
+            toml::Value::Float(value) => args.push(format!("--{}={}", key, value)),
+            _ => {
+                return Err(Error::usage(format!(
+                    "Unsupported value for {0} in config file {1}, expected a string, number or boolean",
+                    key,
+                    file.display()
+                )));
+            }
+        }
+    }
+
+    Ok(args)
+}
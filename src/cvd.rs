@@ -0,0 +1,124 @@
+use crate::preprocess::{linear_to_srgb, srgb_to_linear};
+use image::{DynamicImage, Rgba};
+use std::collections::HashMap;
+
+/// Color vision deficiency the preview is simulated for.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum Deficiency {
+    /// No red cones (about 1% of men)
+    Protanopia,
+    /// No green cones (about 1% of men)
+    Deuteranopia,
+    /// No blue cones (rare)
+    Tritanopia,
+}
+
+impl Deficiency {
+    /// Simulation matrix for linear RGB from Machado, Oliveira and Fernandes (2009), "A
+    /// Physiologically-based Model for Simulation of Color Vision Deficiency", at severity 1.
+    fn matrix(self) -> [[f64; 3]; 3] {
+        match self {
+            Deficiency::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            Deficiency::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            Deficiency::Tritanopia => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+        }
+    }
+
+    /// How a color looks with this deficiency. Alpha is kept.
+    pub fn simulate(self, color: Rgba<u8>) -> Rgba<u8> {
+        let linear = [0, 1, 2].map(|channel| srgb_to_linear(color[channel] as f64 / 255.0));
+        let simulated = self.matrix().map(|row| {
+            let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+            (linear_to_srgb(value.clamp(0.0, 1.0)) * 255.0).round() as u8
+        });
+        Rgba([simulated[0], simulated[1], simulated[2], color[3]])
+    }
+}
+
+/// Simulates a color vision deficiency on every pixel of an image. Posters have few colors, so
+/// every distinct color is only converted once.
+pub fn simulate_image(image: DynamicImage, deficiency: Deficiency) -> DynamicImage {
+    let mut image = image.into_rgba8();
+    let mut simulated = HashMap::new();
+    for pixel in image.pixels_mut() {
+        *pixel = *simulated
+            .entry(*pixel)
+            .or_insert_with(|| deficiency.simulate(*pixel));
+    }
+    DynamicImage::ImageRgba8(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFICIENCIES: [Deficiency; 3] = [Deficiency::Protanopia, Deficiency::Deuteranopia, Deficiency::Tritanopia];
+
+    #[test]
+    fn matrices_keep_white() {
+        // every row of the Machado matrices sums to 1, so neutral colors stay neutral
+        for deficiency in DEFICIENCIES {
+            for row in deficiency.matrix() {
+                assert!((row.iter().sum::<f64>() - 1.0).abs() < 1e-5, "{:?} row {:?}", deficiency, row);
+            }
+        }
+    }
+
+    #[test]
+    fn grays_look_the_same() {
+        for deficiency in DEFICIENCIES {
+            for value in 0..=255 {
+                let simulated = deficiency.simulate(Rgba([value, value, value, 255]));
+                for channel in 0..3 {
+                    assert!(simulated[channel].abs_diff(value) <= 1, "{:?} of gray {} is {:?}", deficiency, value, simulated);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn simulates_reference_colors() {
+        // the Machado (2009) severity 1 matrices applied in linear sRGB, rounded to 8 bits
+        let references = [
+            (Deficiency::Protanopia, [255, 0, 0], [109, 95, 0]),
+            (Deficiency::Protanopia, [0, 255, 0], [255, 229, 0]),
+            (Deficiency::Protanopia, [0, 0, 255], [0, 89, 255]),
+            (Deficiency::Protanopia, [200, 100, 50], [128, 114, 44]),
+            (Deficiency::Deuteranopia, [255, 0, 0], [163, 144, 0]),
+            (Deficiency::Deuteranopia, [0, 255, 0], [239, 214, 58]),
+            (Deficiency::Deuteranopia, [0, 0, 255], [0, 61, 251]),
+            (Deficiency::Deuteranopia, [200, 100, 50], [152, 137, 48]),
+            (Deficiency::Tritanopia, [255, 0, 0], [255, 0, 15]),
+            (Deficiency::Tritanopia, [0, 255, 0], [0, 247, 217]),
+            (Deficiency::Tritanopia, [0, 0, 255], [0, 107, 150]),
+            (Deficiency::Tritanopia, [200, 100, 50], [219, 79, 89]),
+        ];
+        for (deficiency, [r, g, b], expected) in references {
+            let simulated = deficiency.simulate(Rgba([r, g, b, 77]));
+            assert_eq!(simulated.0, [expected[0], expected[1], expected[2], 77], "{:?} of {:?}", deficiency, [r, g, b]);
+        }
+    }
+
+    #[test]
+    fn simulating_an_image_matches_every_pixel() {
+        let image = image::RgbaImage::from_fn(32, 8, |x, y| Rgba([(x * 8) as u8, (y * 32) as u8, (x * y) as u8, 255]));
+        for deficiency in DEFICIENCIES {
+            let simulated = simulate_image(DynamicImage::ImageRgba8(image.clone()), deficiency).into_rgba8();
+            for (pixel, original) in simulated.pixels().zip(image.pixels()) {
+                assert_eq!(*pixel, deficiency.simulate(*original));
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::error::Error;
+use crate::poster::{pixel_color, posters_to_dynamic_image, PosterArray};
+use image::{Rgba, RgbaImage};
+
+/// How many differing pixels are listed with their location, the rest are only counted.
+const LISTED_PIXELS: usize = 20;
+
+/// Finds every pixel whose color differs between two poster arrays of the same grid size, as
+/// (x, y) over the whole print. Colors are compared instead of palette indices, so posters with
+/// the same colors in another palette order don't differ.
+pub fn differing_pixels(old: &PosterArray, new: &PosterArray) -> Result<Vec<(u32, u32)>, Error> {
+    if (old.width, old.height) != (new.width, new.height) {
+        return Err(Error::validation(format!(
+            "Poster grids don't match: {0}x{1} and {2}x{3} posters",
+            old.width, old.height, new.width, new.height
+        )));
+    }
+
+    let mut pixels = Vec::new();
+    for (index, (old_page, new_page)) in old.pages.iter().zip(&new.pages).enumerate() {
+        let (offset_x, offset_y) = ((index as u32 % old.width) * 128, (index as u32 / old.width) * 128);
+        for (pixel_index, (old_pixel, new_pixel)) in old_page.pixels.iter().zip(&new_page.pixels).enumerate() {
+            if pixel_color(old_page, *old_pixel) != pixel_color(new_page, *new_pixel) {
+                pixels.push((offset_x + pixel_index as u32 % 128, offset_y + pixel_index as u32 / 128));
+            }
+        }
+    }
+    Ok(pixels)
+}
+
+pub fn print_diff(poster_array: &PosterArray, pixels: &[(u32, u32)]) {
+    if pixels.is_empty() {
+        println!("Posters are identical");
+        return;
+    }
+
+    let total = poster_array.pages.len() * 128 * 128;
+    println!(
+        "{0} of {1} pixels differ ({2:.2}%)",
+        pixels.len(),
+        total,
+        pixels.len() as f64 / total as f64 * 100.0
+    );
+
+    let mut page_counts = vec![0usize; poster_array.pages.len()];
+    for (x, y) in pixels {
+        page_counts[((y / 128) * poster_array.width + x / 128) as usize] += 1;
+    }
+    for (index, count) in page_counts.iter().enumerate().filter(|(_, count)| **count > 0) {
+        println!(
+            "Poster ({0},{1}): {2} pixels",
+            index as u32 % poster_array.width + 1,
+            index as u32 / poster_array.width + 1,
+            count
+        );
+    }
+
+    let listed: Vec<String> = pixels
+        .iter()
+        .take(LISTED_PIXELS)
+        .map(|(x, y)| format!("({},{})", x, y))
+        .collect();
+    println!(
+        "First differing pixels: {0}{1}",
+        listed.join(" "),
+        if pixels.len() > LISTED_PIXELS { " ..." } else { "" }
+    );
+}
+
+/// Draws the new posters faded out, with the differing pixels in red on top.
+pub fn render_diff(new: &PosterArray, pixels: &[(u32, u32)]) -> RgbaImage {
+    let mut image = posters_to_dynamic_image(new, 1).into_rgba8();
+    for pixel in image.pixels_mut() {
+        for channel in 0..3 {
+            pixel[channel] = (pixel[channel] as u32 + 3 * 255).div_ceil(4) as u8;
+        }
+    }
+    for (x, y) in pixels {
+        image.put_pixel(*x, *y, Rgba([255, 0, 0, 255]));
+    }
+    image
+}
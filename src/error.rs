@@ -0,0 +1,75 @@
+use std::fmt;
+
+/// Category of an error, each one exiting with its own code so scripts can tell them apart.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    /// Invalid combination of arguments
+    Usage,
+    /// Unsupported input, output or preview format
+    Format,
+    /// The input couldn't be decoded or parsed
+    Decode,
+    /// Reading or writing a file failed
+    Io,
+    /// The input is outside a size or length limit
+    Validation,
+    /// The conversion took longer than its timeout
+    Timeout,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Usage => 2,
+            ErrorKind::Format => 3,
+            ErrorKind::Decode => 4,
+            ErrorKind::Io => 5,
+            ErrorKind::Validation => 6,
+            ErrorKind::Timeout => 7,
+        }
+    }
+}
+
+pub struct Error {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Error {
+        Error {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub fn usage(message: impl Into<String>) -> Error {
+        Error::new(ErrorKind::Usage, message)
+    }
+
+    pub fn format(message: impl Into<String>) -> Error {
+        Error::new(ErrorKind::Format, message)
+    }
+
+    pub fn decode(message: impl Into<String>) -> Error {
+        Error::new(ErrorKind::Decode, message)
+    }
+
+    pub fn io(message: impl Into<String>) -> Error {
+        Error::new(ErrorKind::Io, message)
+    }
+
+    pub fn validation(message: impl Into<String>) -> Error {
+        Error::new(ErrorKind::Validation, message)
+    }
+
+    pub fn timeout(message: impl Into<String>) -> Error {
+        Error::new(ErrorKind::Timeout, message)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
@@ -0,0 +1,69 @@
+use crate::error::Error;
+use log::debug;
+use std::time::Duration;
+use ureq::{Agent, ResponseExt};
+
+/// Longest a whole download may take, redirects included.
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// Most redirects followed before giving up.
+const MAX_REDIRECTS: u32 = 10;
+/// Largest response that is read, so a wrong URL can't fill the memory.
+const MAX_RESPONSE_BYTES: u64 = 512 * 1024 * 1024;
+/// Content types a poster file may be served as. Static file servers often send JSON as text/plain
+/// and unknown extensions (like 2djb) as application/octet-stream.
+const CONTENT_TYPES: [&str; 3] = ["application/json", "text/plain", "application/octet-stream"];
+
+/// Downloads a file over http or https, following redirects. Any final response but 200, and
+/// content types that can't be a poster file (like an HTML error page), are errors.
+pub fn fetch(url: &str) -> Result<Vec<u8>, Error> {
+    let download_error = |message: String| Error::io(format!("Failed to download {0}: {1}", url, message));
+
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(TIMEOUT))
+        .max_redirects(MAX_REDIRECTS)
+        .http_status_as_error(false)
+        .user_agent(format!("img2poster/{}", env!("CARGO_PKG_VERSION")))
+        .accept(format!("{}, */*", CONTENT_TYPES.join(", ")))
+        .build()
+        .into();
+
+    let mut response = agent.get(url).call().map_err(|error| match error {
+        ureq::Error::BadUri(_) | ureq::Error::Http(_) => Error::format(format!("Invalid URL: {}", url)),
+        ureq::Error::TooManyRedirects => download_error(format!("more than {} redirects", MAX_REDIRECTS)),
+        error => download_error(error.to_string()),
+    })?;
+    debug!("Downloaded from {}", response.get_uri());
+
+    let status = response.status();
+    if status != 200 {
+        return Err(download_error(format!(
+            "server responded with {0} {1}",
+            status.as_u16(),
+            status.canonical_reason().unwrap_or_default()
+        )));
+    }
+
+    if let Some(content_type) = response.headers().get("content-type") {
+        let content_type = content_type.to_str().unwrap_or_default();
+        let media_type = content_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+        if !CONTENT_TYPES.contains(&media_type.as_str()) {
+            return Err(Error::format(format!(
+                "Failed to download {0}: expected a poster file, the server sent {1}",
+                url, media_type
+            )));
+        }
+    }
+
+    response
+        .body_mut()
+        .with_config()
+        .limit(MAX_RESPONSE_BYTES)
+        .read_to_vec()
+        .map_err(|error| match error {
+            ureq::Error::BodyExceedsLimit(_) => download_error(format!(
+                "response is bigger than {} MB",
+                MAX_RESPONSE_BYTES / 1024 / 1024
+            )),
+            error => download_error(error.to_string()),
+        })
+}
@@ -0,0 +1,46 @@
+use crate::error::Error;
+use image::codecs::gif::GifDecoder;
+use image::io::Limits;
+use image::{AnimationDecoder, DynamicImage, ImageDecoder};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Duration;
+
+fn open(file: &Path, limits: Limits) -> Result<GifDecoder<BufReader<File>>, Error> {
+    let reader = File::open(file)
+        .map(BufReader::new)
+        .map_err(|error| Error::io(format!("Failed to open image: {}", error)))?;
+    GifDecoder::with_limits(reader, limits).map_err(|error| Error::decode(format!("Failed to decode image: {}", error)))
+}
+
+/// Reads how long every frame of a GIF is shown, which also gives the frame count.
+pub fn frame_delays(file: &Path) -> Result<Vec<Duration>, Error> {
+    open(file, Limits::default())?
+        .into_frames()
+        .map(|frame| {
+            frame
+                .map(|frame| Duration::from(frame.delay()))
+                .map_err(|error| Error::decode(format!("Failed to decode image: {}", error)))
+        })
+        .collect()
+}
+
+/// Decodes a single frame of an animated GIF, drawn over the frames before it like a viewer shows
+/// it. The image crate only reads the first frame.
+pub fn read_frame(
+    file: &Path,
+    frame: usize,
+    unlimited: bool,
+    check_dimensions: impl Fn(u32, u32) -> Result<(), Error>,
+) -> Result<DynamicImage, Error> {
+    let decoder = open(file, if unlimited { Limits::no_limits() } else { Limits::default() })?;
+    let (width, height) = decoder.dimensions();
+    check_dimensions(width, height)?;
+
+    match decoder.into_frames().nth(frame) {
+        Some(Ok(decoded)) => Ok(DynamicImage::ImageRgba8(decoded.into_buffer())),
+        Some(Err(error)) => Err(Error::decode(format!("Failed to decode frame {0} of image: {1}", frame + 1, error))),
+        None => Err(Error::decode(format!("Image has no frame {}", frame + 1))),
+    }
+}
@@ -1,27 +1,115 @@
 use exoquant::Color;
-use image::{DynamicImage, GenericImageView, Pixel};
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba};
+use crate::error::Error;
 use crate::poster;
+use crate::output::{status, status_start};
 use crate::poster::Poster;
+use crate::stats::{self, FlatPoster};
+
+use log::{debug, warn};
 
 use std::thread;
-use std::sync::Arc;
-use std::sync::mpsc::{SyncSender, Receiver, sync_channel};
+use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::sync_channel;
+
+/// How palettes are computed and stored across the posters of an image.
+#[derive(clap::ValueEnum, Clone, Copy, Default, PartialEq)]
+pub enum QuantizationMode {
+    /// One palette computed over the whole image, stored in full on every poster
+    #[default]
+    Shared,
+    /// A separate palette computed for every poster
+    PerPoster,
+    /// One palette computed over the whole image, every poster stores only the colors it uses
+    SharedPalettePerTile,
+}
 
 struct PosterWithPosition {
     poster: Poster,
     x: u32,
     y: u32,
+    flat: Option<FlatPoster>,
+}
+
+/// Converts a pixel to an opaque color, or to a fully transparent one (no ink) if its alpha is
+/// below the threshold.
+fn to_color(pixel: Rgba<u8>, alpha_threshold: Option<u8>) -> Color {
+    let alpha = match alpha_threshold {
+        Some(threshold) if pixel[3] < threshold => 0,
+        _ => 255,
+    };
+    Color::new(pixel[0], pixel[1], pixel[2], alpha)
+}
+
+/// How an image is quantized into posters, see `image_to_posters`.
+#[derive(Clone)]
+pub struct QuantizeOptions<'a> {
+    /// How palettes are computed and stored across the posters
+    pub mode: QuantizationMode,
+    /// Threads quantizing posters side by side with per poster quantization, 1 without
+    pub threads: Option<u32>,
+    /// Most colors a poster may use with per poster quantization
+    pub max_colors_per_poster: Option<usize>,
+    /// Cancels the conversion once set. It is checked between posters, and a cancelled conversion
+    /// fails with a timeout error
+    pub cancelled: Option<&'a AtomicBool>,
+    /// Colorspace palette colors are compared in
+    pub color_distance: poster::ColorDistance,
+    /// How the palette is derived from the image colors
+    pub palette_generation: poster::PaletteGeneration,
+    /// How pixels are mapped onto the palette, see `poster::dither`
+    pub search: poster::PaletteSearch<'a>,
+    /// Pixels with a lower alpha are transparent (no ink), every other pixel is opaque
+    pub alpha_threshold: Option<u8>,
 }
 
-pub fn image_to_posters<F1,F2>(image: DynamicImage, label_generator: F1, tooltip_generator: F2, per_poster_quantization: (bool, Option<u32>)) -> poster::PosterArray
+/// Splits an image into quantized posters. Posters that are almost entirely a single color are
+/// detected while quantizing and returned alongside the poster array.
+pub fn image_to_posters<F1,F2>(image: DynamicImage, label_generator: F1, tooltip_generator: F2, options: &QuantizeOptions) -> Result<(poster::PosterArray, Vec<FlatPoster>), Error>
 where
     F1: Fn(u32, u32, u32, u32) -> String + Send + Sync + 'static, // label_generator:   pos_x, pos_y, width, height
     F2: Fn(u32, u32, u32, u32) -> String + Send + Sync + 'static, // tooltip_generator: pos_x, pos_y, width, height
 {
-    //let mut poster_array.pages: Vec<Poster> = Vec::new();
+    let (x_size, y_size) = image.dimensions();
+    let image = image.into_rgba8();
+    rgba_to_posters((image.as_raw(), x_size, y_size), label_generator, tooltip_generator, options)
+}
+
+/// Labels posters that were quantized with other labels and tooltips, calling the generators the
+/// way `image_to_posters` does.
+pub fn label_posters<F1,F2>(poster_array: &mut poster::PosterArray, label_generator: F1, tooltip_generator: F2)
+where
+    F1: Fn(u32, u32, u32, u32) -> String, // label_generator:   pos_x, pos_y, width, height
+    F2: Fn(u32, u32, u32, u32) -> String, // tooltip_generator: pos_x, pos_y, width, height
+{
+    let (width, height) = (poster_array.width, poster_array.height);
+    for (index, page) in poster_array.pages.iter_mut().enumerate() {
+        let (x, y) = (index as u32 % width, index as u32 / width);
+        page.label = label_generator(x, y, width, height);
+        page.tooltip = tooltip_generator(x, y, width, height);
+    }
+}
 
+/// Splits a buffer of 8 bit RGBA pixels, row by row, into quantized posters, like
+/// `image_to_posters` does for a `DynamicImage`. `rgba` is the buffer with its width and height.
+///
+/// Fails with a validation error if the width or height isn't a non-zero multiple of 128, or the
+/// buffer isn't width * height * 4 bytes long.
+pub fn rgba_to_posters<F1,F2>(rgba: (&[u8], u32, u32), label_generator: F1, tooltip_generator: F2, options: &QuantizeOptions) -> Result<(poster::PosterArray, Vec<FlatPoster>), Error>
+where
+    F1: Fn(u32, u32, u32, u32) -> String + Send + Sync + 'static, // label_generator:   pos_x, pos_y, width, height
+    F2: Fn(u32, u32, u32, u32) -> String + Send + Sync + 'static, // tooltip_generator: pos_x, pos_y, width, height
+{
     let block_size = 128;
-    let (x_size, y_size) = image.dimensions();
+    let (pixels, x_size, y_size) = rgba;
+    if x_size == 0 || y_size == 0 || x_size % block_size != 0 || y_size % block_size != 0 {
+        return Err(Error::validation(format!("Image resolutions have to be non-zero multiples of 128 (Currently x:{0} y:{1})", x_size, y_size)));
+    }
+    let image: ImageBuffer<Rgba<u8>, &[u8]> = Some(pixels)
+        .filter(|pixels| pixels.len() as u64 == u64::from(x_size) * u64::from(y_size) * 4)
+        .and_then(|pixels| ImageBuffer::from_raw(x_size, y_size, pixels))
+        .ok_or_else(|| Error::validation(format!("RGBA buffer has to be width * height * 4 bytes long (Currently {0} bytes for x:{1} y:{2})", pixels.len(), x_size, y_size)))?;
 
     let mut poster_array: poster::PosterArray = poster::PosterArray {
         pages: Vec::new(),
@@ -29,58 +117,85 @@ where
         height: y_size/block_size,
         title: "untitled".to_string(), // TODO: do title
     };
+    let mut flat_posters: Vec<FlatPoster> = Vec::new();
 
-    let (per_poster_quantization, per_poster_quantization_thread_count) = per_poster_quantization;
+    let is_cancelled = || options.cancelled.is_some_and(|cancelled| cancelled.load(Ordering::Relaxed));
+    let cancelled_error = || Error::timeout("Conversion cancelled");
+    let (color_distance, search, alpha_threshold) = (options.color_distance, options.search, options.alpha_threshold);
 
-    if per_poster_quantization {
-        let (sender, receiver): (SyncSender<(Poster,u32,u32)>, Receiver<(Poster,u32,u32)>) = sync_channel(0);
+    if options.mode == QuantizationMode::PerPoster {
+        let (sender, receiver) = sync_channel::<(Poster, u32, u32, Option<FlatPoster>)>(0);
 
         let block_count = (x_size/block_size)*(y_size/block_size);
 
-        let per_poster_quantization_thread_count = u32::min(per_poster_quantization_thread_count.unwrap_or(1), block_count);
+        let per_poster_quantization_thread_count = u32::min(options.threads.unwrap_or(1), block_count);
 
         let blocks_per_thread = block_count / per_poster_quantization_thread_count;
         let remaining_blocks = block_count % per_poster_quantization_thread_count;
 
         let mut pos: u32 = 0;
 
-        let label_generator = Arc::new(label_generator);
-        let tooltip_generator = Arc::new(tooltip_generator);
-        let image = Arc::new(image);
+        let mut pages: Vec<PosterWithPosition> = Vec::new();
 
+        thread::scope(|scope| {
         for i in 0..per_poster_quantization_thread_count {
             let thread_block_count = if i<remaining_blocks { blocks_per_thread+1 } else { blocks_per_thread };
 
             let thread_range = pos..pos+thread_block_count;
             pos += thread_block_count;
 
-            let label_generator = label_generator.clone();
-            let tooltip_generator = tooltip_generator.clone();
+            let label_generator = &label_generator;
+            let tooltip_generator = &tooltip_generator;
+            let palette_generation = &options.palette_generation;
 
-            let image = image.clone();
+            let image = &image;
             
             let sender = sender.clone();
 
-            thread::spawn(move || {
-                let label_generator = label_generator.clone();
-                let tooltip_generator = tooltip_generator.clone();
-
+            scope.spawn(move || {
                 for i in thread_range {
+                    if is_cancelled() {
+                        break;
+                    }
                     let (block_x,block_y) = ( i%(x_size/block_size), i/(x_size/block_size));
 
                     let mut pixels: Vec<Color> = Vec::new();
 
                     for y in 0..block_size {
                         for x in 0..block_size {
-                            let pixel = image
+                            let pixel = *image
                                 .get_pixel(x + block_x * block_size, y + block_y * block_size);
 
-                            let rgb = pixel.to_rgb();
-                            pixels.push(Color::new(rgb[0], rgb[1], rgb[2], 255));
+                            pixels.push(to_color(pixel, alpha_threshold));
+                        }
+                    }
+
+                    let start = Instant::now();
+                    let (mut dithered_pixels, mut color_palette) = poster::dither(pixels.clone(), block_size as usize, color_distance, palette_generation, search);
+                    if let Some(max_colors) = options.max_colors_per_poster {
+                        let used_colors = poster::subset_palette(&dithered_pixels, &color_palette).1.len();
+                        if used_colors > max_colors {
+                            warn!(
+                                "Poster ({},{}) uses {} colors, more than the {} allowed per poster, quantizing it again",
+                                block_x,
+                                block_y,
+                                used_colors,
+                                max_colors
+                            );
+                            let capped = palette_generation.capped(max_colors, &dithered_pixels, &color_palette);
+                            (dithered_pixels, color_palette) = poster::dither(pixels, block_size as usize, color_distance, &capped, search);
                         }
+                        (dithered_pixels, color_palette) = poster::subset_palette(&dithered_pixels, &color_palette);
                     }
+                    debug!(
+                        "Quantized poster ({},{}) to {} colors in {:?}",
+                        block_x,
+                        block_y,
+                        color_palette.len(),
+                        start.elapsed()
+                    );
 
-                    let (dithered_pixels, color_palette) = poster::dither(pixels, block_size as usize);
+                    let flat = stats::flat_poster(&dithered_pixels, &color_palette, block_x, block_y);
 
                     let poster: Poster = Poster {
                         label: label_generator(block_x, block_y, x_size / block_size, y_size / block_size),
@@ -91,53 +206,65 @@ where
                         height: block_size,
                     };
 
-                    sender.send((poster, block_x, block_y)).unwrap();
+                    sender.send((poster, block_x, block_y, flat)).unwrap();
                 }
             });
         }
 
-        let mut pages: Vec<PosterWithPosition> = Vec::new();
-
-        let mut last_percentage: f64 = -1 as f64;
+        // once every thread is done, a cancelled conversion stops receiving early
+        drop(sender);
+        let mut last_percentage: f64 = -1.0;
 
         for i in 0..block_count {
-            let (poster,x,y) = receiver.recv().unwrap();
+            let Ok((poster,x,y,flat)) = receiver.recv() else {
+                break;
+            };
             pages.push(PosterWithPosition {
-                poster: poster,
-                x: x,
-                y: y
+                poster,
+                x,
+                y,
+                flat
             });
-            let percentage = (i as f64/block_count as f64)*100 as f64;
-            if last_percentage+(1 as f64) < percentage {
+            let percentage = (i as f64/block_count as f64)*100.0;
+            if last_percentage+1.0 < percentage {
                 last_percentage = percentage;
-                println!("Converting image to posters: {}%", percentage as u32);
+                status!("Converting image to posters: {}%", percentage as u32);
             }
         }
+        });
         
+        if pages.len() as u32 != block_count {
+            return Err(cancelled_error());
+        }
         pages.sort_by_key(|x| x.x+x.y*(x_size/block_size));
 
         for page in pages {
             poster_array.pages.push(page.poster);
+            flat_posters.extend(page.flat);
         }
     } else {
         let mut pixels: Vec<Color> = Vec::with_capacity((block_size * block_size) as usize);
 
-        print!("Parsing image... ");
+        status_start!("Parsing image... ");
         for y in 0..y_size {
             for x in 0..x_size {
-                let pixel = image.get_pixel(x, y);
+                let pixel = *image.get_pixel(x, y);
 
-                let rgb = pixel.to_rgb();
-                pixels.push(Color::new(rgb[0], rgb[1], rgb[2], 255));
+                pixels.push(to_color(pixel, alpha_threshold));
             }
         }
-        println!("Done");
+        status!("Done");
 
-        print!("Quantizing and dithering image... ");
-        let (dithered_pixels, color_palette) = poster::dither(pixels, x_size as usize);
-        println!("Done");
+        status_start!("Quantizing and dithering image... ");
+        let start = Instant::now();
+        let (dithered_pixels, color_palette) = poster::dither(pixels, x_size as usize, color_distance, &options.palette_generation, search);
+        status!("Done");
+        debug!("Quantized image to {} colors in {:?}", color_palette.len(), start.elapsed());
 
         for block_y in 0..y_size / block_size {
+            if is_cancelled() {
+                return Err(cancelled_error());
+            }
             for block_x in 0..x_size / block_size {
                 let mut block_pixels: Vec<u8> =
                     Vec::with_capacity((block_size * block_size) as usize);
@@ -151,10 +278,17 @@ where
                     }
                 }
 
+                let (block_pixels, block_palette) = if options.mode == QuantizationMode::SharedPalettePerTile {
+                    poster::subset_palette(&block_pixels, &color_palette)
+                } else {
+                    (block_pixels, color_palette.clone())
+                };
+                flat_posters.extend(stats::flat_poster(&block_pixels, &block_palette, block_x, block_y));
+
                 let poster: Poster = Poster {
                     label: label_generator(block_x, block_y, x_size / block_size, y_size / block_size),
                     tooltip: tooltip_generator(block_x, block_y, x_size / block_size, y_size / block_size),
-                    palette: color_palette.clone(),
+                    palette: block_palette,
                     pixels: block_pixels,
                     width: block_size,
                     height: block_size,
@@ -163,16 +297,127 @@ where
                 poster_array.pages.push(poster);
             }
 
-            println!(
+            status!(
                 "Splitting image into posters: {0}% complete",
                 f32::min(
-                    100 as f32,
-                    f32::max(0 as f32, block_y as f32 / ((y_size / block_size) as f32)) * (100 as f32)
+                    100.0,
+                    f32::max(0.0, block_y as f32 / ((y_size / block_size) as f32)) * 100.0
                 )
             );
         }
-        println!("Splitting image into posters: 100% complete");
+        status!("Splitting image into posters: 100% complete");
+    }
+
+    Ok((poster_array, flat_posters))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use image::RgbaImage;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// 3x1 posters of gradients with noise, and a few transparent pixels.
+    fn test_image() -> DynamicImage {
+        let mut rng = StdRng::seed_from_u64(7);
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(384, 128, |x, y| {
+            let noise = rng.gen_range(0..32);
+            let alpha = if (x + y) % 97 == 0 { 0 } else { 255 };
+            Rgba([(x * 255 / 383) as u8 ^ noise, (y * 2) as u8, ((x + y) / 3) as u8 + noise, alpha])
+        }))
+    }
+
+    /// Median cut to 16 colors, on one thread and without an alpha threshold.
+    fn quantize_options(mode: QuantizationMode) -> QuantizeOptions<'static> {
+        QuantizeOptions {
+            mode,
+            threads: None,
+            max_colors_per_poster: None,
+            cancelled: None,
+            color_distance: poster::ColorDistance::default(),
+            palette_generation: poster::PaletteGeneration::MedianCut { colors: 16 },
+            search: poster::PaletteSearch::default(),
+            alpha_threshold: None,
+        }
+    }
+
+    fn quantize(palette_generation: &poster::PaletteGeneration, threads: u32, max_colors: Option<usize>) -> poster::PosterArray {
+        image_to_posters(
+            test_image(),
+            |x, y, _, _| format!("{0},{1}", x, y),
+            |_, _, _, _| String::new(),
+            &QuantizeOptions {
+                threads: Some(threads),
+                max_colors_per_poster: max_colors,
+                palette_generation: palette_generation.clone(),
+                alpha_threshold: Some(128),
+                ..quantize_options(QuantizationMode::PerPoster)
+            },
+        )
+        .unwrap_or_else(|error| panic!("{}", error))
+        .0
     }
 
-    return poster_array;
-}
\ No newline at end of file
+    #[test]
+    fn per_poster_quantization_doesnt_depend_on_thread_count() {
+        let runs = [
+            (poster::PaletteGeneration::MedianCut { colors: 16 }, None),
+            (poster::PaletteGeneration::MedianCut { colors: 16 }, Some(6)),
+            (poster::PaletteGeneration::KMeans { colors: 8, seed: 3 }, None),
+        ];
+        for (palette_generation, max_colors) in &runs {
+            let single_threaded = quantize(palette_generation, 1, *max_colors);
+            assert_eq!(single_threaded.pages.len(), 3);
+            for threads in [2, 3, 8] {
+                assert!(
+                    quantize(palette_generation, threads, *max_colors) == single_threaded,
+                    "{} threads gave other posters than 1",
+                    threads
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bad_rgba_buffers_are_validation_errors() {
+        let pixels = vec![0; 256 * 128 * 4];
+        for (len, width, height) in [(pixels.len(), 200, 128), (pixels.len(), 0, 128), (pixels.len() - 4, 256, 128), (pixels.len(), 128, 128)] {
+            let result = rgba_to_posters(
+                (&pixels[..len], width, height),
+                |_, _, _, _| String::new(),
+                |_, _, _, _| String::new(),
+                &quantize_options(QuantizationMode::Shared),
+            );
+            assert!(matches!(result, Err(ref error) if error.kind == ErrorKind::Validation), "{0}x{1} with {2} bytes", width, height, len);
+        }
+    }
+
+    #[test]
+    fn label_posters_labels_like_quantizing_does() {
+        fn label(x: u32, y: u32, w: u32, h: u32) -> String {
+            format!("{0},{1} of {2}x{3}", x, y, w, h)
+        }
+        fn tooltip(x: u32, y: u32, _: u32, _: u32) -> String {
+            format!("{}", x * 10 + y)
+        }
+        fn unlabelled(_: u32, _: u32, _: u32, _: u32) -> String {
+            String::new()
+        }
+        let quantize = |label_generator: fn(u32, u32, u32, u32) -> String, tooltip_generator: fn(u32, u32, u32, u32) -> String| {
+            image_to_posters(
+                test_image(),
+                label_generator,
+                tooltip_generator,
+                &quantize_options(QuantizationMode::Shared),
+            )
+            .unwrap_or_else(|error| panic!("{}", error))
+            .0
+        };
+
+        let mut relabelled = quantize(unlabelled, unlabelled);
+        label_posters(&mut relabelled, label, tooltip);
+        assert!(relabelled == quantize(label, tooltip));
+    }
+}
@@ -0,0 +1,152 @@
+use crate::error::Error;
+use crate::poster::{subset_palette, Poster, PosterArray};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Side of a poster in pixels.
+const POSTER_SIZE: u32 = 128;
+
+/// Label and tooltip of one poster of a 2dji print.
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PosterMetadata {
+    pub label: String,
+    pub tooltip: String,
+}
+
+/// The 2dji format: a whole print as one indexed image with a single palette, plus the label and
+/// tooltip of every poster. Pixel 0 is transparent and pixel N is palette color N, like in 2dj.
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct IndexedImage {
+    pub title: String,
+    /// Width of the print in posters
+    pub width: u32,
+    /// Height of the print in posters
+    pub height: u32,
+    pub palette: Vec<u32>,
+    /// width * 128 by height * 128 palette indices, row by row
+    #[serde(with = "serde_bytes")]
+    pub pixels: Vec<u8>,
+    /// Row by row, like the pages of a 2dja
+    pub posters: Vec<PosterMetadata>,
+}
+
+impl IndexedImage {
+    /// Joins the posters into one image, with every color a poster uses in the shared palette
+    /// once, in the order they first appear. Fails if that is more than 255 colors.
+    pub fn from_poster_array(poster_array: &PosterArray) -> Result<IndexedImage, Error> {
+        let image_width = poster_array.width * POSTER_SIZE;
+        let mut pixels = vec![0u8; (image_width * poster_array.height * POSTER_SIZE) as usize];
+        let mut palette: Vec<u32> = Vec::new();
+        let mut indices: HashMap<u32, usize> = HashMap::new();
+
+        for (page_index, page) in poster_array.pages.iter().enumerate() {
+            let (left, top) = (
+                page_index as u32 % poster_array.width * POSTER_SIZE,
+                page_index as u32 / poster_array.width * POSTER_SIZE,
+            );
+            for (pixel_index, pixel) in page.pixels.iter().enumerate() {
+                if *pixel == 0 {
+                    continue;
+                }
+                let color = page.palette[*pixel as usize - 1];
+                let index = *indices.entry(color).or_insert_with(|| {
+                    palette.push(color);
+                    palette.len()
+                });
+                if index > u8::MAX as usize {
+                    return Err(Error::validation(format!(
+                        "2dji supports at most {} colors across the print, the posters use more",
+                        u8::MAX
+                    )));
+                }
+
+                let (x, y) = (left + pixel_index as u32 % page.width, top + pixel_index as u32 / page.width);
+                pixels[(y * image_width + x) as usize] = index as u8;
+            }
+        }
+
+        Ok(IndexedImage {
+            title: poster_array.title.clone(),
+            width: poster_array.width,
+            height: poster_array.height,
+            palette,
+            pixels,
+            posters: poster_array
+                .pages
+                .iter()
+                .map(|page| PosterMetadata {
+                    label: page.label.clone(),
+                    tooltip: page.tooltip.clone(),
+                })
+                .collect(),
+        })
+    }
+
+    /// Splits the image into posters, every one with only the palette colors it uses.
+    pub fn into_poster_array(self) -> Result<PosterArray, Error> {
+        let image_width = self.width as u64 * POSTER_SIZE as u64;
+        let expected_pixels = image_width * self.height as u64 * POSTER_SIZE as u64;
+        if self.pixels.len() as u64 != expected_pixels {
+            return Err(Error::decode(format!(
+                "Invalid 2dji: {0}x{1} posters should have {2} pixels, found {3}",
+                self.width,
+                self.height,
+                expected_pixels,
+                self.pixels.len()
+            )));
+        }
+        if self.posters.len() as u64 != self.width as u64 * self.height as u64 {
+            return Err(Error::decode(format!(
+                "Invalid 2dji: {0}x{1} posters should have {2} labels and tooltips, found {3}",
+                self.width,
+                self.height,
+                self.width as u64 * self.height as u64,
+                self.posters.len()
+            )));
+        }
+        if let Some(pixel) = self.pixels.iter().find(|pixel| **pixel as usize > self.palette.len()) {
+            return Err(Error::decode(format!(
+                "Invalid 2dji: pixel {0} is outside the palette of {1} colors",
+                pixel,
+                self.palette.len()
+            )));
+        }
+
+        let pages = self
+            .posters
+            .into_iter()
+            .enumerate()
+            .map(|(page_index, metadata)| {
+                let (left, top) = (
+                    page_index as u64 % self.width as u64 * POSTER_SIZE as u64,
+                    page_index as u64 / self.width as u64 * POSTER_SIZE as u64,
+                );
+                let pixels: Vec<u8> = (top..top + POSTER_SIZE as u64)
+                    .flat_map(|y| {
+                        let row = (y * image_width + left) as usize;
+                        self.pixels[row..row + POSTER_SIZE as usize].iter().copied()
+                    })
+                    .collect();
+                let (pixels, palette) = subset_palette(&pixels, &self.palette);
+
+                Poster {
+                    label: metadata.label,
+                    tooltip: metadata.tooltip,
+                    palette,
+                    pixels,
+                    width: POSTER_SIZE,
+                    height: POSTER_SIZE,
+                }
+            })
+            .collect();
+
+        Ok(PosterArray {
+            pages,
+            width: self.width,
+            height: self.height,
+            title: self.title,
+        })
+    }
+}
@@ -0,0 +1,86 @@
+use crate::poster::PosterArray;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+#[derive(Serialize)]
+struct PageInfo<'a> {
+    label: &'a str,
+    tooltip: &'a str,
+    width: u32,
+    height: u32,
+    palette: &'a [u32],
+}
+
+#[derive(Serialize)]
+struct PosterArrayInfo<'a> {
+    title: &'a str,
+    width: u32,
+    height: u32,
+    page_count: usize,
+    pages: Vec<PageInfo<'a>>,
+    palette: Vec<u32>,
+}
+
+/// Every palette color used across the pages, sorted and deduplicated.
+pub fn distinct_colors(poster_array: &PosterArray) -> Vec<u32> {
+    let colors: BTreeSet<u32> = poster_array
+        .pages
+        .iter()
+        .flat_map(|page| page.palette.iter().cloned())
+        .collect();
+    colors.into_iter().collect()
+}
+
+pub fn print_info(poster_array: &PosterArray, json: bool) {
+    let palette = distinct_colors(poster_array);
+
+    if json {
+        let info = PosterArrayInfo {
+            title: &poster_array.title,
+            width: poster_array.width,
+            height: poster_array.height,
+            page_count: poster_array.pages.len(),
+            pages: poster_array
+                .pages
+                .iter()
+                .map(|page| PageInfo {
+                    label: &page.label,
+                    tooltip: &page.tooltip,
+                    width: page.width,
+                    height: page.height,
+                    palette: &page.palette,
+                })
+                .collect(),
+            palette,
+        };
+        println!("{}", serde_json::to_string_pretty(&info).unwrap());
+        return;
+    }
+
+    println!("Title: {}", poster_array.title);
+    println!(
+        "Size: {0}x{1} posters, {2} pages",
+        poster_array.width,
+        poster_array.height,
+        poster_array.pages.len()
+    );
+
+    for (index, page) in poster_array.pages.iter().enumerate() {
+        println!(
+            "Page {0} ({1},{2}): {3}x{4}, {5} palette colors",
+            index + 1,
+            index as u32 % poster_array.width.max(1) + 1,
+            index as u32 / poster_array.width.max(1) + 1,
+            page.width,
+            page.height,
+            page.palette.len()
+        );
+        println!("  Label: {}", page.label);
+        println!("  Tooltip: {}", page.tooltip);
+    }
+
+    println!("Distinct palette colors: {}", palette.len());
+    for color in palette {
+        println!("  #{:06X}", color);
+    }
+}
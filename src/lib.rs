@@ -0,0 +1,15 @@
+//! Converting images into SwitchCraft3 posters, the library side of the img2poster CLI.
+//!
+//! [`image_to_poster`] quantizes and splits an image into posters, [`poster`] holds the poster
+//! types and reads and writes them. The other modules are shared with the CLI.
+
+pub mod binary;
+pub mod error;
+pub mod image_to_poster;
+pub mod indexed;
+pub mod output;
+pub mod poster;
+pub mod quantizer;
+#[cfg(feature = "simd")]
+mod simd;
+pub mod stats;
@@ -1,28 +1,128 @@
-mod image_to_poster;
-mod poster;
+mod animation;
+mod cache;
+mod config;
+mod cvd;
+mod diff;
+#[cfg(feature = "url")]
+mod fetch;
+mod gif_frames;
+mod info;
+mod manifest;
+mod memory;
+mod naming;
+mod orientation;
+mod preprocess;
+mod schema;
+mod sheet;
+mod tiff_pages;
+#[cfg(feature = "tui")]
+mod tui;
 
-use clap::{arg, command, Parser};
-use image::io::Reader as ImageReader;
-use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use clap::{Parser, ValueEnum};
+use img2poster::{error, image_to_poster, output, poster, stats};
+use error::{Error, ErrorKind};
+use exoquant::Color;
+use log::{debug, warn, LevelFilter};
+use image::io::{Limits, Reader as ImageReader};
+use image::{imageops, imageops::FilterType, DynamicImage, GenericImageView, ImageError, Rgba, RgbaImage};
+use image_to_poster::{QuantizationMode, QuantizeOptions};
+use output::{note, status};
 use poster::*;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::ffi::OsString;
 use std::fs;
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+#[cfg(feature = "notify")]
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(PartialEq)]
+/// How close to their limit labels and tooltips get before the stored text is printed.
+const LABEL_LENGTH_MARGIN: usize = 4;
+const TOOLTIP_LENGTH_MARGIN: usize = 32;
+
+/// Length limits of the poster printer, used unless overridden for servers with other limits.
+const LABEL_LIMIT: usize = 23;
+const FORCED_LABEL_LIMIT: usize = 48;
+const TOOLTIP_LIMIT: usize = 256;
+
+/// How long the input of watch has to go without changes before it is converted, so an editor
+/// saving in several steps only triggers one conversion.
+#[cfg(feature = "notify")]
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+/// How long a frame of the animated preview is shown when the input has no timing for it, like
+/// TIFF pages or GIF frames without a delay.
+const DEFAULT_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+/// Levels of gray a palette weight of 2 adds to the distance of a color by default.
+const PALETTE_WEIGHT_THRESHOLD: u8 = 16;
+
+/// Most posters an image may be split into unless max-posters says otherwise.
+const DEFAULT_MAX_POSTERS: u32 = 256;
+
+/// How often an output file another program has locked is retried by default.
+const WRITE_RETRIES: u32 = 5;
+/// Wait before the first retry of a locked output file, doubled for every retry after it.
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Longest total wait for a locked output file, however many retries are allowed.
+const WRITE_RETRY_LIMIT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, PartialEq)]
 enum Format {
     Image,
+    Poster(PosterFormat),
+}
+
+/// File format given on the command line, used instead of the extension.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug)]
+enum FileFormat {
+    /// Any supported image format, detected from the file contents. Written as PNG
+    Image,
+    /// Single poster
+    #[value(name = "2dj")]
     Poster,
+    /// Poster array
+    #[value(name = "2dja")]
+    PosterArray,
+    /// Binary poster array
+    #[value(name = "2djb")]
+    BinaryPosterArray,
+    /// Poster array as one image with a shared palette
+    #[value(name = "2dji")]
+    IndexedImage,
+}
+
+impl FileFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            FileFormat::Image => "png",
+            FileFormat::Poster => PosterFormat::Poster.extension(),
+            FileFormat::PosterArray => PosterFormat::PosterArray.extension(),
+            FileFormat::BinaryPosterArray => PosterFormat::Binary.extension(),
+            FileFormat::IndexedImage => PosterFormat::Indexed.extension(),
+        }
+    }
 }
 
-#[derive(clap::ValueEnum, Clone)]
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
 enum ResizeAlgorithm {
     Nearest,
     Triangle,
     CatmullRom,
     Gaussian,
     Lanczos3,
+    /// Exact average of every source block, for shrinking by whole factors. Other sizes use
+    /// triangle
+    Box,
 }
 
 impl From<ResizeAlgorithm> for FilterType {
@@ -33,488 +133,4423 @@ impl From<ResizeAlgorithm> for FilterType {
             ResizeAlgorithm::CatmullRom => FilterType::CatmullRom,
             ResizeAlgorithm::Gaussian => FilterType::Gaussian,
             ResizeAlgorithm::Lanczos3 => FilterType::Lanczos3,
+            // box is handled before resizing, this is its fallback for other sizes
+            ResizeAlgorithm::Box => FilterType::Triangle,
         }
     }
 }
 
-#[derive(clap::Parser)]
-#[command(author, version, about, long_about = None)]
-struct Cli {
-    #[arg(short, long, value_name = "INPUT_FILE")]
-    input: PathBuf,
+/// Resize algorithm for the x and y axis, given as one algorithm for both or as x,y like
+/// nearest,lanczos3.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ResizeFilter {
+    x: ResizeAlgorithm,
+    y: ResizeAlgorithm,
+}
 
-    #[arg(short, long, value_name = "OUTPUT_FILE")]
-    output: PathBuf,
+impl Default for ResizeFilter {
+    fn default() -> Self {
+        ResizeFilter {
+            x: ResizeAlgorithm::CatmullRom,
+            y: ResizeAlgorithm::CatmullRom,
+        }
+    }
+}
 
-    #[arg(short = 'p', long, value_name = "PREVIEW_OUTPUT_FILE")]
-    preview: Option<PathBuf>,
+impl ResizeFilter {
+    /// Resizes an image, in two passes (width first, then height) when the axes use different
+    /// algorithms.
+    fn resize(self, image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+        if self.x == self.y {
+            return resize_pass(image, width, height, self.x);
+        }
 
-    #[arg(short = 'x', long, value_name = "SCALE_X")]
-    scale_x: Option<u32>,
+        debug!("Resizing in two passes, x with {:?} and y with {:?}", self.x, self.y);
+        let resized = resize_pass(image, width, image.height(), self.x);
+        resize_pass(&resized, width, height, self.y)
+    }
+}
 
-    #[arg(short = 'y', long, value_name = "SCALE_Y")]
-    scale_y: Option<u32>,
+/// Resizes an image with a single algorithm. Box averages the blocks of whole shrink factors
+/// exactly, and falls back to triangle with a warning for any other size.
+fn resize_pass(image: &DynamicImage, width: u32, height: u32, algorithm: ResizeAlgorithm) -> DynamicImage {
+    if algorithm == ResizeAlgorithm::Box {
+        let (source_width, source_height) = image.dimensions();
+        let whole_factor = |source: u32, target: u32| target > 0 && target <= source && source.is_multiple_of(target);
+        if whole_factor(source_width, width) && whole_factor(source_height, height) {
+            debug!(
+                "Box resizing by {}x{}",
+                source_width / width,
+                source_height / height
+            );
+            return preprocess::downsample(image, width, height, preprocess::DownsampleMode::Average);
+        }
+        warn!(
+            "Box resizing needs whole shrink factors, {0}x{1} to {2}x{3} isn't one, using triangle instead",
+            source_width, source_height, width, height
+        );
+    }
+    image.resize_exact(width, height, algorithm.into())
+}
 
-    /// Algorithm to use for resizing and scaling. Defaults to catmull-rom
-    #[arg(short = 'r', long, value_name = "RESIZE_ALGORITHM")]
-    resize_algorithm: Option<ResizeAlgorithm>,
+fn parse_resize_algorithm(value: &str) -> Result<ResizeFilter, String> {
+    let parse = |value: &str| {
+        ResizeAlgorithm::from_str(value.trim(), true)
+            .map_err(|_| {
+                format!(
+                    "invalid resize algorithm: {} (expected nearest, triangle, catmull-rom, gaussian, lanczos3 or box, or two of them like nearest,lanczos3)",
+                    value
+                )
+            })
+    };
 
-    #[arg(short = 'a', long, value_name = "AUTOSCALE")]
-    autoscale: Option<f64>,
+    match value.split_once(',') {
+        Some((x, y)) => Ok(ResizeFilter {
+            x: parse(x)?,
+            y: parse(y)?,
+        }),
+        None => {
+            let filter = parse(value)?;
+            Ok(ResizeFilter { x: filter, y: filter })
+        }
+    }
+}
 
-    #[arg(short, long, value_name = "LABEL")]
-    label: Option<String>,
+/// What to do when a resize would enlarge the image.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug)]
+enum ShrinkOnly {
+    /// Keep the original size of dimensions that would be enlarged
+    Keep,
+    /// Fail instead of enlarging
+    Error,
+}
 
-    #[arg(short = 'L', long = "forcelabel", value_name = "FORCED_LABEL")]
-    force_label: Option<String>,
+/// What to do when the image is wider than max-width or taller than max-height.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug)]
+enum MaxSizeMode {
+    /// Scale the image down to fit, keeping its aspect ratio
+    Downscale,
+    /// Fail instead of downscaling
+    Error,
+}
 
-    #[arg(short = 'T', long = "forcetooltip", value_name = "TOOLTIP")]
-    force_tooltip: Option<String>,
+/// How an image is fitted into the poster grid of fit.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug)]
+enum FitMode {
+    /// Scale the image to fit inside the grid, padding the rest with transparent pixels
+    Contain,
+    /// Scale the image to fill the whole grid, cropping what sticks out
+    Cover,
+}
 
-    #[arg(short = 'Q', long)]
-    per_poster_quantization: bool,
+/// Which way autoscale rounds the scaled size to a multiple of 128.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+enum Rounding {
+    /// Round to the closest multiple, up from half a poster
+    #[default]
+    Nearest,
+    /// Always round up, never cropping detail but possibly stretching the image
+    Up,
+    /// Always round down, never adding more posters than the image fills
+    Down,
+}
 
-    #[arg(short = 'j', long, value_name = "JOBS")]
-    jobs: Option<u32>,
+/// Color space the preview is drawn in.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+enum PreviewColorspace {
+    /// The stored poster colors, unchanged
+    #[default]
+    Srgb,
+    /// The stored colors through the tone curve of the game, to look like the print on screen
+    Game,
 }
 
-fn read_image(image_file: &PathBuf) -> (bool, Option<DynamicImage>) {
-    let image_reader = ImageReader::open(image_file);
-    if image_reader.is_err() {
-        return (false, None);
-    }
+/// Tone curve exponent assumed for how the game displays poster colors: a little darker in the
+/// midtones than the stored sRGB values.
+const GAME_PREVIEW_GAMMA: f64 = 1.1;
 
-    let mut decoder = image_reader.unwrap();
-    decoder.no_limits();
+/// Corner the poster coordinates in labels and tooltips count from.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+enum Origin {
+    /// Rows count down from the top
+    #[default]
+    TopLeft,
+    /// Rows count up from the bottom
+    BottomLeft,
+}
 
-    let decoded_image = decoder.decode();
-    if decoded_image.is_err() {
-        return (false, None);
+impl Origin {
+    /// Row of a poster counted from this origin, for a print that is height posters high.
+    fn row(self, y: u32, height: u32) -> u32 {
+        match self {
+            Origin::TopLeft => y,
+            Origin::BottomLeft => height - 1 - y,
+        }
     }
+}
 
-    return (true, Some(decoded_image.unwrap()));
+/// A resize target, either in pixels, in posters (tiles of 128 pixels) or relative to the input.
+#[derive(Clone, Copy, Debug)]
+enum Scale {
+    Pixels(u32),
+    Tiles(u32),
+    Percent(f64),
 }
 
-fn autoscale_image(mut width: u32, mut height: u32, scale: f64) -> (u32, u32) {
-    //TODO: make this attempt to preserve aspect ratio later
-    width = (width as f64 * scale) as u32;
-    height = (height as f64 * scale) as u32;
-    let (wr, hr) = (width % 128, height % 128);
-    let (scaled_x, scaled_y) = (
-        if wr >= 64 {
-            width + (128 - wr)
-        } else {
-            width - wr
-        },
-        if hr >= 64 {
-            height + (128 - hr)
-        } else {
-            height - hr
-        },
-    );
-    return (
-        if scaled_x == 0 { 128 } else { scaled_x },
-        if scaled_y == 0 { 128 } else { scaled_y },
-    );
+impl Scale {
+    fn resolve(self, original: u32) -> u32 {
+        match self {
+            Scale::Pixels(pixels) => pixels,
+            Scale::Tiles(tiles) => tiles * 128,
+            Scale::Percent(percent) => (original as f64 * percent / 100.0).round() as u32,
+        }
+    }
 }
 
-fn main() {
-    let cli = Cli::parse();
+/// Parses a number of posters in each direction, like 3x2.
+fn parse_grid(value: &str) -> Result<(u32, u32), String> {
+    let error = || format!("invalid size: {} (expected posters like 3x2)", value);
+    let value = value.trim().to_lowercase();
+    let (width, height) = value.split_once('x').ok_or_else(error)?;
+
+    match (width.trim().parse::<u32>(), height.trim().parse::<u32>()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(error()),
+    }
+}
 
-    let per_poster_quantization_enabled = cli.per_poster_quantization;
+/// Parses an aspect ratio like 4:3 into the width divided by the height.
+fn parse_aspect(value: &str) -> Result<f64, String> {
+    let error = || format!("invalid aspect: {} (expected a ratio like 4:3 or 1:1.5)", value);
+    let (width, height) = value.split_once(':').ok_or_else(error)?;
 
-    if !cli.input.exists() {
-        eprintln!("Input file doesn't exist.");
-        return;
+    match (width.trim().parse::<f64>(), height.trim().parse::<f64>()) {
+        (Ok(width), Ok(height)) if width.is_finite() && height.is_finite() && width > 0.0 && height > 0.0 => {
+            Ok(width / height)
+        }
+        _ => Err(error()),
     }
-    if cli.input.is_dir() {
-        eprintln!("Input can't be a directory.");
-        return;
+}
+
+/// Parses a scale factor, which has to be a finite number above 0.
+fn parse_factor(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(factor) if factor.is_finite() && factor > 0.0 => Ok(factor),
+        _ => Err(format!("invalid factor: {} (expected a number above 0, like 1 or 0.5)", value)),
     }
+}
 
-    if cli.output.is_dir() {
-        eprintln!("Output can't be a directory.");
-        return;
+/// Parses a number of seconds above 0, like 30 or 2.5.
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    match value.trim().parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds > 0.0 => Ok(Duration::from_secs_f64(seconds)),
+        _ => Err(format!("invalid duration: {} (expected seconds above 0, like 30 or 2.5)", value)),
     }
+}
 
-    match cli.output.parent() {
-        Some(parent) => {
-            if !parent.exists() {
-                eprintln!("Output file parent directory doesn't exist.");
-                return;
-            } else if !parent.is_dir() {
-                eprintln!("Output file parent is not a directory.");
-                return;
-            }
-        }
-        None => {
-            eprintln!("Output file parent directory doesn't exist.");
-            return;
-        }
+/// Parses a job count, a number above 0 or auto for the available parallelism.
+fn parse_jobs(value: &str) -> Result<u32, String> {
+    if value.trim().eq_ignore_ascii_case("auto") {
+        return Ok(available_jobs());
+    }
+    match value.trim().parse::<u32>() {
+        Ok(jobs) if jobs > 0 => Ok(jobs),
+        _ => Err(format!("invalid job count: {} (expected a number above 0 or auto)", value)),
     }
+}
 
-    if let Some(ref preview) = cli.preview {
-        match preview.parent() {
-            Some(parent) => {
-                if !parent.exists() {
-                    eprintln!("Preview file parent directory doesn't exist.");
-                    return;
-                } else if !parent.is_dir() {
-                    eprintln!("Preview file parent is not a directory.");
-                    return;
-                }
-            }
-            None => {
-                eprintln!("Preview file parent directory doesn't exist.");
-                return;
-            }
-        }
+/// Number of threads the system can run at once, 1 if it can't be determined.
+fn available_jobs() -> u32 {
+    thread::available_parallelism()
+        .map(|count| count.get() as u32)
+        .unwrap_or(1)
+}
 
-        let preview_extension = match preview.extension() {
-            Some(t) => t,
-            None => {
-                eprintln!("Preview file has no extension.");
-                return;
-            }
-        }
-        .to_str()
-        .unwrap()
-        .to_lowercase();
-        let preview_extension = preview_extension.as_str();
+fn job_count(options: &ConvertOptions) -> u32 {
+    options.jobs.unwrap_or_else(available_jobs)
+}
 
-        match preview_extension {
-            "png" => Format::Image,
-            "jpg" => Format::Image,
-            "jpeg" => Format::Image,
-            "bmp" => Format::Image,
-            _ => {
-                eprintln!("Unsupported preview format: {}", preview_extension);
-                return;
-            }
-        };
-    }
+fn parse_scale(value: &str) -> Result<Scale, String> {
+    let value = value.trim().to_lowercase();
 
-    let input_extension = match cli.input.extension() {
-        Some(t) => t,
-        None => {
-            eprintln!("Input file has no extension.");
-            return;
+    if let Some(percent) = value.strip_suffix('%') {
+        let percent: f64 = percent
+            .parse()
+            .map_err(|_| format!("invalid percentage: {}", value))?;
+        if !percent.is_finite() || percent <= 0.0 {
+            return Err(format!("invalid percentage: {} (has to be more than 0%)", value));
         }
+        return Ok(Scale::Percent(percent));
     }
-    .to_str()
-    .unwrap()
-    .to_lowercase();
-    let output_extension = match cli.output.extension() {
-        Some(t) => t,
-        None => {
-            eprintln!("Output file has no extension.");
-            return;
-        }
+
+    let (number, tiles) = if let Some(number) = value.strip_suffix("px") {
+        (number, false)
+    } else if let Some(number) = value.strip_suffix('t') {
+        (number, true)
+    } else {
+        (value.as_str(), false)
+    };
+    let number: u32 = number.parse().map_err(|_| {
+        format!(
+            "invalid scale: {} (expected pixels like 512 or 512px, tiles like 4t, or a percentage like 200%)",
+            value
+        )
+    })?;
+    if number == 0 {
+        return Err(format!("invalid scale: {} (has to be more than 0)", value));
+    }
+    if tiles && number.checked_mul(128).is_none() {
+        return Err(format!("invalid scale: {} (too many tiles)", value));
     }
-    .to_str()
-    .unwrap()
-    .to_lowercase();
-    let input_extension = input_extension.as_str();
-    let output_extension = output_extension.as_str();
 
-    let input_format: Format = match input_extension {
-        "png" => Format::Image,
-        "jpg" => Format::Image,
-        "jpeg" => Format::Image,
-        "bmp" => Format::Image,
-        // can likely support more image formats, but cant be bothered
-        "2dj" => Format::Poster,
-        "2dja" => Format::Poster,
-        _ => {
-            eprintln!("Unsupported input format: {}", input_extension);
-            return;
-        }
-    };
-    let output_format: Format = match output_extension {
-        "png" => Format::Image,
-        "jpg" => Format::Image,
-        "jpeg" => Format::Image,
-        "bmp" => Format::Image,
-        // can likely support more image formats, but cant be bothered
-        "2dj" => Format::Poster,
-        "2dja" => Format::Poster,
-        _ => {
-            eprintln!("Unsupported output format: {}", output_extension);
-            return;
-        }
+    Ok(if tiles {
+        Scale::Tiles(number)
+    } else {
+        Scale::Pixels(number)
+    })
+}
+
+/// Colors that can be given by name to every color argument.
+const NAMED_COLORS: [(&str, [u8; 4]); 18] = [
+    ("black", [0, 0, 0, 255]),
+    ("white", [255, 255, 255, 255]),
+    ("gray", [128, 128, 128, 255]),
+    ("grey", [128, 128, 128, 255]),
+    ("silver", [192, 192, 192, 255]),
+    ("red", [255, 0, 0, 255]),
+    ("maroon", [128, 0, 0, 255]),
+    ("orange", [255, 165, 0, 255]),
+    ("yellow", [255, 255, 0, 255]),
+    ("olive", [128, 128, 0, 255]),
+    ("lime", [0, 255, 0, 255]),
+    ("green", [0, 128, 0, 255]),
+    ("cyan", [0, 255, 255, 255]),
+    ("teal", [0, 128, 128, 255]),
+    ("blue", [0, 0, 255, 255]),
+    ("navy", [0, 0, 128, 255]),
+    ("magenta", [255, 0, 255, 255]),
+    ("transparent", [0, 0, 0, 0]),
+];
+
+/// Parses a color as hex (#RRGGBB or #RRGGBBAA, the # is optional), rgb(R, G, B) or
+/// rgb(R, G, B, A) with channels from 0 to 255, or one of the named colors. Colors without an
+/// alpha are opaque.
+fn parse_color(value: &str) -> Result<Rgba<u8>, String> {
+    let invalid = || {
+        format!(
+            "invalid color: {} (expected hex like #FF8000 or #FF800080, rgb(255, 128, 0), rgb(255, 128, 0, 128) or one of {})",
+            value,
+            NAMED_COLORS.map(|(name, _)| name).join(", ")
+        )
     };
+    let color = value.trim().to_lowercase();
 
-    // TODO: clean up
-    {
-        let mut e: bool = false;
-        if input_format == Format::Poster {
-            if cli.per_poster_quantization {
-                eprintln!("per-poster-quantization flag only allowed with input format: Image");
-                e = true;
-            }
-            if cli.label.is_some() {
-                eprintln!("label arg only allowed with input format: Image");
-                e = true;
-            }
-            if cli.force_label.is_some() {
-                eprintln!("force-label arg only allowed with input format: Image");
-                e = true;
-            }
-            if cli.force_tooltip.is_some() {
-                eprintln!("force-tooltip arg only allowed with input format: Image");
-                e = true;
-            }
-            if cli.scale_x.is_some() {
-                eprintln!("scale-x arg only allowed with input format: Image");
-                e = true;
-            }
-            if cli.scale_y.is_some() {
-                eprintln!("scale-y arg only allowed with input format: Image");
-                e = true;
-            }
-            if cli.autoscale.is_some() {
-                eprintln!("autoscale arg only allowed with input format: Image");
-                e = true;
-            }
-        }
+    if let Some((_, channels)) = NAMED_COLORS.iter().find(|(name, _)| *name == color) {
+        return Ok(Rgba(*channels));
+    }
 
-        if cli.autoscale.is_some() {
-            if cli.scale_x.is_some() {
-                eprintln!("scale-x arg not allowed with autoscale");
-                e = true;
-            }
-            if cli.scale_y.is_some() {
-                eprintln!("scale-y arg not allowed with autoscale");
-                e = true;
-            }
-        }
+    if let Some(channels) = color.strip_prefix("rgb(").and_then(|color| color.strip_suffix(')')) {
+        let channels = channels
+            .split(',')
+            .map(|channel| channel.trim().parse::<u8>())
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+        return match channels[..] {
+            [red, green, blue] => Ok(Rgba([red, green, blue, 255])),
+            [red, green, blue, alpha] => Ok(Rgba([red, green, blue, alpha])),
+            _ => Err(invalid()),
+        };
+    }
 
-        if e {
-            return;
-        }
+    let hex = color.strip_prefix('#').unwrap_or(&color);
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
     }
+    let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).unwrap();
+    let alpha = if hex.len() == 8 { channel(6) } else { 255 };
+    Ok(Rgba([channel(0), channel(2), channel(4), alpha]))
+}
 
-    let mut poster_array: poster::PosterArray;
-    if input_format == Format::Image {
-        let (image_ok, image) = read_image(&cli.input);
-        if !image_ok {
-            eprintln!("Failed to decode or open image.");
-            return;
-        }
-        let mut unwrapped_image = image.unwrap();
+/// Parses a color replacement like #FF0000:#0000FF, with an optional tolerance like
+/// #FF0000:#0000FF:16.
+fn parse_color_replacement(value: &str) -> Result<preprocess::ColorReplacement, String> {
+    let mut parts = value.split(':');
+    let (Some(from), Some(to)) = (parts.next(), parts.next()) else {
+        return Err(format!(
+            "invalid color replacement: {} (expected FROM:TO like #FF0000:#0000FF, optionally with a tolerance like #FF0000:#0000FF:16)",
+            value
+        ));
+    };
+    let tolerance = match parts.next() {
+        Some(tolerance) => tolerance
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid tolerance: {} (expected 0 to 255)", tolerance))?,
+        None => 0,
+    };
+    if parts.next().is_some() {
+        return Err(format!("invalid color replacement: {} (expected FROM:TO[:TOLERANCE])", value));
+    }
 
-        let (mut x_size, mut y_size) = unwrapped_image.dimensions();
+    Ok(preprocess::ColorReplacement {
+        from: parse_color(from)?,
+        to: parse_color(to)?,
+        tolerance,
+    })
+}
 
-        {
-            let mut resize = false;
-            let (mut resize_x, mut resize_y) = (x_size, y_size);
+#[derive(clap::Parser)]
+#[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true, args_override_self = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-            if let Some(res) = cli.scale_x {
-                resize = true;
-                resize_x = res;
-            }
+    #[arg(short, long, value_name = "INPUT_FILE", required = true)]
+    input: Option<PathBuf>,
 
-            if let Some(res) = cli.scale_y {
-                resize = true;
-                resize_y = res;
-            }
+    #[arg(short, long, value_name = "OUTPUT_FILE", required_unless_present = "clipboard")]
+    output: Option<PathBuf>,
 
-            if cli.autoscale.is_some() {
-                let (x, y) = autoscale_image(x_size, y_size, cli.autoscale.unwrap());
-                if x != x_size || y != y_size {
-                    resize_x = x;
-                    resize_y = y;
-                    resize = true;
-                }
-            }
+    #[arg(short = 'p', long, value_name = "PREVIEW_OUTPUT_FILE")]
+    preview: Option<PathBuf>,
 
-            if resize && (resize_x < 1 || resize_y < 1) {
-                eprintln!("Can't resize to x:{0} y:{1}", resize_x, resize_y);
-                return;
-            }
+    /// Contact sheet with every poster in a grid and its label below it
+    #[arg(long, value_name = "SHEET_OUTPUT_FILE")]
+    sheet: Option<PathBuf>,
 
-            if resize && ((resize_x % 128 != 0) || (resize_y % 128 != 0)) {
-                eprintln!("Image resolutions have to be multiples of 128 (Attempted to resize to x:{0} y:{1})",resize_x, resize_y);
-                return;
-            }
+    /// Copy the poster JSON to the clipboard, output is optional with this
+    #[arg(long)]
+    clipboard: bool,
 
-            if resize {
-                println!(
-                    "Resizing image to x:{0} y:{1} (from x:{2} y:{3})",
-                    resize_x, resize_y, x_size, y_size
-                );
+    #[command(flatten)]
+    config: ConfigArgs,
 
-                x_size = resize_x;
-                y_size = resize_y;
-                unwrapped_image = unwrapped_image.resize_exact(
-                    resize_x,
-                    resize_y,
-                    cli.resize_algorithm
-                        .unwrap_or(ResizeAlgorithm::CatmullRom)
-                        .into(),
-                );
-            }
-        }
+    #[command(flatten)]
+    options: ConvertOptions,
+}
 
-        if (x_size % 128 != 0) || (y_size % 128 != 0) {
-            eprintln!(
-                "Image resolutions have to be multiples of 128 (Currently x:{0} y:{1})",
-                x_size, y_size
-            );
-            return;
-        }
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Convert every supported image in a directory to a 2dja poster
+    #[command(args_override_self = true)]
+    Batch(Box<BatchArgs>),
+    /// Print the dimensions, labels, tooltips and palette of a 2dj/2dja file
+    Info(InfoArgs),
+    /// Compare the pixels of two 2dj/2dja files, exiting with 1 when they differ
+    Diff(DiffArgs),
+    /// Convert an image with every palette generation and color distance, comparing their error
+    /// and time
+    #[command(args_override_self = true)]
+    Bench(Box<BenchArgs>),
+    /// Convert an image again every time it changes
+    #[cfg(feature = "notify")]
+    #[command(args_override_self = true)]
+    Watch(Box<WatchArgs>),
+    /// Switch palette colors on and off in the terminal while watching the result, then convert
+    /// the image with the picked colors
+    #[cfg(feature = "tui")]
+    #[command(args_override_self = true)]
+    Tui(Box<TuiArgs>),
+}
 
-        let mut forced_label: bool = false;
-        let label: String;
+#[derive(clap::Args)]
+struct BatchArgs {
+    #[arg(short, long, value_name = "INPUT_DIR")]
+    input: PathBuf,
 
-        if let Some(txt) = cli.force_label {
-            label = txt.to_string();
-            forced_label = true;
-            if label.len() > 48 {
-                eprintln!(
-                    "Forced label can't be longer than 48 characters, currently {0}",
-                    label.len()
-                );
-                return;
-            }
-        } else if let Some(txt) = cli.label {
-            label = txt.to_string();
-            if label.len() > 23 {
-                eprintln!(
-                    "Label can't be longer than 23 characters, currently {0}",
-                    label.len()
-                );
-                return;
-            }
-        } else {
-            label = "PatriikPlays/img2poster".to_string();
-        }
+    #[arg(short, long, value_name = "OUTPUT_DIR")]
+    output: PathBuf,
 
-        let mut use_forced_tooltip = false;
-        let mut forced_tooltip: String = "".to_string();
-        if let Some(txt) = cli.force_tooltip {
-            forced_tooltip = txt.to_string();
-            use_forced_tooltip = true;
-            if forced_tooltip.len() > 256 {
-                eprintln!(
-                    "Forced tooltip can't be longer than 256 characters, currently {0}",
-                    forced_tooltip.len()
-                );
-                return;
-            }
-        }
+    #[command(flatten)]
+    config: ConfigArgs,
 
-        let print_id = format!("{:0>6}", rand::thread_rng().gen_range(0..999999));
+    #[command(flatten)]
+    options: ConvertOptions,
+}
 
-        let label_generator_label = label.clone();
-        let tooltip_generator_label = label.clone();
+#[derive(clap::Args)]
+struct BenchArgs {
+    #[arg(short, long, value_name = "INPUT_FILE")]
+    input: PathBuf,
 
-        poster_array = image_to_poster::image_to_posters(
-            unwrapped_image,
-            move |x, y, w, h| {
-                if forced_label {
-                    return label.clone();
-                } else {
-                    return format!(
-                        "{0}: ({1},{2})/({3}x{4})",
-                        label_generator_label.clone(),
-                        x + 1,
-                        y + 1,
-                        w,
-                        h
-                    );
-                }
-            },
-            move |x, y, w, h| {
-                let tooltip: PosterTooltip = PosterTooltip {
-                    print_id: print_id.clone(),
-                    print_name: tooltip_generator_label.clone(),
-                    total_width: w,
-                    total_height: h,
-                    pos_x: x,
-                    pos_y: y,
-                    info: "https://github.com/PatriikPlays/img2poster".to_string(),
-                };
+    /// Directory to save a preview of every variant in
+    #[arg(long, value_name = "OUT_DIR")]
+    out_dir: Option<PathBuf>,
 
-                if use_forced_tooltip {
-                    return forced_tooltip.clone();
-                } else {
-                    return serde_json::to_string(&tooltip)
-                        .unwrap()
-                        .as_str()
-                        .to_string();
-                }
-            },
-            (per_poster_quantization_enabled, Some(cli.jobs.unwrap_or(1))),
-        );
-    } else if input_format == Format::Poster {
-        if input_extension == "2dj" {
-            poster_array = PosterArray {
-                pages: vec![],
-                width: 1,
-                height: 1,
-                title: "untitled".to_string(),
-            };
-            let reader = File::open(cli.input).expect("Failed to open input file.");
-            poster_array
-                .pages
-                .push(serde_json::from_reader(reader).expect("Failed to parse json in input file"));
-        } else if input_extension == "2dja" {
-            let reader = File::open(cli.input).expect("Failed to open input file.");
-            poster_array =
-                serde_json::from_reader(reader).expect("Failed to parse json in input file");
-        } else {
-            eprintln!("Shouldn't have gotten here 0");
-            return;
-        }
-    } else {
-        eprintln!("Shouldn't have gotten here 1");
-        return;
-    }
+    #[command(flatten)]
+    config: ConfigArgs,
 
-    println!("Done, saving to file");
-    if output_format == Format::Poster {
-        match output_extension {
-            "2dj" => {
-                if poster_array.pages.len() > 1 {
-                    eprintln!("Format 2dj doesn't support multi poster images.");
-                    return;
-                }
+    #[command(flatten)]
+    options: ConvertOptions,
+}
 
-                let json_str = serde_json::to_string(&poster_array.pages[0])
-                    .expect("Failed to serialize this somehow");
-                fs::write(&cli.output, json_str).expect("Failed to write to output file.");
+#[cfg(feature = "notify")]
+#[derive(clap::Args)]
+struct WatchArgs {
+    #[arg(short, long, value_name = "INPUT_FILE")]
+    input: PathBuf,
 
-                if let Some(ref preview) = cli.preview {
-                    println!("Generating preview...");
-                    let output_image = posters_to_dynamic_image(&poster_array);
-                    output_image
-                        .save(preview)
-                        .expect("Failed to save preview image.");
-                }
-            }
-            "2dja" => {
-                let json_str =
-                    serde_json::to_string(&poster_array).expect("Failed to serialize this somehow");
-                fs::write(cli.output, json_str).expect("Failed to write to output file.");
-                if let Some(ref preview) = cli.preview {
-                    println!("Generating preview...");
-                    let output_image = posters_to_dynamic_image(&poster_array);
-                    output_image
-                        .save(preview)
-                        .expect("Failed to save preview image.");
-                }
-            }
-            _ => {
-                eprintln!("Invalid output extension: {}.", output_extension);
-                return;
-            }
-        }
-    } else if output_format == Format::Image {
-        let output_image = posters_to_dynamic_image(&poster_array);
+    #[arg(short, long, value_name = "OUTPUT_FILE")]
+    output: PathBuf,
+
+    #[arg(short = 'p', long, value_name = "PREVIEW_OUTPUT_FILE")]
+    preview: Option<PathBuf>,
+
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    #[command(flatten)]
+    options: ConvertOptions,
+}
+
+#[cfg(feature = "tui")]
+#[derive(clap::Args)]
+struct TuiArgs {
+    #[arg(short, long, value_name = "INPUT_FILE")]
+    input: PathBuf,
+
+    /// Output for the posters. The picked palette is saved next to it as <OUTPUT>-palette.png
+    #[arg(short, long, value_name = "OUTPUT_FILE")]
+    output: PathBuf,
+
+    #[arg(short = 'p', long, value_name = "PREVIEW_OUTPUT_FILE")]
+    preview: Option<PathBuf>,
+
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    #[command(flatten)]
+    options: ConvertOptions,
+}
+
+#[derive(clap::Args)]
+struct ConfigArgs {
+    /// Config file with default values for the flags. Defaults to img2poster.toml in the working
+    /// directory, then in $XDG_CONFIG_HOME
+    #[arg(long, value_name = "CONFIG_FILE")]
+    config: Option<PathBuf>,
+
+    /// Don't look for a config file
+    #[arg(long, conflicts_with = "config")]
+    no_config: bool,
+}
+
+/// The flags of a config file on their own, parsed to report mistakes in the file.
+#[derive(clap::Parser)]
+#[command(name = "img2poster.toml", no_binary_name = true)]
+struct ConfigFile {
+    #[command(flatten)]
+    options: ConvertOptions,
+}
+
+#[derive(clap::Args)]
+struct InfoArgs {
+    #[arg(short, long, value_name = "INPUT_FILE")]
+    input: PathBuf,
+
+    /// Print the information as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args)]
+struct DiffArgs {
+    #[arg(value_name = "OLD_FILE")]
+    old: PathBuf,
+
+    #[arg(value_name = "NEW_FILE")]
+    new: PathBuf,
+
+    /// Save an image of the new posters with the differing pixels in red
+    #[arg(long, value_name = "IMAGE_FILE")]
+    image: Option<PathBuf>,
+
+    /// Overwrite the image if it already exists
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(clap::Args, Clone)]
+struct ConvertOptions {
+    /// Format of the input file (image, 2dj, 2dja, 2djb or 2dji), instead of detecting it from the extension
+    #[arg(long, value_name = "FORMAT")]
+    input_format: Option<FileFormat>,
+
+    /// Format of the output file (image, 2dj, 2dja, 2djb or 2dji), instead of detecting it from the extension
+    #[arg(long, value_name = "FORMAT")]
+    output_format: Option<FileFormat>,
+
+    /// Factor to scale the preview by, using the resize algorithm. Defaults to 1.0
+    #[arg(long, value_name = "PREVIEW_SCALE")]
+    preview_scale: Option<f64>,
+
+    /// Aspect ratio (W:H) of a poster pixel in game, the preview is stretched to it. Defaults to 1:1
+    #[arg(long, value_name = "W:H", value_parser = parse_aspect)]
+    preview_aspect: Option<f64>,
+
+    /// Size of every poster pixel in the preview or image output, upscaled with nearest neighbor.
+    /// Applied before preview-scale. Defaults to 1
+    #[arg(long, value_name = "PIXEL_SCALE")]
+    preview_pixel_scale: Option<u32>,
+
+    /// Factor to upscale the image output by, for a wallpaper-sized render of a print. Uses nearest
+    /// neighbor unless smooth is given. Defaults to preview-pixel-scale
+    #[arg(long, value_name = "N")]
+    upscale: Option<u32>,
+
+    /// Upscale the image output with the resize algorithm instead of nearest neighbor
+    #[arg(long)]
+    smooth: bool,
+
+    /// Color space of the preview (srgb or game), never changing the stored posters. Defaults to
+    /// srgb
+    #[arg(long, value_name = "COLORSPACE")]
+    preview_colorspace: Option<PreviewColorspace>,
+
+    /// Exponent of the tone curve of the game preview colorspace. Defaults to 1.1
+    #[arg(long, value_name = "GAMMA")]
+    preview_gamma: Option<f64>,
+
+    /// Simulate a color vision deficiency in the preview, to check the print for color blind
+    /// viewers, never changing the stored posters
+    #[arg(long, value_name = "DEFICIENCY")]
+    preview_cvd: Option<cvd::Deficiency>,
+
+    /// Number of posters per row in the sheet. Defaults to the width of the poster array
+    #[arg(long, value_name = "COLUMNS")]
+    sheet_columns: Option<u32>,
+
+    /// Width to resize to, in pixels (512, 512px), posters (4t) or percent of the input (200%)
+    #[arg(short = 'x', long, value_name = "SCALE_X", value_parser = parse_scale)]
+    scale_x: Option<Scale>,
+
+    /// Height to resize to, in pixels (512, 512px), posters (4t) or percent of the input (200%)
+    #[arg(short = 'y', long, value_name = "SCALE_Y", value_parser = parse_scale)]
+    scale_y: Option<Scale>,
+
+    /// Algorithm to use for resizing and scaling (nearest, triangle, catmull-rom, gaussian or
+    /// lanczos3), or one for each axis as x,y. Defaults to catmull-rom
+    #[arg(short = 'r', long, value_name = "RESIZE_ALGORITHM", value_parser = parse_resize_algorithm)]
+    resize_algorithm: Option<ResizeFilter>,
+
+    /// Combine the block of source pixels under every poster pixel (average, mode or median)
+    /// instead of using the resize algorithm. Defaults to the resize algorithm
+    #[arg(long, value_name = "DOWNSAMPLE_MODE")]
+    downsample_mode: Option<preprocess::DownsampleMode>,
+
+    #[arg(short = 'a', long, value_name = "AUTOSCALE", value_parser = parse_factor)]
+    autoscale: Option<f64>,
+
+    /// Which way autoscale rounds to a multiple of 128 (nearest, up or down). Defaults to nearest
+    #[arg(long, value_name = "ROUNDING")]
+    round: Option<Rounding>,
+
+    /// Crop away uniform margins, the color of the top left pixel, before resizing
+    #[arg(long)]
+    trim: bool,
+
+    /// How far (0-255 on every channel) a color may be from the margin color and still be trimmed.
+    /// Defaults to 0
+    #[arg(long, value_name = "TOLERANCE")]
+    trim_tolerance: Option<u8>,
+
+    /// Make pixels of this color transparent before anything else, for sprites on a green or
+    /// magenta background instead of an alpha channel. Hex (#RRGGBB), rgb(R, G, B) or a color name
+    #[arg(long, value_name = "COLOR", value_parser = parse_color)]
+    chroma_key: Option<Rgba<u8>>,
+
+    /// How far (0-255 on every channel) a color may be from the chroma key and still be made
+    /// transparent. Up to twice as far becomes partly transparent, for anti-aliased edges.
+    /// Defaults to 0
+    #[arg(long, value_name = "TOLERANCE")]
+    chroma_tolerance: Option<u8>,
+
+    /// Never enlarge the image when resizing, either keeping dimensions that would grow or failing.
+    /// Defaults to keep when given without a value
+    #[arg(long, value_name = "MODE", num_args = 0..=1, default_missing_value = "keep")]
+    shrink_only: Option<ShrinkOnly>,
+
+    /// Largest width of the print in pixels after resizing, rounded down to a multiple of 128
+    #[arg(long, value_name = "PIXELS")]
+    max_width: Option<u32>,
+
+    /// Largest height of the print in pixels after resizing, rounded down to a multiple of 128
+    #[arg(long, value_name = "PIXELS")]
+    max_height: Option<u32>,
+
+    /// What to do when the image is larger than max-width or max-height. Defaults to downscale
+    #[arg(long, value_name = "MODE")]
+    max_size_mode: Option<MaxSizeMode>,
+
+    /// Resize the image to exactly this many posters (like 3x2), keeping its aspect ratio
+    #[arg(long, value_name = "WxH", value_parser = parse_grid)]
+    fit: Option<(u32, u32)>,
+
+    /// How fit handles a different aspect ratio. Defaults to contain
+    #[arg(long, value_name = "FIT_MODE")]
+    fit_mode: Option<FitMode>,
+
+    /// Crop equally from all sides down to the nearest lower multiple of 128, instead of failing
+    /// when the image (after resizing) isn't a whole number of posters
+    #[arg(long)]
+    center_crop: bool,
+
+    /// Fail instead of changing the image: the input has to be 8 bit and exactly a whole number of
+    /// posters already, and no resize, crop or trim is allowed
+    #[arg(long)]
+    strict: bool,
+
+    /// Split the output into several 2dja files of at most this many posters (like 4x4) each,
+    /// named with a row and column suffix
+    #[arg(long, value_name = "WxH", value_parser = parse_grid)]
+    max_grid: Option<(u32, u32)>,
+
+    /// File name of the outputs of frames, max-grid and batch, like {stem}_f{frame:03}.{ext},
+    /// instead of the page, row and column suffixes
+    #[arg(long, value_name = "TEMPLATE", value_parser = naming::OutputTemplate::parse)]
+    output_template: Option<naming::OutputTemplate>,
+
+    /// Add the new posters after the pages of an existing 2dj/2dja file, and write them all to the
+    /// output. The output may be the existing file itself
+    #[arg(long, value_name = "EXISTING_2DJA")]
+    append: Option<PathBuf>,
+
+    /// Grid of the posters after appending, needed when they don't fill whole rows of the existing
+    /// grid
+    #[arg(long, value_name = "WxH", value_parser = parse_grid)]
+    grid: Option<(u32, u32)>,
+
+    #[arg(short, long, value_name = "LABEL")]
+    label: Option<String>,
+
+    #[arg(short = 'L', long = "forcelabel", value_name = "FORCED_LABEL")]
+    force_label: Option<String>,
+
+    #[arg(short = 'T', long = "forcetooltip", value_name = "TOOLTIP")]
+    force_tooltip: Option<String>,
+
+    /// Store an empty label on every poster, instead of the default attribution
+    #[arg(long)]
+    no_label: bool,
+
+    /// Store an empty tooltip on every poster, instead of the default print info
+    #[arg(long)]
+    no_tooltip: bool,
+
+    /// Maximum label length, for servers with a raised limit. Defaults to 23
+    #[arg(long, value_name = "LENGTH")]
+    label_limit: Option<usize>,
+
+    /// Maximum forced label length, for servers with a raised limit. Defaults to 48
+    #[arg(long, value_name = "LENGTH")]
+    forced_label_limit: Option<usize>,
+
+    /// Maximum tooltip length, for servers with a raised limit. Defaults to 256
+    #[arg(long, value_name = "LENGTH")]
+    tooltip_limit: Option<usize>,
+
+    /// Corner the poster positions in labels and tooltips count from, the pixels stay the same.
+    /// Defaults to top-left
+    #[arg(long, value_name = "ORIGIN")]
+    origin: Option<Origin>,
+
+    /// Info stored in the default tooltip. Defaults to the img2poster repository URL
+    #[arg(long, value_name = "TOOLTIP_INFO")]
+    tooltip_info: Option<String>,
+
+    /// Store the file name of the input image in the default tooltip, as its source field
+    #[arg(long)]
+    tooltip_source: bool,
+
+    /// Label of every poster instead of "<LABEL>: (x,y)/(wxh)". The placeholders {col}, {row}
+    /// (1-based poster position), {cols}, {rows} (size in posters), {index} (1-based, row by row),
+    /// {col_letter}, {row_letter} (position as A, B, ...) and {label} are replaced for every poster
+    #[arg(long, value_name = "TEMPLATE")]
+    label_template: Option<String>,
+
+    /// File with the tooltip text to use instead of the default tooltip. The placeholders {x}, {y}
+    /// (0-based poster position), {w}, {h} (size in posters), {label} and {print_id} are replaced
+    /// for every poster
+    #[arg(long, value_name = "TEMPLATE_FILE")]
+    tooltip_template: Option<PathBuf>,
+
+    /// JSON file with labels and tooltips for specific posters, by their 1-based position in the
+    /// labels, replacing the generated ones for just those posters
+    #[arg(long, value_name = "MANIFEST_FILE")]
+    manifest: Option<PathBuf>,
+
+    /// Print ID stored in the default tooltip. Defaults to a random 6 digit number
+    #[arg(long, value_name = "PRINT_ID")]
+    print_id: Option<String>,
+
+    /// Seed for the random print ID, making repeated runs produce identical files.
+    /// Defaults to a random seed
+    #[arg(long, value_name = "SEED")]
+    seed: Option<u64>,
+
+    /// Shorthand for --quantization per-poster
+    #[arg(short = 'Q', long)]
+    per_poster_quantization: bool,
+
+    /// How palettes are computed and stored across posters. Defaults to shared
+    #[arg(long, value_name = "QUANTIZATION")]
+    quantization: Option<QuantizationMode>,
+
+    /// Threads to quantize posters and draw previews on, or images to convert at once in batch
+    /// mode. A number or auto, defaults to the available parallelism
+    #[arg(short = 'j', long, value_name = "JOBS", value_parser = parse_jobs)]
+    jobs: Option<u32>,
+
+    /// Metric used to match pixels to palette colors. Defaults to weighted-rgb
+    #[arg(long, value_name = "COLOR_DISTANCE")]
+    color_distance: Option<ColorDistance>,
+
+    /// Derive a palette of N colors (1-63) from the image with k-means clustering in CIELAB,
+    /// instead of median cut. Seeded from seed
+    #[arg(long, value_name = "N")]
+    generate_palette: Option<usize>,
+
+    /// Quantize to exactly the distinct colors of a reference image, such as an in-game color
+    /// chart. Only the first 63 colors are used
+    #[arg(long, value_name = "PALETTE_IMAGE")]
+    palette_from: Option<PathBuf>,
+
+    /// Directory of palette images (like palette-from) to choose from with pick-best
+    #[arg(long, value_name = "DIR")]
+    palettes: Option<PathBuf>,
+
+    /// Quantize against every palette in palettes and keep the one with the lowest mean squared
+    /// error
+    #[arg(long)]
+    pick_best: bool,
+
+    /// Pixels with an alpha below this value are stored as transparent (no ink)
+    #[arg(long, value_name = "ALPHA_THRESHOLD")]
+    alpha_threshold: Option<u8>,
+
+    /// Snap pixels within this distance (0-255 on every channel) of a palette color exactly onto
+    /// it before dithering, for images that almost only use palette colors. Defaults to 0 (off)
+    #[arg(long, value_name = "N")]
+    snap_tolerance: Option<u8>,
+
+    /// Keep pixels that already are exactly a palette color and only quantize the rest, so art
+    /// that is already right stays bit-exact. Pairs with snap-tolerance
+    #[arg(long)]
+    only_exact: bool,
+
+    /// JSON file of palette colors and their cost multiplier, like {"#FFFFFF": 0.8, "red": 1.5}.
+    /// Dithering adds (weight - 1) times the palette weight threshold to the distance of a color,
+    /// so cheaper colors win when they are almost as near. Colors without a weight have weight 1
+    #[arg(long, value_name = "FILE")]
+    palette_weights: Option<PathBuf>,
+
+    /// Levels of gray (0-255) a weight of 2 adds to the distance of a palette color, and a weight
+    /// of 0 takes off. Defaults to 16
+    #[arg(long, value_name = "LEVELS")]
+    palette_weight_threshold: Option<u8>,
+
+    /// Remember the nearest palette color of colors that repeat while dithering, which speeds up
+    /// flat images like pixel art. The output stays the same
+    #[arg(long)]
+    palette_distance_cache: bool,
+
+    /// Color to blend transparent pixels onto before quantization, as hex (#RRGGBB or
+    /// #RRGGBBAA), rgb(R, G, B[, A]) or a color name
+    #[arg(long, value_name = "COLOR", value_parser = parse_color)]
+    background: Option<Rgba<u8>>,
+
+    /// Sharpen the image with an unsharp mask of this amount after resizing, before the color
+    /// adjustments, to bring back edges softened by downscaling. Around 0.5 to 1 is a light touch.
+    /// Defaults to 0 (off)
+    #[arg(long, value_name = "AMOUNT")]
+    sharpen: Option<f64>,
+
+    /// Replace a color before quantization, as FROM:TO colors with an optional per-channel
+    /// tolerance (#FF0000:#0000FF:16). Can be given several times, the first match wins. Color
+    /// adjustments run in the order replace-color, grayscale, hue-rotate, saturation, brightness,
+    /// tonemap, contrast, invert, posterize
+    #[arg(long, value_name = "FROM:TO", value_parser = parse_color_replacement)]
+    replace_color: Vec<preprocess::ColorReplacement>,
+
+    /// Convert the image to grayscale before quantization
+    #[arg(long)]
+    grayscale: bool,
+
+    /// Degrees to rotate the hue of the image by before quantization
+    #[arg(long, value_name = "DEGREES", allow_negative_numbers = true)]
+    hue_rotate: Option<f64>,
+
+    /// Factor to multiply the saturation of the image by before quantization. Defaults to 1.0
+    #[arg(long, value_name = "FACTOR")]
+    saturation: Option<f64>,
+
+    /// Factor to multiply the brightness (linear light) of the image by before quantization.
+    /// Defaults to 1.0
+    #[arg(long, value_name = "FACTOR")]
+    brightness: Option<f64>,
+
+    /// Roll off highlights in linear light before quantization instead of clipping them, like the
+    /// overbright values a brightness above 1 gives. Runs right after brightness
+    #[arg(long, value_name = "CURVE")]
+    tonemap: Option<preprocess::Tonemap>,
+
+    /// Factor to scale the contrast of the image by before quantization. Defaults to 1.0
+    #[arg(long, value_name = "FACTOR")]
+    contrast: Option<f64>,
+
+    /// Invert the colors of the image before quantization, after the other color adjustments
+    #[arg(long)]
+    invert: bool,
+
+    /// Round every color channel to one of this many levels (2-255) before quantization, flattening
+    /// gradients into bands. Runs after the other color adjustments
+    #[arg(long, value_name = "LEVELS")]
+    posterize: Option<u8>,
+
+    /// Most colors a poster may use with per poster quantization (1-63). Posters that use more are
+    /// quantized again down to this many, with a warning
+    #[arg(long, value_name = "N")]
+    max_colors_per_poster: Option<usize>,
+
+    /// Maximum number of posters an image may be split into, 0 disables the limit. Defaults to 256
+    #[arg(long, value_name = "MAX_POSTERS")]
+    max_posters: Option<u32>,
+
+    /// Maximum memory in MB a conversion may need, estimated before decoding, resizing and
+    /// quantizing. 0 disables the limit, which is the default
+    #[arg(long, value_name = "MB")]
+    max_memory: Option<u64>,
+
+    /// Abort a conversion that takes longer than this many seconds, discarding its work
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    timeout: Option<Duration>,
+
+    /// Maximum number of pixels an input image may have. Lifts the decoder's default memory limit
+    #[arg(long, value_name = "PIXELS")]
+    max_decode_pixels: Option<u64>,
+
+    /// Directory to cache decoded and resized input images in, so repeated runs on the same image
+    /// only quantize it again
+    #[arg(long, value_name = "CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Save the image exactly as it goes into quantization, after resizing and color adjustments,
+    /// to find out whether a problem comes from preprocessing or from the palette
+    #[arg(long, value_name = "FILE")]
+    dump_preprocessed: Option<PathBuf>,
+
+    /// Save every color the posters use as a grid of swatches with their hex values below them
+    #[arg(long, value_name = "FILE")]
+    palette_swatch: Option<PathBuf>,
+
+    /// Save a guide to placing the posters: the whole print with every poster numbered like the
+    /// label template's {index} and labeled. An image, or text with a .txt extension
+    #[arg(long, value_name = "FILE")]
+    assembly_guide: Option<PathBuf>,
+
+    /// Decode input images without any size or memory limits
+    #[arg(long)]
+    no_limits: bool,
+
+    /// Validate 2dj/2dja input against a JSON Schema before reading it, reporting every mismatch
+    /// with its JSON pointer. Without a file the bundled schema of the poster format is used
+    #[arg(long, value_name = "SCHEMA_FILE", num_args = 0..=1)]
+    schema: Option<Option<PathBuf>>,
+
+    /// Convert every page of a multi-page TIFF or frame of an animated GIF to its own output,
+    /// named with a page suffix like poster_p2.2dja
+    #[arg(long)]
+    frames: bool,
+
+    /// Animated preview (.png for APNG or .gif) of every converted frame, shown as long as the
+    /// frame of the input GIF. Only with frames
+    #[arg(long, value_name = "ANIMATED_PREVIEW_FILE")]
+    preview_animated: Option<PathBuf>,
+
+    /// Page or frame of a multi-page input to convert, set for every page with frames
+    #[arg(skip)]
+    page: usize,
+
+    /// Number of the page in output names, set for every page with frames
+    #[arg(skip)]
+    frame: Option<u32>,
+
+    /// Set once the conversion timed out, so it stops at the next poster and writes nothing
+    #[arg(skip)]
+    cancelled: Option<Arc<Cancellation>>,
+
+    /// Rotate and flip JPEG and PNG input as its EXIF orientation says. On by default
+    #[arg(long, overrides_with = "no_auto_orient")]
+    auto_orient: bool,
+
+    /// Use the input image as it is stored, ignoring its EXIF orientation
+    #[arg(long, overrides_with = "auto_orient")]
+    no_auto_orient: bool,
+
+    /// Warn about posters where more than 95% of the pixels are a single palette color
+    #[arg(long)]
+    warn_flat: bool,
+
+    /// Fail when more than 95% of the pixels of a poster are a single palette color
+    #[arg(long)]
+    fail_flat: bool,
+
+    /// Order every palette by luminance, then hue, so the same colors are always stored in the same
+    /// order
+    #[arg(long)]
+    sort_palette: bool,
+
+    /// Report posters with the same image, and store every image once in 2djb output, the other
+    /// posters referencing it
+    #[arg(long)]
+    dedup: bool,
+
+    /// Print palette usage and quantization error after converting an image
+    #[arg(long)]
+    stats: bool,
+
+    /// Overwrite existing output, preview and sheet files instead of refusing to
+    #[arg(long)]
+    force: bool,
+
+    /// How often to retry writing an output file another program has locked, waiting twice as long
+    /// every time. Defaults to 5
+    #[arg(long, value_name = "RETRIES")]
+    write_retries: Option<u32>,
+
+    /// Print how many pixels of every color the posters need ink for, in total and per poster
+    #[arg(long)]
+    ink_report: bool,
+
+    /// Read poster output back after writing and check it matches what was serialized
+    #[arg(long)]
+    verify: bool,
+
+    /// Print a single line of JSON summing up the conversion instead of progress lines
+    #[arg(long)]
+    json: bool,
+
+    /// Only print errors
+    #[arg(short = 'q', long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Log debug information to stderr
+    #[arg(short = 'v', long)]
+    verbose: bool,
+}
+
+fn check_decode_pixels(width: u32, height: u32, max_pixels: Option<u64>) -> Result<(), Error> {
+    match max_pixels {
+        Some(max_pixels) if width as u64 * height as u64 > max_pixels => Err(Error::validation(format!(
+            "Image is {0}x{1}, which is more than the {2} pixels allowed by max-decode-pixels",
+            width, height, max_pixels
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Decodes a JPEG XL image, which the image crate has no decoder for.
+#[cfg(feature = "jxl")]
+fn read_jxl_image(
+    image_file: &Path,
+    limits: Option<Limits>,
+    check_dimensions: &dyn Fn(u32, u32) -> Result<(), Error>,
+) -> Result<DynamicImage, Error> {
+    let mut builder = jxl_oxide::JxlImage::builder();
+    if let Some(max_alloc) = limits.and_then(|limits| limits.max_alloc) {
+        builder = builder.alloc_tracker(jxl_oxide::AllocTracker::with_limit(max_alloc as usize));
+    }
+
+    let image = builder
+        .open(image_file)
+        .map_err(|error| Error::decode(format!("Failed to decode image: {}", error)))?;
+    debug!("Decoding {} as JPEG XL", image_file.display());
+    check_dimensions(image.width(), image.height())?;
+
+    if image.pixel_format().has_black() {
+        return Err(Error::decode("Failed to decode image: CMYK JPEG XL images are not supported"));
+    }
+
+    let render = image
+        .render_frame(0)
+        .map_err(|error| Error::decode(format!("Failed to decode image: {}", error)))?;
+    let mut stream = render.stream();
+    let (width, height, channels) = (stream.width(), stream.height(), stream.channels());
+    let mut buffer = vec![0u8; (width * height * channels) as usize];
+    stream.write_to_buffer(&mut buffer);
+
+    let decoded_image = match channels {
+        1 => image::ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageLuma8),
+        2 => image::ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageLumaA8),
+        3 => image::ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageRgb8),
+        _ => image::ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageRgba8),
+    };
+    decoded_image.ok_or_else(|| Error::decode("Failed to decode image: unexpected JPEG XL buffer size"))
+}
+
+/// Decodes an image, after passing its dimensions to check_dimensions, which can refuse it before
+/// anything big is allocated.
+fn read_image(
+    image_file: &Path,
+    limits: Option<Limits>,
+    check_dimensions: &dyn Fn(u32, u32) -> Result<(), Error>,
+    auto_orient: bool,
+    page: usize,
+) -> Result<DynamicImage, Error> {
+    if page > 0 && is_gif(image_file) {
+        debug!("Decoding frame {} of {} as GIF", page + 1, image_file.display());
+        return gif_frames::read_frame(image_file, page, limits.is_none(), check_dimensions);
+    }
+    if page > 0 {
+        debug!("Decoding page {} of {} as TIFF", page + 1, image_file.display());
+        return tiff_pages::read_page(image_file, page, limits.is_none(), check_dimensions);
+    }
+
+    #[cfg(feature = "jxl")]
+    if image_file
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("jxl"))
+    {
+        return read_jxl_image(image_file, limits, check_dimensions);
+    }
+
+    // the contents decide the format, so images with a wrong or missing extension still decode
+    let mut decoder = ImageReader::open(image_file)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|error| Error::io(format!("Failed to open image: {}", error)))?;
+    debug!("Decoding {} as {:?}", image_file.display(), decoder.format());
+
+    let (width, height) = ImageReader::open(image_file)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|error| Error::io(format!("Failed to open image: {}", error)))?
+        .into_dimensions()
+        .map_err(|error| Error::decode(format!("Failed to decode image: {}", error)))?;
+    check_dimensions(width, height)?;
+
+    match limits {
+        Some(limits) => decoder.limits(limits),
+        None => decoder.no_limits(),
+    }
+
+    let mut decoded_image = decoder.decode().map_err(|error| match error {
+        ImageError::Limits(_) => Error::validation(format!(
+            "Image exceeds the decode limits ({}), use max-decode-pixels or no-limits to allow it",
+            error
+        )),
+        _ => Error::decode(format!("Failed to decode image: {}", error)),
+    })?;
+    debug!(
+        "Decoded {}x{} image with color type {:?}",
+        decoded_image.width(),
+        decoded_image.height(),
+        decoded_image.color()
+    );
+
+    if auto_orient {
+        if let Some(orientation) = orientation::read_orientation(image_file).filter(|orientation| *orientation != 1) {
+            debug!("Applying EXIF orientation {}", orientation);
+            decoded_image = orientation::apply_orientation(decoded_image, orientation);
+        }
+    }
+
+    Ok(decoded_image)
+}
+
+fn log_written_size(file: &Path) {
+    if let Ok(metadata) = fs::metadata(file) {
+        debug!("Wrote {} bytes to {}", metadata.len(), file.display());
+    }
+}
+
+/// Checks that a written poster file holds exactly the serialized posters and can be read back.
+fn verify_written(file: &Path, format: PosterFormat, expected: &[u8]) -> Result<(), Error> {
+    let written = fs::read(file)
+        .map_err(|error| Error::io(format!("Verification failed, couldn't reopen {0}: {1}", file.display(), error)))?;
+    if written != expected {
+        return Err(Error::io(format!(
+            "Verification failed, {0} doesn't match the serialized posters.",
+            file.display()
+        )));
+    }
+
+    match format.parse(written.as_slice()) {
+        Ok(_) => {
+            status!("Verified {0}", file.display());
+            Ok(())
+        }
+        Err(error) => Err(Error::io(format!(
+            "Verification failed, couldn't parse {0}: {1}",
+            file.display(),
+            error
+        ))),
+    }
+}
+
+/// Writes posters in a poster format to the output file and/or the clipboard, verifying the
+/// written file when asked to. Only the JSON formats can go to the clipboard.
+fn write_posters(
+    output: Option<&Path>,
+    format: PosterFormat,
+    poster_array: &PosterArray,
+    options: &ConvertOptions,
+    clipboard: bool,
+) -> Result<(), Error> {
+    let mut bytes = Vec::new();
+    format.write(poster_array, options.dedup, &mut bytes)?;
+
+    if let Some(output) = output {
+        write_output(output, options.write_retries.unwrap_or(WRITE_RETRIES), || fs::write(output, &bytes))?;
+        log_written_size(output);
+
+        if options.verify {
+            verify_written(output, format, &bytes)?;
+        }
+    }
+
+    if clipboard {
+        copy_to_clipboard(String::from_utf8_lossy(&bytes).into_owned())?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "arboard")]
+fn copy_to_clipboard(text: String) -> Result<(), Error> {
+    let length = text.len();
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(text))
+        .map_err(|error| Error::io(format!("Failed to copy to the clipboard: {}", error)))?;
+    note!("Copied {} bytes of poster JSON to the clipboard", length);
+    Ok(())
+}
+
+#[cfg(not(feature = "arboard"))]
+fn copy_to_clipboard(_text: String) -> Result<(), Error> {
+    Err(Error::format("Clipboard output requires img2poster to be built with the arboard feature."))
+}
+
+/// Whether an input is an http(s) URL instead of a file.
+fn is_url(input: &Path) -> bool {
+    input.to_str().is_some_and(|input| {
+        let input = input.to_lowercase();
+        input.starts_with("http://") || input.starts_with("https://")
+    })
+}
+
+#[cfg(feature = "url")]
+fn download(url: &Path) -> Result<Vec<u8>, Error> {
+    let url = url.to_str().unwrap();
+    status!("Downloading {}", url);
+    let bytes = fetch::fetch(url)?;
+    debug!("Downloaded {} bytes", bytes.len());
+    Ok(bytes)
+}
+
+#[cfg(not(feature = "url"))]
+fn download(_url: &Path) -> Result<Vec<u8>, Error> {
+    Err(Error::format("Reading input from a URL requires img2poster to be built with the url feature."))
+}
+
+/// Checks 2dj/2dja input against a schema file, or the bundled schema of its format without one.
+fn check_schema(input: &[u8], format: PosterFormat, schema_file: Option<&Path>) -> Result<(), Error> {
+    let document: serde_json::Value = serde_json::from_slice(input)
+        .map_err(|error| Error::decode(format!("Failed to parse json in input file: {}", error)))?;
+
+    let (schema, path) = match schema_file {
+        Some(schema_file) => {
+            let schema = fs::read(schema_file)
+                .map_err(|error| Error::io(format!("Failed to open schema file: {}", error)))?;
+            let schema = serde_json::from_slice(&schema)
+                .map_err(|error| Error::decode(format!("Failed to parse json in schema file: {}", error)))?;
+            (schema, "")
+        }
+        None => (
+            serde_json::from_str(schema::DEFAULT_SCHEMA).unwrap(),
+            if format == PosterFormat::Poster { "/$defs/poster" } else { "" },
+        ),
+    };
+
+    let violations = schema::validate(&schema, path, &document)?;
+    if !violations.is_empty() {
+        return Err(Error::decode(format!(
+            "Input file doesn't match the schema ({0} {1}):\n{2}",
+            violations.len(),
+            if violations.len() == 1 { "problem" } else { "problems" },
+            schema::format_violations(&violations)
+        )));
+    }
+    debug!("Input file matches the schema");
+    Ok(())
+}
+
+/// Flattens posters into an image, with every poster pixel upscaled to a pixel_scale block.
+fn flatten_posters(poster_array: &PosterArray, pixel_scale: u32, threads: u32) -> DynamicImage {
+    let output_image = posters_to_dynamic_image(poster_array, threads);
+    if pixel_scale == 1 {
+        return output_image;
+    }
+
+    let (width, height) = output_image.dimensions();
+    debug!("Upscaling poster pixels by {}", pixel_scale);
+    output_image.resize_exact(width * pixel_scale, height * pixel_scale, FilterType::Nearest)
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+fn is_text(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("txt"))
+}
+
+fn is_gif(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"))
+}
+
+/// Streams posters to a PNG file row by row, without flattening them into an image in memory.
+fn stream_png(poster_array: &PosterArray, file: &Path, pixel_scale: u32) -> io::Result<()> {
+    debug!("Streaming {} as PNG", file.display());
+    let writer = File::create(file).map(BufWriter::new)?;
+    write_posters_png(poster_array, pixel_scale, writer).map_err(|error| match error {
+        png::EncodingError::IoError(error) => error,
+        error => io::Error::other(error),
+    })
+}
+
+/// Applies a tone curve `value ^ gamma` to the color channels of an image.
+fn apply_gamma(image: DynamicImage, gamma: f64) -> DynamicImage {
+    let mut lookup = [0u8; 256];
+    for (value, entry) in lookup.iter_mut().enumerate() {
+        *entry = ((value as f64 / 255.0).powf(gamma) * 255.0).round() as u8;
+    }
+
+    let mut image = image.into_rgba8();
+    for pixel in image.pixels_mut() {
+        for channel in 0..3 {
+            pixel[channel] = lookup[pixel[channel] as usize];
+        }
+    }
+    DynamicImage::ImageRgba8(image)
+}
+
+/// Flattens posters into a preview image, with the preview pixel scale, colors and scale. `colors`
+/// is the gamma of the tone curve and the color vision deficiency to simulate.
+fn preview_image(
+    poster_array: &PosterArray,
+    pixel_scale: u32,
+    scale: (f64, f64),
+    filter: ResizeFilter,
+    threads: u32,
+    colors: (Option<f64>, Option<cvd::Deficiency>),
+) -> DynamicImage {
+    let (gamma, deficiency) = colors;
+    let mut output_image = flatten_posters(poster_array, pixel_scale, threads);
+
+    if let Some(gamma) = gamma {
+        debug!("Applying preview tone curve with gamma {}", gamma);
+        output_image = apply_gamma(output_image, gamma);
+    }
+
+    if let Some(deficiency) = deficiency {
+        debug!("Simulating {:?} in the preview", deficiency);
+        output_image = cvd::simulate_image(output_image, deficiency);
+    }
+
+    if scale != (1.0, 1.0) {
+        let (width, height) = output_image.dimensions();
+        let (scaled_width, scaled_height) = (
+            u32::max(1, (width as f64 * scale.0).round() as u32),
+            u32::max(1, (height as f64 * scale.1).round() as u32),
+        );
+        debug!("Scaling preview to {}x{}", scaled_width, scaled_height);
+        output_image = filter.resize(&output_image, scaled_width, scaled_height);
+    }
+    output_image
+}
+
+fn save_preview(
+    poster_array: &PosterArray,
+    preview: &Path,
+    pixel_scale: u32,
+    scale: (f64, f64),
+    filter: ResizeFilter,
+    threads: u32,
+    colors: (Option<f64>, Option<cvd::Deficiency>),
+) -> Result<(), Error> {
+    status!("Generating preview...");
+    if scale == (1.0, 1.0) && colors == (None, None) && is_png(preview) {
+        stream_png(poster_array, preview, pixel_scale)
+            .map_err(|error| Error::io(format!("Failed to save preview image: {}", error)))?;
+        log_written_size(preview);
+        return Ok(());
+    }
+
+    preview_image(poster_array, pixel_scale, scale, filter, threads, colors)
+        .save(preview)
+        .map_err(|error| Error::io(format!("Failed to save preview image: {}", error)))?;
+    log_written_size(preview);
+    Ok(())
+}
+
+/// Lists the groups of posters with the same image, and how many of them 2djb output stores as
+/// references. The JSON formats have no references, so they still store every poster.
+fn report_duplicates(poster_array: &PosterArray, format: PosterFormat) {
+    let groups = duplicate_pages(poster_array);
+    if groups.is_empty() {
+        status!("No posters have the same image");
+        return;
+    }
+
+    let duplicates: usize = groups.iter().map(|group| group.len() - 1).sum();
+    status!(
+        "{0} of {1} posters repeat the image of an earlier one, posters with the same image:",
+        duplicates,
+        poster_array.pages.len()
+    );
+    for group in &groups {
+        let positions: Vec<String> = group
+            .iter()
+            .map(|page| format!("({},{})", *page as u32 % poster_array.width + 1, *page as u32 / poster_array.width + 1))
+            .collect();
+        status!("  {}", positions.join(", "));
+    }
+    if format == PosterFormat::Binary {
+        status!("Storing {} posters as references to an earlier one", duplicates);
+    } else {
+        status!("{} can't reference posters, every poster is stored in full (2djb can)", format.extension());
+    }
+}
+
+/// Refuses to overwrite an existing file, unless force is given.
+fn check_overwrite(path: &Path, name: &str, force: bool) -> Result<(), Error> {
+    if !force && path.exists() {
+        return Err(Error::io(format!(
+            "{0} file {1} already exists, use --force to overwrite it.",
+            name,
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Whether a file can't be opened because another program has it open, which passes unlike
+/// missing permissions. Mostly Windows, where viewers keep the files they show locked.
+fn is_locked(error: &io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+    (cfg!(windows) && matches!(error.raw_os_error(), Some(32 | 33))) || error.kind() == io::ErrorKind::ResourceBusy
+}
+
+/// Writes an output file, retrying with a backoff while another program has it locked. Any other
+/// error, like missing permissions, fails right away. `write` does the whole write, from creating
+/// the file on, so every retry starts over.
+fn write_output<T>(path: &Path, retries: u32, mut write: impl FnMut() -> io::Result<T>) -> Result<T, Error> {
+    let mut delay = WRITE_RETRY_DELAY;
+    let mut waited = Duration::ZERO;
+    let mut attempt = 0;
+    loop {
+        match write() {
+            Ok(written) => return Ok(written),
+            Err(error) if is_locked(&error) && attempt < retries && waited + delay <= WRITE_RETRY_LIMIT => {
+                attempt += 1;
+                warn!(
+                    "{0} is locked by another program, retrying in {1} ms ({2}/{3})",
+                    path.display(),
+                    delay.as_millis(),
+                    attempt,
+                    retries
+                );
+                thread::sleep(delay);
+                waited += delay;
+                delay *= 2;
+            }
+            Err(error) if is_locked(&error) => {
+                return Err(Error::io(format!(
+                    "Failed to write to output file {0}, another program still has it locked after {1} retries ({2}). Close it and try again.",
+                    path.display(),
+                    attempt,
+                    error
+                )));
+            }
+            Err(error) => {
+                return Err(Error::io(format!(
+                    "Failed to write to output file {0}: {1}",
+                    path.display(),
+                    error
+                )));
+            }
+        }
+    }
+}
+
+/// The io error behind an image error, so a locked file can be told apart from other failures.
+fn image_io_error(error: ImageError) -> io::Error {
+    match error {
+        ImageError::IoError(error) => error,
+        error => io::Error::other(error),
+    }
+}
+
+/// Checks that an extra image output (preview or sheet) can be written and has a supported format.
+fn check_image_output(path: &Path, name: &str, force: bool) -> Result<(), Error> {
+    check_overwrite(path, name, force)?;
+
+    match path.parent() {
+        Some(parent) => {
+            if !parent.exists() {
+                return Err(Error::io(format!("{} file parent directory doesn't exist.", name)));
+            } else if !parent.is_dir() {
+                return Err(Error::io(format!("{} file parent is not a directory.", name)));
+            }
+        }
+        None => {
+            return Err(Error::io(format!("{} file parent directory doesn't exist.", name)));
+        }
+    }
+
+    let extension = match path.extension() {
+        Some(t) => t,
+        None => {
+            return Err(Error::format(format!("{} file has no extension.", name)));
+        }
+    }
+    .to_str()
+    .unwrap()
+    .to_lowercase();
+    let extension = extension.as_str();
+
+    match extension {
+        "png" | "jpg" | "jpeg" | "bmp" | "tif" | "tiff" => Ok(()),
+        "webp" if cfg!(feature = "webp") => Ok(()),
+        "webp" => Err(Error::format(format!(
+            "WebP {}s require img2poster to be built with the webp feature.",
+            name.to_lowercase()
+        ))),
+        _ => Err(Error::format(format!("Unsupported {} format: {}", name.to_lowercase(), extension))),
+    }
+}
+
+fn save_sheet(poster_array: &PosterArray, sheet: &Path, columns: u32, threads: u32) -> Result<(), Error> {
+    status!("Generating sheet...");
+    sheet::render_sheet(poster_array, columns, threads)
+        .save(sheet)
+        .map_err(|error| Error::io(format!("Failed to save sheet image: {}", error)))?;
+    log_written_size(sheet);
+    Ok(())
+}
+
+/// Saves the colors of all posters, in the order they first appear, as a palette swatch image.
+fn save_palette_swatch(poster_array: &PosterArray, swatch: &Path) -> Result<(), Error> {
+    let mut palette: Vec<u32> = Vec::new();
+    for color in poster_array.pages.iter().flat_map(|page| &page.palette) {
+        if !palette.contains(color) {
+            palette.push(*color);
+        }
+    }
+
+    status!("Generating palette swatch of {} colors...", palette.len());
+    sheet::render_swatch(&palette)
+        .save(swatch)
+        .map_err(|error| Error::io(format!("Failed to save palette swatch image: {}", error)))?;
+    log_written_size(swatch);
+    Ok(())
+}
+
+/// Saves the assembly guide of a print, as text or as an image. Posters are numbered row by row
+/// with rows following origin, like `{index}` of the label template.
+fn save_assembly_guide(poster_array: &PosterArray, guide: &Path, title: &str, origin: Origin, threads: u32) -> Result<(), Error> {
+    let numbers: Vec<u32> = (0..poster_array.pages.len() as u32)
+        .map(|index| {
+            let (x, y) = (index % poster_array.width, index / poster_array.width);
+            origin.row(y, poster_array.height) * poster_array.width + x + 1
+        })
+        .collect();
+
+    status!("Generating assembly guide...");
+    if is_text(guide) {
+        fs::write(guide, sheet::assembly_guide_text(poster_array, title, &numbers))
+            .map_err(|error| Error::io(format!("Failed to write assembly guide: {}", error)))?;
+    } else {
+        sheet::render_assembly_guide(poster_array, title, &numbers, threads)
+            .save(guide)
+            .map_err(|error| Error::io(format!("Failed to save assembly guide image: {}", error)))?;
+    }
+    log_written_size(guide);
+    Ok(())
+}
+
+/// Prints the exact text that will be stored to stderr when it is close to its length limit, so
+/// it isn't a surprise when it gets cut off.
+fn note_length(what: &str, text: &str, limit: usize, margin: usize) {
+    if text.len() + margin > limit && text.len() <= limit {
+        note!(
+            "{0} is {1} characters, {2} left before the limit of {3}: {4}",
+            what,
+            text.len(),
+            limit - text.len(),
+            limit,
+            text
+        );
+    }
+}
+
+/// Label and tooltip length limits of a conversion.
+struct LengthLimits {
+    label: usize,
+    forced_label: usize,
+    tooltip: usize,
+}
+
+impl LengthLimits {
+    fn new(options: &ConvertOptions) -> Self {
+        LengthLimits {
+            label: options.label_limit.unwrap_or(LABEL_LIMIT),
+            forced_label: options.forced_label_limit.unwrap_or(FORCED_LABEL_LIMIT),
+            tooltip: options.tooltip_limit.unwrap_or(TOOLTIP_LIMIT),
+        }
+    }
+}
+
+/// Replaces the {name} placeholders of a template with their values. Unknown placeholders are
+/// kept as they are.
+fn render_template(template: &str, values: &[(&str, String)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest.find('}').and_then(|end| {
+            values
+                .iter()
+                .find(|(name, _)| *name == &rest[1..end])
+                .map(|(_, value)| (value, end))
+        });
+
+        match value {
+            Some((value, end)) => {
+                rendered.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+
+    rendered
+}
+
+/// Replaces the placeholders of a tooltip template for the poster at (x, y).
+fn render_tooltip_template(template: &str, x: u32, y: u32, w: u32, h: u32, label: &str, print_id: &str) -> String {
+    render_template(
+        template,
+        &[
+            ("x", x.to_string()),
+            ("y", y.to_string()),
+            ("w", w.to_string()),
+            ("h", h.to_string()),
+            ("label", label.to_string()),
+            ("print_id", print_id.to_string()),
+        ],
+    )
+}
+
+/// Letters for a 1-based number like spreadsheet columns: A-Z, then AA, AB and so on.
+fn number_letters(mut number: u32) -> String {
+    let mut letters = Vec::new();
+    while number > 0 {
+        number -= 1;
+        letters.push((b'A' + (number % 26) as u8) as char);
+        number /= 26;
+    }
+    letters.iter().rev().collect()
+}
+
+/// Checks the labels and tooltips of a manifest against the length limits, warning about posters
+/// outside the grid of `width` by `height` posters.
+fn check_manifest(manifest: &manifest::Manifest, width: u32, height: u32, limits: &LengthLimits) -> Result<(), Error> {
+    for poster in manifest.overrides() {
+        if poster.x > width || poster.y > height {
+            warn!(
+                "Manifest poster ({0},{1}) is outside the grid of {2}x{3} posters",
+                poster.x, poster.y, width, height
+            );
+        }
+        if let Some(ref label) = poster.label {
+            let what = format!("Manifest label of poster ({},{})", poster.x, poster.y);
+            note_length(&what, label, limits.forced_label, LABEL_LENGTH_MARGIN);
+            if label.len() > limits.forced_label {
+                return Err(Error::validation(format!(
+                    "{0} can't be longer than {1} characters, currently {2}",
+                    what,
+                    limits.forced_label,
+                    label.len()
+                )));
+            }
+        }
+        if let Some(ref tooltip) = poster.tooltip {
+            let what = format!("Manifest tooltip of poster ({},{})", poster.x, poster.y);
+            note_length(&what, tooltip, limits.tooltip, TOOLTIP_LENGTH_MARGIN);
+            if tooltip.len() > limits.tooltip {
+                return Err(Error::validation(format!(
+                    "{0} can't be longer than {1} characters, currently {2}",
+                    what,
+                    limits.tooltip,
+                    tooltip.len()
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the placeholders of a label template for the poster at (x, y), counting from 1.
+fn render_label_template(template: &str, x: u32, y: u32, w: u32, h: u32, label: &str) -> String {
+    render_template(
+        template,
+        &[
+            ("col", (x + 1).to_string()),
+            ("row", (y + 1).to_string()),
+            ("cols", w.to_string()),
+            ("rows", h.to_string()),
+            ("index", (y * w + x + 1).to_string()),
+            ("col_letter", number_letters(x + 1)),
+            ("row_letter", number_letters(y + 1)),
+            ("label", label.to_string()),
+        ],
+    )
+}
+
+/// Prepares a decoded input image for quantization: reduces the bit depth, flattens the alpha
+/// onto the background and resizes it.
+fn resize_input(
+    image: DynamicImage,
+    options: &ConvertOptions,
+    resize_filter: ResizeFilter,
+    budget: Option<&memory::Budget>,
+) -> Result<DynamicImage, Error> {
+    if options.strict {
+        check_strict(&image)?;
+    }
+    let mut image = preprocess::reduce_bit_depth(image);
+
+    if let Some(key) = options.chroma_key {
+        image = preprocess::chroma_key(image, key, options.chroma_tolerance.unwrap_or(0));
+    }
+
+    if options.trim {
+        let (width, height) = image.dimensions();
+        let trimmed;
+        (image, trimmed) = preprocess::trim_borders(image, options.trim_tolerance.unwrap_or(0));
+        match trimmed {
+            Some(trimmed) => status!(
+                "Trimmed borders from x:{0} y:{1} to x:{2} y:{3} (top {4}, bottom {5}, left {6}, right {7} pixels)",
+                width,
+                height,
+                image.width(),
+                image.height(),
+                trimmed.top,
+                trimmed.bottom,
+                trimmed.left,
+                trimmed.right
+            ),
+            None => status!("Not trimming, the whole image is a single color"),
+        }
+    }
+
+    if let Some(background) = options.background {
+        image = preprocess::flatten_alpha(image, background);
+    }
+
+    if let Some((width, height)) = options.fit {
+        return fit_image(image, width, height, options, resize_filter, budget);
+    }
+
+    let (x_size, y_size) = image.dimensions();
+    let requested = requested_size(options, x_size, y_size);
+    let mut resize = requested.is_some();
+    let (mut resize_x, mut resize_y) = requested.unwrap_or((x_size, y_size));
+
+    if let Some(shrink_only) = options.shrink_only {
+        if resize && (resize_x > x_size || resize_y > y_size) {
+            if shrink_only == ShrinkOnly::Error {
+                return Err(Error::validation(format!(
+                    "Resizing to x:{0} y:{1} would enlarge the image (from x:{2} y:{3}), not allowed with shrink-only",
+                    resize_x, resize_y, x_size, y_size
+                )));
+            }
+
+            status!(
+                "Not enlarging the image (shrink-only), keeping x:{0} y:{1}",
+                u32::min(resize_x, x_size),
+                u32::min(resize_y, y_size)
+            );
+            resize_x = u32::min(resize_x, x_size);
+            resize_y = u32::min(resize_y, y_size);
+            resize = resize_x != x_size || resize_y != y_size;
+        }
+    }
+
+    if let Some((clamped_x, clamped_y)) = clamp_size(options, resize_x, resize_y)? {
+        status!(
+            "Clamping x:{0} y:{1} to x:{2} y:{3} (max-width/max-height)",
+            resize_x, resize_y, clamped_x, clamped_y
+        );
+        resize_x = clamped_x;
+        resize_y = clamped_y;
+        resize = resize_x != x_size || resize_y != y_size;
+    }
+
+    if resize && (resize_x < 1 || resize_y < 1) {
+        return Err(Error::validation(format!("Can't resize to x:{0} y:{1}", resize_x, resize_y)));
+    }
+
+    // checked before resizing, so a huge scale fails right away instead of after allocating
+    if resize {
+        check_poster_count(resize_x, resize_y, options)?;
+    }
+    if let Some(budget) = budget {
+        budget.check((x_size, y_size), (resize_x, resize_y))?;
+    }
+
+    if resize && !options.center_crop && ((resize_x % 128 != 0) || (resize_y % 128 != 0)) {
+        return Err(Error::validation(format!("Image resolutions have to be multiples of 128 (Attempted to resize to x:{0} y:{1})", resize_x, resize_y)));
+    }
+
+    if resize {
+        status!(
+            "Resizing image to x:{0} y:{1} (from x:{2} y:{3})",
+            resize_x, resize_y, x_size, y_size
+        );
+
+        image = scale_image(&image, resize_x, resize_y, options, resize_filter);
+    }
+
+    if options.center_crop {
+        image = center_crop(image)?;
+    }
+
+    Ok(image)
+}
+
+/// Size max-width and max-height clamp an image of x_size by y_size to, scaling both sides by the
+/// same factor and rounding them down to multiples of 128. `None` when it already fits.
+fn clamp_size(options: &ConvertOptions, x_size: u32, y_size: u32) -> Result<Option<(u32, u32)>, Error> {
+    let max_x = options.max_width.map_or(u32::MAX, |width| width / 128 * 128);
+    let max_y = options.max_height.map_or(u32::MAX, |height| height / 128 * 128);
+    if x_size <= max_x && y_size <= max_y {
+        return Ok(None);
+    }
+
+    if options.max_size_mode == Some(MaxSizeMode::Error) {
+        let mut limits = Vec::new();
+        if x_size > max_x {
+            limits.push(format!("max-width {}", max_x));
+        }
+        if y_size > max_y {
+            limits.push(format!("max-height {}", max_y));
+        }
+        return Err(Error::validation(format!(
+            "Image of x:{0} y:{1} is larger than {2}",
+            x_size,
+            y_size,
+            limits.join(" and ")
+        )));
+    }
+
+    let factor = f64::min(max_x as f64 / x_size as f64, max_y as f64 / y_size as f64);
+    let clamp = |size: u32, max: u32| ((size as f64 * factor) as u32 / 128 * 128).clamp(128, max);
+    Ok(Some((clamp(x_size, max_x), clamp(y_size, max_y))))
+}
+
+/// Size scale-x, scale-y and autoscale ask for an image of x_size by y_size, `None` when they
+/// leave it as it is.
+fn requested_size(options: &ConvertOptions, x_size: u32, y_size: u32) -> Option<(u32, u32)> {
+    let mut resize = false;
+    let (mut resize_x, mut resize_y) = (x_size, y_size);
+
+    if let Some(res) = options.scale_x {
+        resize = true;
+        resize_x = res.resolve(x_size);
+    }
+
+    if let Some(res) = options.scale_y {
+        resize = true;
+        resize_y = res.resolve(y_size);
+    }
+
+    if let Some(autoscale) = options.autoscale {
+        let (x, y) = autoscale_image(x_size, y_size, autoscale, options.round.unwrap_or_default());
+        if x != x_size || y != y_size {
+            resize_x = x;
+            resize_y = y;
+            resize = true;
+        }
+    }
+
+    resize.then_some((resize_x, resize_y))
+}
+
+/// Decodes and resizes an input image. A TIFF that gets shrunk to well under half its size is
+/// read one tile or strip at a time and reduced on the way, so a gigapixel scan never has to fit
+/// in memory whole; everything else is decoded whole and then resized.
+fn read_resized_image(
+    input: &Path,
+    limits: Option<Limits>,
+    check_dimensions: &dyn Fn(u32, u32) -> Result<(), Error>,
+    options: &ConvertOptions,
+    resize_filter: ResizeFilter,
+    budget: Option<&memory::Budget>,
+) -> Result<DynamicImage, Error> {
+    let is_tiff = input
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| matches!(extension.to_lowercase().as_str(), "tif" | "tiff"));
+    // options that look at the full resolution image need the full decode
+    let streamable = is_tiff
+        && options.fit.is_none()
+        && !options.trim
+        && !options.strict
+        && options.downsample_mode.is_none()
+        && options.chroma_key.is_none();
+
+    if streamable {
+        let (width, height) = tiff_pages::page_dimensions(input, options.page)?;
+        if let Some((target_x, target_y)) = requested_size(options, width, height).filter(|(x, y)| *x > 0 && *y > 0) {
+            // reduce to about twice the target, leaving the last step to the resize filter
+            let factor = u32::min(width / target_x, height / target_y) / 2;
+            if factor >= 2 {
+                debug!("Reading {} in chunks, reduced {}x", input.display(), factor);
+                if let Some(image) =
+                    tiff_pages::read_reduced(input, options.page, factor, limits.is_none(), check_dimensions)?
+                {
+                    status!(
+                        "Read TIFF in chunks, reduced from x:{0} y:{1} to x:{2} y:{3}",
+                        width,
+                        height,
+                        image.width(),
+                        image.height()
+                    );
+                    let mut reduced_options = options.clone();
+                    reduced_options.scale_x = Some(Scale::Pixels(target_x));
+                    reduced_options.scale_y = Some(Scale::Pixels(target_y));
+                    reduced_options.autoscale = None;
+                    return resize_input(image, &reduced_options, resize_filter, budget);
+                }
+                debug!("{} can't be read in chunks, decoding it whole", input.display());
+            }
+        }
+    }
+
+    let image = read_image(input, limits, check_dimensions, !options.no_auto_orient, options.page)?;
+    resize_input(image, options, resize_filter, budget)
+}
+
+/// Refuses images that strict would have to change: anything but 8 bits per channel, or a size
+/// that isn't a whole number of posters.
+fn check_strict(image: &DynamicImage) -> Result<(), Error> {
+    let color = image.color();
+    let bits_per_channel = color.bits_per_pixel() / color.channel_count() as u16;
+    if bits_per_channel != 8 {
+        return Err(Error::validation(format!(
+            "Image has {} bits per channel, strict only allows 8 bit images",
+            bits_per_channel
+        )));
+    }
+
+    let (width, height) = image.dimensions();
+    if width % 128 != 0 || height % 128 != 0 {
+        return Err(Error::validation(format!(
+            "Image resolutions have to be multiples of 128 in strict mode, without resizing (Currently x:{0} y:{1})",
+            width, height
+        )));
+    }
+    Ok(())
+}
+
+/// Crops an image equally from all sides down to the nearest lower multiple of 128, leaving images
+/// that already are multiples untouched.
+fn center_crop(image: DynamicImage) -> Result<DynamicImage, Error> {
+    let (width, height) = image.dimensions();
+    let (cropped_width, cropped_height) = (width / 128 * 128, height / 128 * 128);
+    if (cropped_width, cropped_height) == (width, height) {
+        return Ok(image);
+    }
+    if cropped_width == 0 || cropped_height == 0 {
+        return Err(Error::validation(format!(
+            "Can't center crop x:{0} y:{1}, the image is smaller than a poster (128x128)",
+            width, height
+        )));
+    }
+
+    let (left, top) = ((width - cropped_width) / 2, (height - cropped_height) / 2);
+    status!(
+        "Center cropping x:{0} y:{1} to x:{2} y:{3} (the rectangle from ({4},{5}) to ({6},{7}))",
+        width,
+        height,
+        cropped_width,
+        cropped_height,
+        left,
+        top,
+        left + cropped_width,
+        top + cropped_height
+    );
+    Ok(image.crop_imm(left, top, cropped_width, cropped_height))
+}
+
+/// Reads the distinct opaque colors of a palette-from reference image, at most 63.
+fn read_palette_image(reference: &Path, limits: Option<Limits>, options: &ConvertOptions) -> Result<Vec<Color>, Error> {
+    let mut colors = palette_from_image(&read_image(
+        reference,
+        limits,
+        &|width, height| check_decode_pixels(width, height, options.max_decode_pixels),
+        !options.no_auto_orient,
+        0,
+    )?);
+    if colors.is_empty() {
+        return Err(Error::validation("Palette image doesn't contain any opaque colors."));
+    }
+    if colors.len() > 63 {
+        warn!(
+            "Palette image contains {} colors, only the first 63 are used",
+            colors.len()
+        );
+        colors.truncate(63);
+    }
+    debug!("Using {} colors from {}", colors.len(), reference.display());
+    Ok(colors)
+}
+
+/// Reads the palette-weights file, a JSON object of colors (anything color arguments accept) and
+/// their weights, which have to be 0 or more. `None` without one.
+fn read_palette_weights(options: &ConvertOptions) -> Result<Option<Arc<PaletteWeights>>, Error> {
+    let Some(ref file) = options.palette_weights else {
+        return Ok(None);
+    };
+    let text = fs::read_to_string(file)
+        .map_err(|error| Error::io(format!("Failed to read palette weights {0}: {1}", file.display(), error)))?;
+    let entries: BTreeMap<String, f64> = serde_json::from_str(&text)
+        .map_err(|error| Error::decode(format!("Failed to parse palette weights {0}: {1}", file.display(), error)))?;
+
+    let mut weights = HashMap::new();
+    for (color, weight) in entries {
+        let rgba = parse_color(&color)
+            .map_err(|error| Error::validation(format!("Palette weights color {0} is invalid: {1}", color, error)))?;
+        if !(weight.is_finite() && weight >= 0.0) {
+            return Err(Error::validation(format!(
+                "Palette weight of {0} has to be 0 or more, found {1}",
+                color, weight
+            )));
+        }
+        let key = ((rgba[0] as u32) << 16) | ((rgba[1] as u32) << 8) | rgba[2] as u32;
+        if weights.insert(key, weight).is_some() {
+            return Err(Error::validation(format!("Palette weights list #{:06X} more than once", key)));
+        }
+    }
+    debug!("Read {0} palette weights from {1}", weights.len(), file.display());
+    Ok(Some(Arc::new(PaletteWeights::new(
+        weights,
+        options.palette_weight_threshold.unwrap_or(PALETTE_WEIGHT_THRESHOLD),
+    ))))
+}
+
+/// Reads every palette image in a pick-best palettes directory, sorted by file name.
+fn read_palette_dir(dir: &Path, limits: Option<Limits>, options: &ConvertOptions) -> Result<Vec<(PathBuf, Vec<Color>)>, Error> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|error| Error::io(format!("Failed to read palettes directory: {}", error)))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            let extension = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .map(|extension| extension.to_lowercase());
+            path.is_file() && extension.and_then(|extension| file_format(&extension)) == Some(Format::Image)
+        })
+        .collect();
+    entries.sort();
+    if entries.is_empty() {
+        return Err(Error::validation(format!("Palettes directory {} has no images", dir.display())));
+    }
+
+    entries
+        .into_iter()
+        .map(|file| Ok((file.clone(), read_palette_image(&file, limits.clone(), options)?)))
+        .collect()
+}
+
+/// Quantize options of a conversion with the given palette generation. Per poster quantization
+/// gets every job.
+fn quantize_options<'a>(options: &'a ConvertOptions, palette_generation: PaletteGeneration, weights: Option<&'a PaletteWeights>) -> QuantizeOptions<'a> {
+    QuantizeOptions {
+        mode: if options.per_poster_quantization {
+            QuantizationMode::PerPoster
+        } else {
+            options.quantization.unwrap_or_default()
+        },
+        threads: Some(job_count(options)),
+        max_colors_per_poster: options.max_colors_per_poster,
+        cancelled: options.cancelled.as_deref().map(|cancellation| &cancellation.cancelled),
+        color_distance: options.color_distance.unwrap_or_default(),
+        palette_generation,
+        search: PaletteSearch {
+            snap_tolerance: options.snap_tolerance.unwrap_or(0),
+            only_exact: options.only_exact,
+            distance_cache: options.palette_distance_cache,
+            weights,
+        },
+        alpha_threshold: alpha_threshold(options),
+    }
+}
+
+/// Quantizes the image against every palette in parallel and returns the one with the lowest mean
+/// squared error, the first on a tie.
+fn pick_palette(
+    image: &DynamicImage,
+    palettes: Vec<(PathBuf, Vec<Color>)>,
+    options: &ConvertOptions,
+) -> Result<(PosterArray, Vec<stats::FlatPoster>), Error> {
+    status!("Comparing {} palettes...", palettes.len());
+    let threads = job_count(options) as usize;
+    // palettes run side by side, leftover threads go to quantizing each one
+    let jobs = (threads / palettes.len()).max(1) as u32;
+    let next_palette = AtomicUsize::new(0);
+    let scores: Mutex<Vec<(usize, f64)>> = Mutex::new(Vec::new());
+    // only the posters of the best palette so far are kept, ties go to the first palette
+    let best = Mutex::new(None);
+    let weights = read_palette_weights(options)?;
+
+    let results: Result<(), Error> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(palettes.len()))
+            .map(|_| {
+                scope.spawn(|| -> Result<(), Error> {
+                    loop {
+                        let index = next_palette.fetch_add(1, Ordering::Relaxed);
+                        let Some((_, colors)) = palettes.get(index) else {
+                            return Ok(());
+                        };
+                        let (poster_array, flat_posters) = image_to_poster::image_to_posters(
+                            image.clone(),
+                            |_, _, _, _| String::new(),
+                            |_, _, _, _| String::new(),
+                            &QuantizeOptions {
+                                threads: Some(jobs),
+                                ..quantize_options(options, PaletteGeneration::Fixed(colors.clone().into()), weights.as_deref())
+                            },
+                        )?;
+                        let error = stats::poster_array_stats(&poster_array, image).mean_squared_error;
+                        scores.lock().unwrap().push((index, error));
+                        let mut best = best.lock().unwrap();
+                        if best.as_ref().is_none_or(|(best_index, best_error, _, _)| (error, index) < (*best_error, *best_index)) {
+                            *best = Some((index, error, poster_array, flat_posters));
+                        }
+                    }
+                })
+            })
+            .collect();
+        workers.into_iter().try_for_each(|worker| worker.join().unwrap())
+    });
+    results?;
+
+    let mut scores = scores.into_inner().unwrap();
+    scores.sort_by_key(|(index, _)| *index);
+    for (index, error) in &scores {
+        debug!("Palette {} has a mean squared error of {:.2}", palettes[*index].0.display(), error);
+    }
+    let (best, error, poster_array, flat_posters) = best.into_inner().unwrap().expect("there is at least one palette");
+    status!(
+        "Picked palette {} (mean squared error {:.2})",
+        palettes[best].0.file_name().unwrap_or_default().to_string_lossy(),
+        error
+    );
+    Ok((poster_array, flat_posters))
+}
+
+/// Refuses images that would be split into more posters than max-posters allows.
+fn check_poster_count(width: u32, height: u32, options: &ConvertOptions) -> Result<(), Error> {
+    let max_posters = options.max_posters.unwrap_or(DEFAULT_MAX_POSTERS);
+    let poster_count = (width / 128) as u64 * (height / 128) as u64;
+    if max_posters != 0 && poster_count > max_posters as u64 {
+        return Err(Error::validation(format!(
+            "Image would be split into {0} posters, more than the limit of {1} (Use --autoscale or --scale-x/--scale-y to shrink it, or raise --max-posters)",
+            poster_count, max_posters
+        )));
+    }
+    Ok(())
+}
+
+/// Resizes an image with the downsample mode, or the resize algorithm without one.
+fn scale_image(image: &DynamicImage, width: u32, height: u32, options: &ConvertOptions, resize_filter: ResizeFilter) -> DynamicImage {
+    match options.downsample_mode {
+        Some(mode) => {
+            debug!("Downsampling with mode {:?}", mode);
+            preprocess::downsample(image, width, height, mode)
+        }
+        None => resize_filter.resize(image, width, height),
+    }
+}
+
+/// Alpha threshold to quantize with. With a chroma key and no background it defaults to 128, so
+/// keyed pixels get no ink instead of being quantized to a color.
+fn alpha_threshold(options: &ConvertOptions) -> Option<u8> {
+    let keyed = options.chroma_key.is_some() && options.background.is_none();
+    options.alpha_threshold.or(keyed.then_some(128))
+}
+
+fn color_adjustments(options: &ConvertOptions) -> preprocess::ColorAdjustments {
+    preprocess::ColorAdjustments {
+        replace_colors: options.replace_color.clone(),
+        grayscale: options.grayscale,
+        hue_rotate: options.hue_rotate.unwrap_or(0.0),
+        saturation: options.saturation.unwrap_or(1.0),
+        brightness: options.brightness.unwrap_or(1.0),
+        tonemap: options.tonemap,
+        contrast: options.contrast.unwrap_or(1.0),
+        invert: options.invert,
+        posterize: options.posterize,
+    }
+}
+
+/// Resizes an image to exactly `width` by `height` posters, keeping its aspect ratio. The scaled
+/// image is centered, what doesn't fit is cropped and the rest padded with transparent pixels (or
+/// the background color).
+fn fit_image(
+    image: DynamicImage,
+    width: u32,
+    height: u32,
+    options: &ConvertOptions,
+    resize_filter: ResizeFilter,
+    budget: Option<&memory::Budget>,
+) -> Result<DynamicImage, Error> {
+    let (target_x, target_y) = (width * 128, height * 128);
+    let (x_size, y_size) = image.dimensions();
+    let (scale_x, scale_y) = (target_x as f64 / x_size as f64, target_y as f64 / y_size as f64);
+
+    let mut scale = match options.fit_mode.unwrap_or(FitMode::Contain) {
+        FitMode::Contain => f64::min(scale_x, scale_y),
+        FitMode::Cover => f64::max(scale_x, scale_y),
+    };
+
+    if scale > 1.0 {
+        match options.shrink_only {
+            Some(ShrinkOnly::Error) => {
+                return Err(Error::validation(format!(
+                    "Fitting to x:{0} y:{1} would enlarge the image by {2:.3} (from x:{3} y:{4}), not allowed with shrink-only",
+                    target_x, target_y, scale, x_size, y_size
+                )));
+            }
+            Some(ShrinkOnly::Keep) => {
+                status!("Not enlarging the image (shrink-only), keeping its size");
+                scale = 1.0;
+            }
+            None => {}
+        }
+    }
+
+    let (scaled_x, scaled_y) = (
+        u32::max(1, (x_size as f64 * scale).round() as u32),
+        u32::max(1, (y_size as f64 * scale).round() as u32),
+    );
+    if let Some(budget) = budget {
+        // cover scales past the target before cropping, so the bigger of the two is allocated
+        budget.check((x_size, y_size), (u32::max(scaled_x, target_x), u32::max(scaled_y, target_y)))?;
+    }
+    let scaled = if (scaled_x, scaled_y) == (x_size, y_size) {
+        image.into_rgba8()
+    } else {
+        scale_image(&image, scaled_x, scaled_y, options, resize_filter).into_rgba8()
+    };
+
+    let fill = options.background.unwrap_or(Rgba([0, 0, 0, 0]));
+    let mut canvas = RgbaImage::from_pixel(target_x, target_y, fill);
+    imageops::overlay(
+        &mut canvas,
+        &scaled,
+        (target_x as i64 - scaled_x as i64) / 2,
+        (target_y as i64 - scaled_y as i64) / 2,
+    );
+
+    let cropped = scaled_x > target_x || scaled_y > target_y;
+    let padded = scaled_x < target_x || scaled_y < target_y;
+    status!(
+        "Fitting image to {0}x{1} posters (x:{2} y:{3}), scaled by {4:.3} to x:{5} y:{6}{7}",
+        width,
+        height,
+        target_x,
+        target_y,
+        scale,
+        scaled_x,
+        scaled_y,
+        match (cropped, padded) {
+            (true, true) => ", cropped and padded",
+            (true, false) => ", cropped",
+            (false, true) => ", padded",
+            (false, false) => "",
+        }
+    );
+
+    Ok(DynamicImage::ImageRgba8(canvas))
+}
+
+fn autoscale_image(mut width: u32, mut height: u32, scale: f64, rounding: Rounding) -> (u32, u32) {
+    //TODO: make this attempt to preserve aspect ratio later
+    // capped to the largest multiple of 128 a u32 holds, so rounding up can't overflow, the
+    // poster count check rejects sizes that big anyway
+    width = (width as f64 * scale).min((u32::MAX - 127) as f64) as u32;
+    height = (height as f64 * scale).min((u32::MAX - 127) as f64) as u32;
+    let round = |size: u32| {
+        let remainder = size % 128;
+        let up = match rounding {
+            Rounding::Nearest => remainder >= 64,
+            Rounding::Up => remainder > 0,
+            Rounding::Down => false,
+        };
+        if up {
+            size + (128 - remainder)
+        } else {
+            size - remainder
+        }
+    };
+    let (scaled_x, scaled_y) = (round(width), round(height));
+    (
+        if scaled_x == 0 { 128 } else { scaled_x },
+        if scaled_y == 0 { 128 } else { scaled_y },
+    )
+}
+
+/// Horizontal and vertical scale of the preview: preview-scale, with the side preview-aspect makes
+/// longer stretched. Nothing is shrunk, so no poster pixel gets lost.
+fn preview_scale(options: &ConvertOptions) -> (f64, f64) {
+    let scale = options.preview_scale.unwrap_or(1.0);
+    let aspect = options.preview_aspect.unwrap_or(1.0);
+    (scale * aspect.max(1.0), scale * (1.0 / aspect).max(1.0))
+}
+
+/// Gamma of the preview tone curve, none for an sRGB preview.
+fn preview_gamma(options: &ConvertOptions) -> Option<f64> {
+    match options.preview_colorspace.unwrap_or_default() {
+        PreviewColorspace::Srgb => None,
+        PreviewColorspace::Game => Some(options.preview_gamma.unwrap_or(GAME_PREVIEW_GAMMA)),
+    }
+}
+
+fn file_format(extension: &str) -> Option<Format> {
+    match extension {
+        "png" => Some(Format::Image),
+        "jpg" => Some(Format::Image),
+        "jpeg" => Some(Format::Image),
+        "bmp" => Some(Format::Image),
+        "jxl" => Some(Format::Image),
+        "avif" => Some(Format::Image),
+        "tif" => Some(Format::Image),
+        "tiff" => Some(Format::Image),
+        "gif" => Some(Format::Image),
+        // can likely support more image formats, but cant be bothered
+        extension => PosterFormat::from_extension(extension).map(Format::Poster),
+    }
+}
+
+fn convert(
+    input: &Path,
+    output: Option<&Path>,
+    preview: Option<&Path>,
+    sheet: Option<&Path>,
+    clipboard: bool,
+    options: &ConvertOptions,
+) -> Result<PosterArray, Error> {
+    let start = Instant::now();
+    let remote = is_url(input);
+    if remote && !cfg!(feature = "url") {
+        return Err(Error::format("Reading input from a URL requires img2poster to be built with the url feature."));
+    }
+    if !remote && !input.exists() {
+        return Err(Error::io("Input file doesn't exist."));
+    }
+    if input.is_dir() {
+        return Err(Error::io("Input can't be a directory."));
+    }
+
+    let base_output = output;
+    let output = output.map(|output| naming::output_name(output, options.output_template.as_ref(), options.frame, None));
+    let output = output.as_deref();
+    if let Some(output) = output {
+        if output.is_dir() {
+            return Err(Error::io("Output can't be a directory."));
+        }
+
+        match output.parent() {
+            Some(parent) => {
+                if !parent.exists() {
+                    return Err(Error::io("Output file parent directory doesn't exist."));
+                } else if !parent.is_dir() {
+                    return Err(Error::io("Output file parent is not a directory."));
+                }
+            }
+            None => {
+                return Err(Error::io("Output file parent directory doesn't exist."));
+            }
+        }
+
+        // appending to a file is meant to write it back
+        if options.append.as_deref() != Some(output) {
+            check_overwrite(output, "Output", options.force)?;
+        }
+    }
+
+    if clipboard && !cfg!(feature = "arboard") {
+        return Err(Error::format("Clipboard output requires img2poster to be built with the arboard feature."));
+    }
+
+    if let Some(preview) = preview {
+        check_image_output(preview, "Preview", options.force)?;
+    }
+    if let Some(sheet) = sheet {
+        check_image_output(sheet, "Sheet", options.force)?;
+    }
+
+    // a given format wins over the extension, images keep their extension when it is one
+    let extension_of = |path: &Path| {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase())
+    };
+    // the query and fragment of a URL aren't part of its extension
+    let input_name = match input.to_str() {
+        Some(url) if remote => Path::new(url.split(['?', '#']).next().unwrap_or(url)),
+        _ => input,
+    };
+    let input_extension = match (options.input_format, extension_of(input_name)) {
+        (Some(FileFormat::Image), Some(extension)) if file_format(&extension) == Some(Format::Image) => extension,
+        (Some(format), _) => format.extension().to_string(),
+        (None, Some(extension)) => extension,
+        (None, None) => {
+            return Err(Error::format("Input file has no extension."));
+        }
+    };
+    // without an output file the posters only go to the clipboard, as 2dja
+    let output_extension = match (options.output_format, output.map(extension_of)) {
+        (Some(FileFormat::Image), Some(Some(extension))) if file_format(&extension) == Some(Format::Image) => extension,
+        (Some(format), _) => format.extension().to_string(),
+        (None, Some(Some(extension))) => extension,
+        (None, Some(None)) => {
+            return Err(Error::format("Output file has no extension."));
+        }
+        (None, None) => PosterFormat::PosterArray.extension().to_string(),
+    };
+    let input_extension = input_extension.as_str();
+    let output_extension = output_extension.as_str();
+
+    let input_format: Format = match file_format(input_extension) {
+        Some(format) => format,
+        None => {
+            return Err(Error::format(format!("Unsupported input format: {}", input_extension)));
+        }
+    };
+    if remote && !matches!(input_format, Format::Poster(_)) {
+        return Err(Error::format("Only 2dj, 2dja, 2djb and 2dji posters can be read from a URL."));
+    }
+    let output_format: Format = match file_format(output_extension) {
+        Some(format) => format,
+        None => {
+            return Err(Error::format(format!("Unsupported output format: {}", output_extension)));
+        }
+    };
+
+    // posters are never preprocessed, so there is nothing to dump for them
+    if let Some(ref dump) = options.dump_preprocessed {
+        if input_format == Format::Image {
+            check_image_output(dump, "Preprocessed image", options.force)?;
+        }
+    }
+
+    if let Some(ref swatch) = options.palette_swatch {
+        check_image_output(swatch, "Palette swatch", options.force)?;
+    }
+
+    if let Some(ref guide) = options.assembly_guide {
+        if is_text(guide) {
+            check_overwrite(guide, "Assembly guide", options.force)?;
+        } else {
+            check_image_output(guide, "Assembly guide", options.force)?;
+        }
+    }
+
+    match input_extension {
+        "jxl" if !cfg!(feature = "jxl") => {
+            return Err(Error::format("JPEG XL decoder feature not enabled, rebuild with --features jxl"));
+        }
+        "avif" if !cfg!(feature = "avif") => {
+            return Err(Error::format("AVIF decoder feature not enabled, rebuild with --features avif"));
+        }
+        _ => {}
+    }
+
+    if output_extension == "jxl" || output_extension == "avif" {
+        return Err(Error::format(format!("Unsupported output format: {}, only supported as input", output_extension)));
+    }
+
+    // TODO: clean up
+    {
+        let mut errors: Vec<&str> = Vec::new();
+        if matches!(input_format, Format::Poster(_)) {
+            if options.per_poster_quantization {
+                errors.push("per-poster-quantization flag only allowed with input format: Image");
+            }
+            if options.max_colors_per_poster.is_some() {
+                errors.push("max-colors-per-poster arg only allowed with input format: Image");
+            }
+            if options.quantization.is_some() {
+                errors.push("quantization arg only allowed with input format: Image");
+            }
+            if options.label.is_some() {
+                errors.push("label arg only allowed with input format: Image");
+            }
+            if options.label_template.is_some() {
+                errors.push("label-template arg only allowed with input format: Image");
+            }
+            if options.force_label.is_some() {
+                errors.push("force-label arg only allowed with input format: Image");
+            }
+            if options.force_tooltip.is_some() {
+                errors.push("force-tooltip arg only allowed with input format: Image");
+            }
+            if options.no_label {
+                errors.push("no-label flag only allowed with input format: Image");
+            }
+            if options.no_tooltip {
+                errors.push("no-tooltip flag only allowed with input format: Image");
+            }
+            if options.label_limit.is_some() {
+                errors.push("label-limit arg only allowed with input format: Image");
+            }
+            if options.forced_label_limit.is_some() {
+                errors.push("forced-label-limit arg only allowed with input format: Image");
+            }
+            if options.tooltip_limit.is_some() {
+                errors.push("tooltip-limit arg only allowed with input format: Image");
+            }
+            if options.origin.is_some() {
+                errors.push("origin arg only allowed with input format: Image");
+            }
+            if options.tooltip_info.is_some() {
+                errors.push("tooltip-info arg only allowed with input format: Image");
+            }
+            if options.tooltip_source {
+                errors.push("tooltip-source flag only allowed with input format: Image");
+            }
+            if options.tooltip_template.is_some() {
+                errors.push("tooltip-template arg only allowed with input format: Image");
+            }
+            if options.manifest.is_some() {
+                errors.push("manifest arg only allowed with input format: Image");
+            }
+            if options.print_id.is_some() {
+                errors.push("print-id arg only allowed with input format: Image");
+            }
+            if options.seed.is_some() {
+                errors.push("seed arg only allowed with input format: Image");
+            }
+            if options.generate_palette.is_some() {
+                errors.push("generate-palette arg only allowed with input format: Image");
+            }
+            if options.palette_from.is_some() {
+                errors.push("palette-from arg only allowed with input format: Image");
+            }
+            if options.palettes.is_some() {
+                errors.push("palettes arg only allowed with input format: Image");
+            }
+            if options.pick_best {
+                errors.push("pick-best flag only allowed with input format: Image");
+            }
+            if options.scale_x.is_some() {
+                errors.push("scale-x arg only allowed with input format: Image");
+            }
+            if options.scale_y.is_some() {
+                errors.push("scale-y arg only allowed with input format: Image");
+            }
+            if options.autoscale.is_some() {
+                errors.push("autoscale arg only allowed with input format: Image");
+            }
+            if options.fit.is_some() {
+                errors.push("fit arg only allowed with input format: Image");
+            }
+            if options.center_crop {
+                errors.push("center-crop flag only allowed with input format: Image");
+            }
+            if options.strict {
+                errors.push("strict flag only allowed with input format: Image");
+            }
+            if options.shrink_only.is_some() {
+                errors.push("shrink-only arg only allowed with input format: Image");
+            }
+            if options.max_width.is_some() {
+                errors.push("max-width arg only allowed with input format: Image");
+            }
+            if options.max_height.is_some() {
+                errors.push("max-height arg only allowed with input format: Image");
+            }
+            if options.trim {
+                errors.push("trim flag only allowed with input format: Image");
+            }
+            if options.downsample_mode.is_some() {
+                errors.push("downsample-mode arg only allowed with input format: Image");
+            }
+            if options.stats {
+                errors.push("stats flag only allowed with input format: Image");
+            }
+            if options.max_posters.is_some() {
+                errors.push("max-posters arg only allowed with input format: Image");
+            }
+            if options.max_decode_pixels.is_some() {
+                errors.push("max-decode-pixels arg only allowed with input format: Image");
+            }
+            if options.no_limits {
+                errors.push("no-limits flag only allowed with input format: Image");
+            }
+            if options.cache_dir.is_some() {
+                errors.push("cache-dir arg only allowed with input format: Image");
+            }
+            if options.alpha_threshold.is_some() {
+                errors.push("alpha-threshold arg only allowed with input format: Image");
+            }
+            if options.snap_tolerance.is_some() {
+                errors.push("snap-tolerance arg only allowed with input format: Image");
+            }
+            if options.only_exact {
+                errors.push("only-exact flag only allowed with input format: Image");
+            }
+            if options.palette_weights.is_some() {
+                errors.push("palette-weights arg only allowed with input format: Image");
+            }
+            if options.palette_distance_cache {
+                errors.push("palette-distance-cache flag only allowed with input format: Image");
+            }
+            if options.background.is_some() {
+                errors.push("background arg only allowed with input format: Image");
+            }
+            if options.chroma_key.is_some() {
+                errors.push("chroma-key arg only allowed with input format: Image");
+            }
+            if options.chroma_tolerance.is_some() {
+                errors.push("chroma-tolerance arg only allowed with input format: Image");
+            }
+            if options.saturation.is_some() {
+                errors.push("saturation arg only allowed with input format: Image");
+            }
+            if options.brightness.is_some() {
+                errors.push("brightness arg only allowed with input format: Image");
+            }
+            if options.tonemap.is_some() {
+                errors.push("tonemap arg only allowed with input format: Image");
+            }
+            if options.contrast.is_some() {
+                errors.push("contrast arg only allowed with input format: Image");
+            }
+            if options.grayscale {
+                errors.push("grayscale flag only allowed with input format: Image");
+            }
+            if options.hue_rotate.is_some() {
+                errors.push("hue-rotate arg only allowed with input format: Image");
+            }
+            if !options.replace_color.is_empty() {
+                errors.push("replace-color arg only allowed with input format: Image");
+            }
+            if options.invert {
+                errors.push("invert flag only allowed with input format: Image");
+            }
+            if options.posterize.is_some() {
+                errors.push("posterize arg only allowed with input format: Image");
+            }
+            if options.sharpen.is_some() {
+                errors.push("sharpen arg only allowed with input format: Image");
+            }
+            if options.frames {
+                errors.push("frames flag only allowed with input format: Image");
+            }
+            if options.no_auto_orient {
+                errors.push("no-auto-orient flag only allowed with input format: Image");
+            }
+            if options.warn_flat {
+                errors.push("warn-flat flag only allowed with input format: Image");
+            }
+            if options.fail_flat {
+                errors.push("fail-flat flag only allowed with input format: Image");
+            }
+        }
+
+        if options.strict {
+            if options.scale_x.is_some() {
+                errors.push("scale-x arg not allowed with strict");
+            }
+            if options.scale_y.is_some() {
+                errors.push("scale-y arg not allowed with strict");
+            }
+            if options.autoscale.is_some() {
+                errors.push("autoscale arg not allowed with strict");
+            }
+            if options.fit.is_some() {
+                errors.push("fit arg not allowed with strict");
+            }
+            if options.center_crop {
+                errors.push("center-crop flag not allowed with strict");
+            }
+            if options.trim {
+                errors.push("trim flag not allowed with strict");
+            }
+            if (options.max_width.is_some() || options.max_height.is_some())
+                && options.max_size_mode != Some(MaxSizeMode::Error)
+            {
+                errors.push("max-width and max-height only allowed with strict when max-size-mode is error");
+            }
+        }
+
+        if options.fit.is_some() {
+            if options.scale_x.is_some() {
+                errors.push("scale-x arg not allowed with fit");
+            }
+            if options.scale_y.is_some() {
+                errors.push("scale-y arg not allowed with fit");
+            }
+            if options.autoscale.is_some() {
+                errors.push("autoscale arg not allowed with fit");
+            }
+            if options.center_crop {
+                errors.push("center-crop flag not allowed with fit, which pads and crops on its own");
+            }
+            if options.max_width.is_some() {
+                errors.push("max-width arg not allowed with fit");
+            }
+            if options.max_height.is_some() {
+                errors.push("max-height arg not allowed with fit");
+            }
+        }
+
+        if options.palette_weight_threshold.is_some() && options.palette_weights.is_none() {
+            errors.push("palette-weight-threshold arg only allowed with palette-weights");
+        }
+
+        if options.trim_tolerance.is_some() && !options.trim {
+            errors.push("trim-tolerance arg only allowed with trim");
+        }
+
+        if options.chroma_tolerance.is_some() && options.chroma_key.is_none() {
+            errors.push("chroma-tolerance arg only allowed with chroma-key");
+        }
+
+        if options.max_width.is_some_and(|width| width < 128) {
+            errors.push("max-width has to be at least 128");
+        }
+        if options.max_height.is_some_and(|height| height < 128) {
+            errors.push("max-height has to be at least 128");
+        }
+        if options.max_size_mode.is_some() && options.max_width.is_none() && options.max_height.is_none() {
+            errors.push("max-size-mode arg only allowed with max-width or max-height");
+        }
+
+        if options.fit_mode.is_some() && options.fit.is_none() {
+            errors.push("fit-mode arg only allowed with fit");
+        }
+
+        if options.round.is_some() && options.autoscale.is_none() {
+            errors.push("round arg only allowed with autoscale");
+        }
+
+        if options.autoscale.is_some() {
+            if options.scale_x.is_some() {
+                errors.push("scale-x arg not allowed with autoscale");
+            }
+            if options.scale_y.is_some() {
+                errors.push("scale-y arg not allowed with autoscale");
+            }
+        }
+
+        if options.preview_scale.is_some() && preview.is_none() {
+            errors.push("preview-scale arg only allowed with preview");
+        }
+
+        if let Some(scale) = options.preview_scale {
+            if !scale.is_finite() || scale <= 0.0 {
+                errors.push("preview-scale has to be a positive number");
+            }
+        }
+
+        let previewed = preview.is_some() || options.preview_animated.is_some();
+        if options.preview_aspect.is_some() && !previewed {
+            errors.push("preview-aspect arg only allowed with preview");
+        }
+
+        if options.preview_colorspace.is_some() && !previewed {
+            errors.push("preview-colorspace arg only allowed with preview");
+        }
+
+        if options.preview_cvd.is_some() && !previewed {
+            errors.push("preview-cvd arg only allowed with preview");
+        }
+
+        if options.preview_gamma.is_some() && options.preview_colorspace != Some(PreviewColorspace::Game) {
+            errors.push("preview-gamma arg only allowed with preview-colorspace game");
+        }
+
+        if let Some(gamma) = options.preview_gamma {
+            if !gamma.is_finite() || gamma <= 0.0 {
+                errors.push("preview-gamma has to be a positive number");
+            }
+        }
+
+        if options.preview_pixel_scale.is_some() && preview.is_none() && output_format != Format::Image {
+            errors.push("preview-pixel-scale arg only allowed with preview or output format: Image");
+        }
+
+        if options.hue_rotate.is_some_and(|degrees| !degrees.is_finite()) {
+            errors.push("hue-rotate has to be a number");
+        }
+
+        if let Some(saturation) = options.saturation {
+            if !saturation.is_finite() || saturation < 0.0 {
+                errors.push("saturation has to be a non-negative number");
+            }
+        }
+
+        if let Some(brightness) = options.brightness {
+            if !brightness.is_finite() || brightness < 0.0 {
+                errors.push("brightness has to be a non-negative number");
+            }
+        }
+
+        if let Some(contrast) = options.contrast {
+            if !contrast.is_finite() || contrast < 0.0 {
+                errors.push("contrast has to be a non-negative number");
+            }
+        }
+
+        if options.posterize.is_some_and(|levels| levels < 2) {
+            errors.push("posterize has to be between 2 and 255");
+        }
+
+        if let Some(sharpen) = options.sharpen {
+            if !sharpen.is_finite() || sharpen < 0.0 {
+                errors.push("sharpen has to be a non-negative number");
+            }
+        }
+
+        if options.sheet_columns.is_some() && sheet.is_none() {
+            errors.push("sheet-columns arg only allowed with sheet");
+        }
+
+        if options.sheet_columns == Some(0) {
+            errors.push("sheet-columns has to be at least 1");
+        }
+
+        if options.preview_pixel_scale == Some(0) {
+            errors.push("preview-pixel-scale has to be at least 1");
+        }
+
+        if options.upscale.is_some() && output_format != Format::Image {
+            errors.push("upscale arg only allowed with output format: Image");
+        }
+
+        if options.upscale == Some(0) {
+            errors.push("upscale has to be at least 1");
+        }
+
+        if options.smooth && options.upscale.is_none() {
+            errors.push("smooth flag only allowed with upscale");
+        }
+
+        if options.dedup && !matches!(output_format, Format::Poster(_)) {
+            errors.push("dedup flag only allowed with output format: Poster");
+        }
+
+        if options.sort_palette && !matches!(output_format, Format::Poster(_)) {
+            errors.push("sort-palette flag only allowed with output format: Poster");
+        }
+
+        if clipboard && !matches!(output_format, Format::Poster(_)) {
+            errors.push("clipboard flag only allowed with output format: Poster");
+        }
+
+        if clipboard && output_format == Format::Poster(PosterFormat::Binary) {
+            errors.push("clipboard flag not allowed with output format: 2djb");
+        }
+
+        if options.schema.is_some() && !matches!(input_format, Format::Poster(PosterFormat::Poster | PosterFormat::PosterArray)) {
+            errors.push("schema arg only allowed with input format: 2dj or 2dja");
+        }
+
+        if options.frames && !matches!(input_extension, "tif" | "tiff" | "gif") && input_format == Format::Image {
+            errors.push("frames flag only allowed with input format: TIFF or GIF");
+        }
+
+        if options.preview_animated.is_some() && !options.frames {
+            errors.push("preview-animated arg only allowed with frames");
+        }
+
+        if let Some(ref preview_animated) = options.preview_animated {
+            if !is_png(preview_animated) && !is_gif(preview_animated) {
+                errors.push("preview-animated has to be a png or gif file");
+            }
+        }
+
+        if clipboard && options.frames {
+            errors.push("frames flag not allowed with clipboard");
+        }
+
+        if clipboard && options.max_grid.is_some() {
+            errors.push("max-grid arg not allowed with clipboard");
+        }
+
+        if options.json && options.stats {
+            errors.push("json flag not allowed with stats");
+        }
+
+        if options.verify && output.is_none() {
+            errors.push("verify flag only allowed with output");
+        }
+
+        if let Some(ref template) = options.output_template {
+            if output.is_none() {
+                errors.push("output-template arg only allowed with output");
+            }
+            if template.uses(naming::Placeholder::Frame) && !options.frames {
+                errors.push("output-template {frame} only allowed with frames");
+            }
+            if (template.uses(naming::Placeholder::Row) || template.uses(naming::Placeholder::Col)) && options.max_grid.is_none() {
+                errors.push("output-template {row} and {col} only allowed with max-grid");
+            }
+        }
+
+        if options.max_grid.is_some() && output_format != Format::Poster(PosterFormat::PosterArray) {
+            errors.push("max-grid arg only allowed with output format: 2dja");
+        }
+
+        if options.append.is_some() && output_format != Format::Poster(PosterFormat::PosterArray) {
+            errors.push("append arg only allowed with output format: 2dja");
+        }
+
+        if options.append.is_some() && options.max_grid.is_some() {
+            errors.push("append arg not allowed with max-grid");
+        }
+
+        if options.grid.is_some() && options.append.is_none() {
+            errors.push("grid arg only allowed with append");
+        }
+
+        if options.verify && !matches!(output_format, Format::Poster(_)) {
+            errors.push("verify flag only allowed with output format: Poster");
+        }
+
+        if options.background.is_some() && options.alpha_threshold.is_some() {
+            errors.push("background arg not allowed with alpha-threshold");
+        }
+
+        if options.label_template.is_some() && options.force_label.is_some() {
+            errors.push("label-template arg not allowed with force-label");
+        }
+
+        if options.tooltip_template.is_some() {
+            if options.force_tooltip.is_some() {
+                errors.push("tooltip-template arg not allowed with force-tooltip");
+            }
+            if options.tooltip_info.is_some() {
+                errors.push("tooltip-template arg not allowed with tooltip-info");
+            }
+            if options.tooltip_source {
+                errors.push("tooltip-template arg not allowed with tooltip-source");
+            }
+        }
+
+        if options.tooltip_source && options.force_tooltip.is_some() {
+            errors.push("tooltip-source flag not allowed with force-tooltip");
+        }
+
+        if options.no_label {
+            if options.label.is_some() {
+                errors.push("no-label flag not allowed with label");
+            }
+            if options.force_label.is_some() {
+                errors.push("no-label flag not allowed with force-label");
+            }
+            if options.label_template.is_some() {
+                errors.push("no-label flag not allowed with label-template");
+            }
+        }
+
+        if options.no_tooltip {
+            if options.force_tooltip.is_some() {
+                errors.push("no-tooltip flag not allowed with force-tooltip");
+            }
+            if options.tooltip_template.is_some() {
+                errors.push("no-tooltip flag not allowed with tooltip-template");
+            }
+            if options.tooltip_info.is_some() {
+                errors.push("no-tooltip flag not allowed with tooltip-info");
+            }
+            if options.tooltip_source {
+                errors.push("no-tooltip flag not allowed with tooltip-source");
+            }
+        }
+
+        if let Some(colors) = options.generate_palette {
+            if !(1..=63).contains(&colors) {
+                errors.push("generate-palette has to be between 1 and 63");
+            }
+        }
+
+        if let Some(ref dir) = options.cache_dir {
+            if dir.exists() && !dir.is_dir() {
+                errors.push("cache-dir has to be a directory");
+            }
+        }
+
+        if options.palette_from.is_some() && options.generate_palette.is_some() {
+            errors.push("palette-from arg not allowed with generate-palette");
+        }
+
+        if options.palettes.is_some() != options.pick_best {
+            errors.push("palettes arg and pick-best flag have to be used together");
+        }
+
+        if options.palettes.is_some() && options.palette_from.is_some() {
+            errors.push("palettes arg not allowed with palette-from");
+        }
+
+        if options.palettes.is_some() && options.generate_palette.is_some() {
+            errors.push("palettes arg not allowed with generate-palette");
+        }
+
+        if let Some(ref dir) = options.palettes {
+            if !dir.is_dir() {
+                errors.push("palettes has to be a directory");
+            }
+        }
+
+        if options.no_limits && options.max_decode_pixels.is_some() {
+            errors.push("no-limits flag not allowed with max-decode-pixels");
+        }
+
+        if options.per_poster_quantization && options.quantization.is_some() {
+            errors.push("per-poster-quantization flag not allowed with quantization");
+        }
+
+        if options.max_colors_per_poster.is_some()
+            && !options.per_poster_quantization
+            && options.quantization != Some(QuantizationMode::PerPoster)
+        {
+            errors.push("max-colors-per-poster arg only allowed with per poster quantization");
+        }
+
+        if options.max_colors_per_poster.is_some_and(|colors| !(1..=63).contains(&colors)) {
+            errors.push("max-colors-per-poster has to be between 1 and 63");
+        }
+
+        if !errors.is_empty() {
+            return Err(Error::usage(errors.join("\n")));
+        }
+    }
+
+    let resize_filter = options.resize_algorithm.unwrap_or_default();
+    let preview_scale = preview_scale(options);
+    let preview_pixel_scale = options.preview_pixel_scale.unwrap_or(1);
+    let threads = job_count(options);
+    let budget = options
+        .max_memory
+        .filter(|max_memory| *max_memory != 0)
+        .map(|max_memory| {
+            let previewed = preview.is_some() || options.preview_animated.is_some();
+            memory::Budget::new(max_memory, previewed.then_some((preview_pixel_scale, preview_scale)))
+        });
+    let preview_colors = (preview_gamma(options), options.preview_cvd);
+
+    let mut poster_array: poster::PosterArray;
+    if input_format == Format::Image {
+        let limits = if options.no_limits || options.max_decode_pixels.is_some() {
+            None
+        } else {
+            Some(Limits::default())
+        };
+        let fixed_palette = match options.palette_from {
+            Some(ref reference) => Some(read_palette_image(reference, limits.clone(), options)?),
+            None => None,
+        };
+        let candidate_palettes = match options.palettes {
+            Some(ref dir) => Some(read_palette_dir(dir, limits.clone(), options)?),
+            None => None,
+        };
+
+        let cache = match options.cache_dir {
+            Some(ref dir) => {
+                let params = format!(
+                    "{:?}",
+                    (
+                        options.scale_x,
+                        options.scale_y,
+                        options.autoscale,
+                        options.round,
+                        options.fit,
+                        options.fit_mode,
+                        (options.shrink_only, options.max_width, options.max_height, options.max_size_mode),
+                        (resize_filter, options.downsample_mode),
+                        (options.background, options.chroma_key, options.chroma_tolerance),
+                        (options.trim.then_some(options.trim_tolerance.unwrap_or(0)), options.center_crop, options.strict),
+                        !options.no_auto_orient,
+                        options.page,
+                    )
+                );
+                Some((dir.as_path(), cache::cache_key(input, &params)?))
+            }
+            None => None,
+        };
+
+        let mut unwrapped_image = match cache.as_ref().and_then(|(dir, key)| cache::load(dir, key)) {
+            Some(image) => {
+                status!("Loaded resized image from cache");
+                if let Some(ref budget) = budget {
+                    budget.check(image.dimensions(), image.dimensions())?;
+                }
+                image
+            }
+            None => {
+                let check_dimensions = |width, height| {
+                    check_decode_pixels(width, height, options.max_decode_pixels)?;
+                    match budget {
+                        Some(ref budget) => budget.check_decode(width, height),
+                        None => Ok(()),
+                    }
+                };
+                let image = read_resized_image(input, limits, &check_dimensions, options, resize_filter, budget.as_ref())?;
+                if let Some((dir, ref key)) = cache {
+                    cache::store(dir, key, &image);
+                }
+                image
+            }
+        };
+        let (x_size, y_size) = unwrapped_image.dimensions();
+
+        if (x_size % 128 != 0) || (y_size % 128 != 0) {
+            return Err(Error::validation(format!(
+                "Image resolutions have to be multiples of 128 (Currently x:{0} y:{1})",
+                x_size, y_size
+            )));
+        }
+
+        check_poster_count(x_size, y_size, options)?;
+
+        debug!(
+            "Final image dimensions: {}x{} ({}x{} posters)",
+            x_size,
+            y_size,
+            x_size / 128,
+            y_size / 128
+        );
+
+        unwrapped_image = preprocess::sharpen(unwrapped_image, options.sharpen.unwrap_or(0.0));
+        unwrapped_image = preprocess::adjust_colors(unwrapped_image, &color_adjustments(options));
+
+        if let Some(ref dump) = options.dump_preprocessed {
+            status!("Saving preprocessed image...");
+            unwrapped_image
+                .save(dump)
+                .map_err(|error| Error::io(format!("Failed to save preprocessed image: {}", error)))?;
+            log_written_size(dump);
+        }
+
+        let limits = LengthLimits::new(options);
+        let origin = options.origin.unwrap_or_default();
+        let mut forced_label: bool = false;
+        let label: String;
+
+        if options.no_label {
+            label = String::new();
+            forced_label = true;
+        } else if let Some(ref txt) = options.force_label {
+            label = txt.to_string();
+            forced_label = true;
+            if label.len() > limits.forced_label {
+                return Err(Error::validation(format!(
+                    "Forced label can't be longer than {0} characters, currently {1}",
+                    limits.forced_label,
+                    label.len()
+                )));
+            }
+        } else if let Some(ref txt) = options.label {
+            label = txt.to_string();
+            if label.len() > limits.label {
+                return Err(Error::validation(format!(
+                    "Label can't be longer than {0} characters, currently {1}",
+                    limits.label,
+                    label.len()
+                )));
+            }
+        } else if let Ok(txt) = env::var("IMG2POSTER_DEFAULT_LABEL") {
+            label = txt;
+            if label.len() > limits.label {
+                return Err(Error::validation(format!(
+                    "Label from IMG2POSTER_DEFAULT_LABEL can't be longer than {0} characters, currently {1}",
+                    limits.label,
+                    label.len()
+                )));
+            }
+        } else {
+            label = "PatriikPlays/img2poster".to_string();
+        }
+
+        if forced_label {
+            note_length("Label", &label, limits.forced_label, LABEL_LENGTH_MARGIN);
+        } else if let Some(ref template) = options.label_template {
+            let (w, h) = (x_size / 128, y_size / 128);
+            for y in 0..h {
+                for x in 0..w {
+                    let poster_label = render_label_template(template, x, origin.row(y, h), w, h, &label);
+                    note_length(
+                        &format!("Label of poster ({},{})", x + 1, y + 1),
+                        &poster_label,
+                        limits.forced_label,
+                        LABEL_LENGTH_MARGIN,
+                    );
+                    if poster_label.len() > limits.forced_label {
+                        return Err(Error::validation(format!(
+                            "Label of poster ({0},{1}) can't be longer than {2} characters, currently {3}: {4}",
+                            x + 1,
+                            y + 1,
+                            limits.forced_label,
+                            poster_label.len(),
+                            poster_label
+                        )));
+                    }
+                }
+            }
+        } else if options.label.is_some() || env::var_os("IMG2POSTER_DEFAULT_LABEL").is_some() {
+            // the last poster has the widest coordinates, so its label is the longest
+            let (w, h) = (x_size / 128, y_size / 128);
+            if label.len() + LABEL_LENGTH_MARGIN > limits.label {
+                note!(
+                    "Label is {0} characters, {1} left before the limit of {2}, stored as: {3}: ({4},{5})/({4}x{5})",
+                    label.len(),
+                    limits.label - label.len(),
+                    limits.label,
+                    label,
+                    w,
+                    h
+                );
+            }
+        }
+
+        let mut use_forced_tooltip = options.no_tooltip;
+        let mut forced_tooltip: String = "".to_string();
+        if let Some(ref txt) = options.force_tooltip {
+            forced_tooltip = txt.to_string();
+            use_forced_tooltip = true;
+            if forced_tooltip.len() > limits.tooltip {
+                return Err(Error::validation(format!(
+                    "Forced tooltip can't be longer than {0} characters, currently {1}",
+                    limits.tooltip,
+                    forced_tooltip.len()
+                )));
+            }
+            note_length("Forced tooltip", &forced_tooltip, limits.tooltip, TOOLTIP_LENGTH_MARGIN);
+        }
+
+        let mut rng = match options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        let print_id = match options.print_id {
+            Some(ref id) => id.clone(),
+            None => format!("{:0>6}", rng.gen_range(0..999999)),
+        };
+        let tooltip_info = options
+            .tooltip_info
+            .clone()
+            .unwrap_or("https://github.com/PatriikPlays/img2poster".to_string());
+        let tooltip_source = if options.tooltip_source {
+            input.file_name().map(|name| name.to_string_lossy().into_owned())
+        } else {
+            None
+        };
+
+        let tooltip_template = match options.tooltip_template {
+            Some(ref file) => Some(
+                fs::read_to_string(file)
+                    .map_err(|error| Error::io(format!("Failed to read tooltip template: {}", error)))?
+                    .trim_end_matches(['\r', '\n'])
+                    .to_string(),
+            ),
+            None => None,
+        };
+
+        if let Some(ref template) = tooltip_template {
+            let (w, h) = (x_size / 128, y_size / 128);
+            for y in 0..h {
+                for x in 0..w {
+                    let tooltip = render_tooltip_template(template, x, origin.row(y, h), w, h, &label, &print_id);
+                    let tooltip_length = tooltip.len();
+                    debug!("Tooltip of poster ({},{}) is {} characters", x + 1, y + 1, tooltip_length);
+                    note_length(
+                        &format!("Tooltip of poster ({},{})", x + 1, y + 1),
+                        &tooltip,
+                        limits.tooltip,
+                        TOOLTIP_LENGTH_MARGIN,
+                    );
+                    if tooltip_length > limits.tooltip {
+                        return Err(Error::validation(format!(
+                            "Tooltip of poster ({0},{1}) can't be longer than {2} characters, currently {3}",
+                            x + 1,
+                            y + 1,
+                            limits.tooltip,
+                            tooltip_length
+                        )));
+                    }
+                }
+            }
+        } else if !use_forced_tooltip {
+            // the last poster has the widest coordinates, so its tooltip is the longest
+            let (w, h) = (x_size / 128, y_size / 128);
+            let tooltip = serde_json::to_string(&PosterTooltip {
+                print_id: print_id.clone(),
+                print_name: label.clone(),
+                total_width: w,
+                total_height: h,
+                pos_x: w - 1,
+                pos_y: h - 1,
+                info: tooltip_info.clone(),
+                source: tooltip_source.clone(),
+            })
+            .unwrap();
+            let tooltip_length = tooltip.len();
+            note_length("Tooltip", &tooltip, limits.tooltip, TOOLTIP_LENGTH_MARGIN);
+
+            if tooltip_length > limits.tooltip {
+                return Err(Error::validation(format!(
+                    "Tooltip can't be longer than {0} characters, currently {1} (shorten the tooltip info, print ID or input file name)",
+                    limits.tooltip, tooltip_length
+                )));
+            }
+        }
+
+        let manifest = match options.manifest {
+            Some(ref file) => {
+                let manifest = manifest::Manifest::read(file)?;
+                check_manifest(&manifest, x_size / 128, y_size / 128, &limits)?;
+                Some(Arc::new(manifest))
+            }
+            None => None,
+        };
+        let label_manifest = manifest.clone();
+        let tooltip_manifest = manifest;
+
+        let label_generator_label = label.clone();
+        let label_template = options.label_template.clone();
+        let tooltip_generator_label = label.clone();
+
+        // the posters of the picked palette are reused, they only need their labels
+        let picked = match candidate_palettes {
+            Some(palettes) => Some(pick_palette(&unwrapped_image, palettes, options)?),
+            None => None,
+        };
+        let palette_generation = match (options.generate_palette, fixed_palette) {
+            (Some(colors), _) => PaletteGeneration::KMeans {
+                colors,
+                seed: rng.gen(),
+            },
+            (None, Some(colors)) => PaletteGeneration::Fixed(colors.into()),
+            (None, None) => PaletteGeneration::MedianCut { colors: 63 },
+        };
+
+        let palette_weights = read_palette_weights(options)?;
+
+        let original_image = if options.stats {
+            Some(unwrapped_image.clone())
+        } else {
+            None
+        };
+
+        let label_generator = move |x: u32, y: u32, w: u32, h: u32| {
+            let y = origin.row(y, h);
+            if let Some(label) = label_manifest.as_ref().and_then(|manifest| manifest.label(x + 1, y + 1)) {
+                label.to_string()
+            } else if forced_label {
+                label.clone()
+            } else if let Some(ref template) = label_template {
+                render_label_template(template, x, y, w, h, &label_generator_label)
+            } else {
+                format!(
+                    "{0}: ({1},{2})/({3}x{4})",
+                    label_generator_label.clone(),
+                    x + 1,
+                    y + 1,
+                    w,
+                    h
+                )
+            }
+        };
+        let tooltip_generator = move |x: u32, y: u32, w: u32, h: u32| {
+            let y = origin.row(y, h);
+            let tooltip: PosterTooltip = PosterTooltip {
+                print_id: print_id.clone(),
+                print_name: tooltip_generator_label.clone(),
+                total_width: w,
+                total_height: h,
+                pos_x: x,
+                pos_y: y,
+                info: tooltip_info.clone(),
+                source: tooltip_source.clone(),
+            };
+
+            if let Some(tooltip) = tooltip_manifest.as_ref().and_then(|manifest| manifest.tooltip(x + 1, y + 1)) {
+                tooltip.to_string()
+            } else if use_forced_tooltip {
+                forced_tooltip.clone()
+            } else if let Some(ref template) = tooltip_template {
+                render_tooltip_template(template, x, y, w, h, &tooltip_generator_label, &print_id)
+            } else {
+                serde_json::to_string(&tooltip)
+                    .unwrap()
+                    .as_str()
+                    .to_string()
+            }
+        };
+
+        let flat_posters;
+        (poster_array, flat_posters) = match picked {
+            Some((mut poster_array, flat_posters)) => {
+                image_to_poster::label_posters(&mut poster_array, label_generator, tooltip_generator);
+                (poster_array, flat_posters)
+            }
+            None => image_to_poster::image_to_posters(
+                unwrapped_image,
+                label_generator,
+                tooltip_generator,
+                &quantize_options(options, palette_generation, palette_weights.as_deref()),
+            )?,
+        };
+
+        if let Some(ref original_image) = original_image {
+            stats::print_stats(&stats::poster_array_stats(&poster_array, original_image));
+        }
+
+        if options.warn_flat || options.fail_flat {
+            for poster in &flat_posters {
+                warn!(
+                    "Poster ({0},{1}) is {2:.1}% a single color (#{3:06X})",
+                    poster.x + 1,
+                    poster.y + 1,
+                    poster.share * 100.0,
+                    poster.color
+                );
+            }
+
+            if options.fail_flat && !flat_posters.is_empty() {
+                return Err(Error::validation(format!(
+                    "{0} of {1} posters are more than {2}% a single color",
+                    flat_posters.len(),
+                    poster_array.pages.len(),
+                    stats::FLAT_THRESHOLD * 100.0
+                )));
+            }
+        }
+    } else if let Format::Poster(input_poster_format) = input_format {
+        poster_array = if remote || options.schema.is_some() {
+            let bytes = if remote {
+                download(input)?
+            } else {
+                fs::read(input).map_err(|error| Error::io(format!("Failed to open input file: {}", error)))?
+            };
+            if let Some(ref schema_file) = options.schema {
+                check_schema(&bytes, input_poster_format, schema_file.as_deref())?;
+            }
+            input_poster_format.parse(bytes.as_slice())?
+        } else {
+            input_poster_format.read(input)?
+        };
+        if let Some(ref budget) = budget {
+            // png image output is streamed row by row instead of drawn at once, unless it's smoothed
+            let image_pixel_scale = (output_format == Format::Image && (output_extension != "png" || options.smooth))
+                .then_some(options.upscale.unwrap_or(preview_pixel_scale));
+            budget.check_render(poster_array.width * 128, poster_array.height * 128, image_pixel_scale)?;
+        }
+    } else {
+        return Err(Error::format("Shouldn't have gotten here 1"));
+    }
+
+    if options.sort_palette {
+        debug!("Sorting the palettes of {} posters", poster_array.pages.len());
+        for poster in poster_array.pages.iter_mut() {
+            sort_poster_palette(poster);
+        }
+    }
+
+    if let Some(ref existing) = options.append {
+        let existing = read_poster_array(existing)?;
+        let (existing_count, new_count) = (existing.pages.len(), poster_array.pages.len());
+        poster_array = append_poster_array(existing, poster_array, options.grid)?;
+        check_poster_count(poster_array.width * 128, poster_array.height * 128, options)?;
+        status!(
+            "Appended {0} posters to {1}, now {2}x{3} posters",
+            new_count,
+            existing_count,
+            poster_array.width,
+            poster_array.height
+        );
+    }
+
+    if let (true, Format::Poster(output_poster_format)) = (options.dedup, output_format) {
+        report_duplicates(&poster_array, output_poster_format);
+    }
+
+    let _writing = start_writing(options)?;
+    status!("Done, saving to file");
+    let write_retries = options.write_retries.unwrap_or(WRITE_RETRIES);
+    if let Format::Poster(output_poster_format) = output_format {
+        match options.max_grid {
+            Some((max_width, max_height)) if poster_array.width > max_width || poster_array.height > max_height => {
+                let parts = split_poster_array(&poster_array, max_width, max_height);
+                status!(
+                    "Splitting {0}x{1} posters into {2} prints of at most {3}x{4}",
+                    poster_array.width,
+                    poster_array.height,
+                    parts.len(),
+                    max_width,
+                    max_height
+                );
+
+                let part_outputs: Vec<PathBuf> = parts
+                    .iter()
+                    .map(|(row, column, _)| {
+                        naming::output_name(
+                            base_output.unwrap(),
+                            options.output_template.as_ref(),
+                            options.frame,
+                            Some((row + 1, column + 1)),
+                        )
+                    })
+                    .collect();
+                naming::check_unique(
+                    &parts
+                        .iter()
+                        .map(|(row, column, _)| format!("Part ({0},{1})", column + 1, row + 1))
+                        .zip(part_outputs.iter().cloned())
+                        .collect::<Vec<_>>(),
+                )?;
+                for part_output in &part_outputs {
+                    check_overwrite(part_output, "Output", options.force)?;
+                }
+
+                for ((_, _, part), part_output) in parts.iter().zip(&part_outputs) {
+                    write_posters(Some(part_output), output_poster_format, part, options, false)?;
+                }
+            }
+            _ => write_posters(output, output_poster_format, &poster_array, options, clipboard)?,
+        }
+
+        if let Some(preview) = preview {
+            save_preview(&poster_array, preview, preview_pixel_scale, preview_scale, resize_filter, threads, preview_colors)?;
+        }
+    } else if let (Format::Image, Some(output)) = (&output_format, output) {
+        let image_pixel_scale = options.upscale.unwrap_or(preview_pixel_scale);
+        if options.smooth {
+            let image = flatten_posters(&poster_array, 1, threads);
+            let (width, height) = (image.width() * image_pixel_scale, image.height() * image_pixel_scale);
+            status!(
+                "Upscaling image to x:{0} y:{1} (from x:{2} y:{3})",
+                width, height, image.width(), image.height()
+            );
+            let image = resize_filter.resize(&image, width, height);
+            write_output(output, write_retries, || image.save(output).map_err(image_io_error))?;
+        } else if output_extension == "png" {
+            write_output(output, write_retries, || stream_png(&poster_array, output, image_pixel_scale))?;
+        } else {
+            let image = flatten_posters(&poster_array, image_pixel_scale, threads);
+            write_output(output, write_retries, || image.save(output).map_err(image_io_error))?;
+        }
+        log_written_size(output);
+    }
+
+    if let Some(sheet) = sheet {
+        save_sheet(&poster_array, sheet, options.sheet_columns.unwrap_or(poster_array.width), threads)?;
+    }
+
+    if let Some(ref swatch) = options.palette_swatch {
+        save_palette_swatch(&poster_array, swatch)?;
+    }
+
+    if let Some(ref guide) = options.assembly_guide {
+        let title = match options.force_label.as_ref().or(options.label.as_ref()) {
+            Some(label) => label.clone(),
+            None => input.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+        };
+        save_assembly_guide(&poster_array, guide, &title, options.origin.unwrap_or_default(), threads)?;
+    }
+
+    let ink = options.ink_report.then(|| stats::ink_report(&poster_array));
+    if let (Some(ref ink), false) = (&ink, options.json) {
+        stats::print_ink_report(ink);
+    }
+
+    if options.json {
+        output::print_summary(&output::Summary {
+            input: input.display().to_string(),
+            output: output.map(|output| output.display().to_string()),
+            width: poster_array.width,
+            height: poster_array.height,
+            pixel_width: poster_array.width * 128,
+            pixel_height: poster_array.height * 128,
+            poster_count: poster_array.pages.len(),
+            palette_size: info::distinct_colors(&poster_array).len(),
+            elapsed_ms: start.elapsed().as_millis(),
+            ink,
+        });
+    }
+
+    Ok(poster_array)
+}
+
+/// Fails once the conversion timed out, so it doesn't write anything after that.
+/// Cancels a conversion running on a worker thread. The worker holds the writing lock while it
+/// writes its outputs, so cancelling waits for a write that already started instead of leaving a
+/// truncated file behind.
+#[derive(Default)]
+struct Cancellation {
+    cancelled: AtomicBool,
+    writing: Mutex<()>,
+}
+
+impl Cancellation {
+    fn lock_writing(&self) -> MutexGuard<'_, ()> {
+        self.writing.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn check_cancelled(options: &ConvertOptions) -> Result<(), Error> {
+    match options.cancelled {
+        Some(ref cancellation) if cancellation.cancelled.load(Ordering::Relaxed) => Err(Error::timeout("Conversion cancelled")),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the conversion isn't cancelled before it writes its outputs, and keeps it from
+/// being cancelled until the returned guard is dropped.
+fn start_writing(options: &ConvertOptions) -> Result<Option<MutexGuard<'_, ()>>, Error> {
+    let Some(ref cancellation) = options.cancelled else {
+        return Ok(None);
+    };
+    let writing = cancellation.lock_writing();
+    check_cancelled(options)?;
+    Ok(Some(writing))
+}
+
+/// Runs a conversion on a worker thread when the options have a timeout, and fails with a timeout
+/// error once it takes longer. The conversion is then cancelled: it stops at the next poster, and
+/// everything it hasn't started writing yet is discarded. Outputs it is writing are finished first.
+fn with_timeout<F>(options: &ConvertOptions, conversion: F) -> Result<(), Error>
+where
+    F: FnOnce(&ConvertOptions) -> Result<(), Error> + Send + 'static,
+{
+    let Some(timeout) = options.timeout else {
+        return conversion(options);
+    };
+
+    let cancellation = Arc::new(Cancellation::default());
+    let options = ConvertOptions {
+        cancelled: Some(cancellation.clone()),
+        ..options.clone()
+    };
+    let (sender, receiver) = mpsc::channel();
+    let worker = thread::spawn(move || {
+        let _ = sender.send(conversion(&options));
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => {
+            let _writing = cancellation.lock_writing();
+            cancellation.cancelled.store(true, Ordering::Relaxed);
+            Err(Error::timeout(format!(
+                "Conversion timed out after {} seconds, nothing more is written",
+                timeout.as_secs_f64()
+            )))
+        }
+        // the worker panicked before sending a result
+        Err(RecvTimeoutError::Disconnected) => match worker.join() {
+            Err(panic) => std::panic::resume_unwind(panic),
+            Ok(()) => unreachable!(),
+        },
+    }
+}
+
+/// Converts every page of a multi-page TIFF or frame of an animated GIF to its own output when
+/// frames is given, named by the output template or with the page number as a suffix of the
+/// output, preview and sheet files, and saves the animated preview of them. Any other input is
+/// converted as is.
+fn convert_frames(
+    input: &Path,
+    output: Option<&Path>,
+    preview: Option<&Path>,
+    sheet: Option<&Path>,
+    clipboard: bool,
+    options: &ConvertOptions,
+) -> Result<(), Error> {
+    let extension = input
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+    // GIFs are only decoded frame by frame when asked to, every other GIF reader takes the first
+    let multi_page = matches!(extension.as_deref(), Some("tif" | "tiff")) || (options.frames && is_gif(input));
+    if !multi_page || !input.is_file() || clipboard {
+        return convert(input, output, preview, sheet, clipboard, options).map(|_| ());
+    }
+
+    // GIF frames are shown for as long as the GIF says, TIFF pages have no timing
+    let delays = if is_gif(input) {
+        gif_frames::frame_delays(input)?
+    } else {
+        vec![DEFAULT_FRAME_DELAY; tiff_pages::page_count(input)?]
+    };
+    let pages = delays.len();
+    if !options.frames {
+        if pages > 1 {
+            status!(
+                "Input has {} pages, only converting the first (use --frames to convert every page)",
+                pages
+            );
+        }
+        return convert(input, output, preview, sheet, clipboard, options).map(|_| ());
+    }
+
+    if let Some(ref preview_animated) = options.preview_animated {
+        check_overwrite(preview_animated, "Animated preview", options.force)?;
+    }
+    if let Some(output) = output {
+        let page_outputs: Vec<(String, PathBuf)> = (1..=pages as u32)
+            .map(|page| {
+                let name = naming::output_name(output, options.output_template.as_ref(), Some(page), None);
+                (format!("Page {}", page), name)
+            })
+            .collect();
+        naming::check_unique(&page_outputs)?;
+    }
+
+    let mut frames = Vec::new();
+    for (page, delay) in delays.into_iter().enumerate() {
+        check_cancelled(options)?;
+        status!("Converting page {0} of {1}", page + 1, pages);
+        let suffix = format!("_p{}", page + 1);
+        let page_options = ConvertOptions {
+            page,
+            frame: Some(page as u32 + 1),
+            dump_preprocessed: options.dump_preprocessed.as_ref().map(|dump| naming::with_suffix(dump, &suffix)),
+            palette_swatch: options.palette_swatch.as_ref().map(|swatch| naming::with_suffix(swatch, &suffix)),
+            assembly_guide: options.assembly_guide.as_ref().map(|guide| naming::with_suffix(guide, &suffix)),
+            ..options.clone()
+        };
+        let poster_array = convert(
+            input,
+            output,
+            preview.map(|preview| naming::with_suffix(preview, &suffix)).as_deref(),
+            sheet.map(|sheet| naming::with_suffix(sheet, &suffix)).as_deref(),
+            false,
+            &page_options,
+        )?;
+
+        if options.preview_animated.is_some() {
+            let frame = preview_image(
+                &poster_array,
+                options.preview_pixel_scale.unwrap_or(1),
+                preview_scale(options),
+                options.resize_algorithm.unwrap_or_default(),
+                job_count(options),
+                (preview_gamma(options), options.preview_cvd),
+            );
+            let delay = if delay.is_zero() { DEFAULT_FRAME_DELAY } else { delay };
+            frames.push((frame.into_rgba8(), delay));
+        }
+    }
+
+    if let Some(ref preview_animated) = options.preview_animated {
+        let _writing = start_writing(options)?;
+        status!("Saving animated preview of {} frames", frames.len());
+        animation::save_animation(frames, preview_animated)?;
+        log_written_size(preview_animated);
+    }
+    Ok(())
+}
+
+fn batch(args: &BatchArgs) -> Result<(), Error> {
+    if !args.input.is_dir() {
+        return Err(Error::io("Batch input has to be a directory."));
+    }
+    if args.output.exists() && !args.output.is_dir() {
+        return Err(Error::io("Batch output has to be a directory."));
+    }
+    if args.options.dump_preprocessed.is_some() {
+        return Err(Error::usage("dump-preprocessed arg not allowed in batch mode"));
+    }
+    if args.options.palette_swatch.is_some() {
+        return Err(Error::usage("palette-swatch arg not allowed in batch mode"));
+    }
+    if args.options.assembly_guide.is_some() {
+        return Err(Error::usage("assembly-guide arg not allowed in batch mode"));
+    }
+    fs::create_dir_all(&args.output)
+        .map_err(|error| Error::io(format!("Failed to create output directory: {}", error)))?;
+
+    let mut entries: Vec<PathBuf> = fs::read_dir(&args.input)
+        .map_err(|error| Error::io(format!("Failed to read input directory: {}", error)))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    entries.sort();
+
+    let mut images: Vec<PathBuf> = Vec::new();
+    // the whole stem is kept, so scan.v1.png and scan.v2.png don't both become scan.2dja
+    let mut outputs: Vec<PathBuf> = Vec::new();
+    let mut skipped = 0;
+    for entry in entries {
+        let extension = entry
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+
+        match entry.file_stem() {
+            Some(stem) if extension.and_then(|extension| file_format(&extension)) == Some(Format::Image) => {
+                outputs.push(args.output.join(format!("{}.2dja", stem.to_string_lossy())));
+                images.push(entry);
+            }
+            _ => {
+                note!("Skipping unsupported file {}", entry.display());
+                skipped += 1;
+            }
+        }
+    }
+    naming::check_unique(
+        &images
+            .iter()
+            .zip(&outputs)
+            .map(|(image, output)| {
+                let name = naming::output_name(output, args.options.output_template.as_ref(), None, None);
+                (format!("Image {}", image.display()), name)
+            })
+            .collect::<Vec<_>>(),
+    )?;
+
+    // parallelism goes across files, so every file is quantized on a single thread
+    let options = ConvertOptions {
+        jobs: Some(1),
+        ..args.options.clone()
+    };
+    let thread_count = job_count(&args.options) as usize;
+
+    let next_image = AtomicUsize::new(0);
+    let succeeded = AtomicUsize::new(0);
+    let failures: Mutex<Vec<(usize, ErrorKind)>> = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for _ in 0..usize::min(thread_count, images.len()) {
+            scope.spawn(|| loop {
+                let index = next_image.fetch_add(1, Ordering::Relaxed);
+                let Some(image) = images.get(index) else {
+                    break;
+                };
+
+                let output = &outputs[index];
+                status!(
+                    "Converting {} to {}",
+                    image.display(),
+                    naming::output_name(output, options.output_template.as_ref(), None, None).display()
+                );
+
+                let conversion = {
+                    let image = image.clone();
+                    let output = output.clone();
+                    move |options: &ConvertOptions| convert_frames(&image, Some(&output), None, None, false, options)
+                };
+                match with_timeout(&options, conversion) {
+                    Ok(()) => {
+                        succeeded.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(error) => {
+                        eprintln!("Failed to convert {}: {}", image.display(), error);
+                        failures.lock().unwrap().push((index, error.kind));
+                    }
+                }
+            });
+        }
+    });
+
+    let (succeeded, failures) = (succeeded.into_inner(), failures.into_inner().unwrap());
+    status!(
+        "Batch done: {} succeeded, {} failed, {} skipped",
+        succeeded,
+        failures.len(),
+        skipped
+    );
+
+    // exit with the category of the first failed image, so the code doesn't depend on thread timing
+    if let Some((_, kind)) = failures.iter().min_by_key(|(index, _)| *index) {
+        return Err(Error::new(
+            *kind,
+            format!("{} of {} images failed to convert", failures.len(), images.len()),
+        ));
+    }
+    Ok(())
+}
+
+fn bench(args: &BenchArgs) -> Result<(), Error> {
+    let options = &args.options;
+    if !args.input.is_file() {
+        return Err(Error::io("Bench input has to be a file."));
+    }
+    if let Some(ref out_dir) = args.out_dir {
+        if !out_dir.is_dir() {
+            return Err(Error::io("Bench output has to be a directory."));
+        }
+    }
+    if options.color_distance.is_some() {
+        return Err(Error::usage("color-distance arg not allowed with bench"));
+    }
+    if options.json {
+        return Err(Error::usage("json flag not allowed with bench"));
+    }
+    if options.timeout.is_some() {
+        return Err(Error::usage("timeout arg not allowed with bench"));
+    }
+    if options.pick_best {
+        return Err(Error::usage("pick-best flag not allowed with bench"));
+    }
+    if options.palette_swatch.is_some() {
+        return Err(Error::usage("palette-swatch arg not allowed with bench"));
+    }
+    if options.assembly_guide.is_some() {
+        return Err(Error::usage("assembly-guide arg not allowed with bench"));
+    }
+
+    let resize_filter = options.resize_algorithm.unwrap_or_default();
+    let limits = if options.no_limits || options.max_decode_pixels.is_some() {
+        None
+    } else {
+        Some(Limits::default())
+    };
+    let budget = options
+        .max_memory
+        .filter(|max_memory| *max_memory != 0)
+        .map(|max_memory| memory::Budget::new(max_memory, Some((1, (1.0, 1.0)))));
+    let check_dimensions = |width, height| {
+        check_decode_pixels(width, height, options.max_decode_pixels)?;
+        match budget {
+            Some(ref budget) => budget.check_decode(width, height),
+            None => Ok(()),
+        }
+    };
+    let image = read_resized_image(&args.input, limits, &check_dimensions, options, resize_filter, budget.as_ref())?;
+    let image = preprocess::sharpen(image, options.sharpen.unwrap_or(0.0));
+    let image = preprocess::adjust_colors(image, &color_adjustments(options));
+
+    let (x_size, y_size) = image.dimensions();
+    if (x_size % 128 != 0) || (y_size % 128 != 0) {
+        return Err(Error::validation(format!(
+            "Image resolutions have to be multiples of 128 (Currently x:{0} y:{1})",
+            x_size, y_size
+        )));
+    }
+
+    let seed = options.seed.unwrap_or_else(|| StdRng::from_entropy().gen());
+    let palette_generations = [
+        ("median-cut", PaletteGeneration::MedianCut { colors: 63 }),
+        (
+            "k-means",
+            PaletteGeneration::KMeans {
+                colors: options.generate_palette.unwrap_or(63),
+                seed,
+            },
+        ),
+    ];
+
+    let weights = read_palette_weights(options)?;
+    let mut results = Vec::new();
+    for (palette_name, palette_generation) in &palette_generations {
+        for color_distance in ColorDistance::value_variants() {
+            let distance_name = color_distance.to_possible_value().unwrap().get_name().to_string();
+            println!("Converting with {0} and {1}...", palette_name, distance_name);
+
+            let start = Instant::now();
+            let (poster_array, _) = image_to_poster::image_to_posters(
+                image.clone(),
+                |_, _, _, _| String::new(),
+                |_, _, _, _| String::new(),
+                &QuantizeOptions {
+                    color_distance: *color_distance,
+                    ..quantize_options(options, palette_generation.clone(), weights.as_deref())
+                },
+            )?;
+            let elapsed = start.elapsed();
+            let stats = stats::poster_array_stats(&poster_array, &image);
+
+            if let Some(ref out_dir) = args.out_dir {
+                let preview = out_dir.join(format!("{0}-{1}.png", palette_name, distance_name));
+                save_preview(&poster_array, &preview, 1, (1.0, 1.0), resize_filter, job_count(options), (None, None))?;
+            }
+            results.push((palette_name, distance_name, stats.mean_squared_error, elapsed));
+        }
+    }
+
+    println!();
+    println!("{0:<12} {1:<14} {2:>10} {3:>10}", "Palette", "Distance", "MSE", "Time");
+    for (palette_name, distance_name, mean_squared_error, elapsed) in results {
+        println!(
+            "{0:<12} {1:<14} {2:>10.2} {3:>9.2}s",
+            palette_name,
+            distance_name,
+            mean_squared_error,
+            elapsed.as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+/// Time of day (UTC) to prefix watch messages with.
+#[cfg(feature = "notify")]
+fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default();
+    format!("{:02}:{:02}:{:02}", seconds / 3600 % 24, seconds / 60 % 60, seconds % 60)
+}
+
+/// Converts the input, and again every time the file system reports a change to it, until
+/// interrupted. The output is overwritten every time, and a failed conversion is reported without
+/// ending the watch.
+///
+/// The directory of the input is watched rather than the file itself, so the watch survives
+/// editors that save by writing a new file and renaming it over the old one.
+#[cfg(feature = "notify")]
+fn watch(args: &WatchArgs) -> Result<(), Error> {
+    use notify::{RecursiveMode, Watcher};
+
+    if !args.input.is_file() {
+        return Err(Error::io("Watch input has to be a file."));
+    }
+    let input = fs::canonicalize(&args.input)
+        .map_err(|error| Error::io(format!("Failed to find watch input: {}", error)))?;
+    let directory = input.parent().unwrap_or(Path::new("/")).to_path_buf();
+
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)
+        .map_err(|error| Error::io(format!("Failed to watch for changes: {}", error)))?;
+    watcher
+        .watch(&directory, RecursiveMode::NonRecursive)
+        .map_err(|error| Error::io(format!("Failed to watch {0}: {1}", directory.display(), error)))?;
+    let changes_input = |event: notify::Result<notify::Event>| match event {
+        Ok(event) => !event.kind.is_access() && event.paths.iter().any(|path| path.file_name() == input.file_name()),
+        Err(error) => {
+            warn!("Watch error: {}", error);
+            false
+        }
+    };
+
+    let options = ConvertOptions {
+        force: true,
+        ..args.options.clone()
+    };
+    loop {
+        status!("[{0}] Converting {1}", timestamp(), args.input.display());
+        let conversion = {
+            let (input, output, preview) = (args.input.clone(), args.output.clone(), args.preview.clone());
+            move |options: &ConvertOptions| convert_frames(&input, Some(&output), preview.as_deref(), None, false, options)
+        };
+        match with_timeout(&options, conversion) {
+            Ok(()) => status!("[{0}] Wrote {1}", timestamp(), args.output.display()),
+            Err(error) => eprintln!("[{0}] Failed to convert {1}: {2}", timestamp(), args.input.display(), error),
+        }
+        status!("Watching {} for changes, press Ctrl-C to stop", args.input.display());
+
+        // wait for a change, then for the input to settle, and skip changes that deleted it
+        loop {
+            let event = events
+                .recv()
+                .map_err(|_| Error::io("Stopped receiving file system events"))?;
+            if !changes_input(event) {
+                continue;
+            }
+            loop {
+                match events.recv_timeout(WATCH_DEBOUNCE) {
+                    Ok(_) => continue,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return Err(Error::io("Stopped receiving file system events")),
+                }
+            }
+            if input.is_file() {
+                break;
+            }
+        }
+    }
+}
+
+/// Lets the user pick palette colors in the terminal, starting from the palette the options
+/// generate, then converts the input with the picked colors as a palette-from image.
+#[cfg(feature = "tui")]
+fn tui(args: &TuiArgs) -> Result<(), Error> {
+    let options = &args.options;
+    if !args.input.is_file() {
+        return Err(Error::io("Tui input has to be a file."));
+    }
+    tui::check_terminal()?;
+    if options.json {
+        return Err(Error::usage("json flag not allowed with tui"));
+    }
+    if options.timeout.is_some() {
+        return Err(Error::usage("timeout arg not allowed with tui"));
+    }
+    if options.pick_best {
+        return Err(Error::usage("pick-best flag not allowed with tui"));
+    }
+    if options.palette_from.is_some() && options.generate_palette.is_some() {
+        return Err(Error::usage("palette-from arg not allowed with generate-palette"));
+    }
+    let palette_file = args.output.with_file_name(format!(
+        "{}-palette.png",
+        args.output.file_stem().unwrap_or_default().to_string_lossy()
+    ));
+    check_overwrite(&args.output, "Output", options.force)?;
+    check_overwrite(&palette_file, "Palette", options.force)?;
+
+    let resize_filter = options.resize_algorithm.unwrap_or_default();
+    let limits = if options.no_limits || options.max_decode_pixels.is_some() {
+        None
+    } else {
+        Some(Limits::default())
+    };
+    let budget = options
+        .max_memory
+        .filter(|max_memory| *max_memory != 0)
+        .map(|max_memory| memory::Budget::new(max_memory, Some((1, (1.0, 1.0)))));
+    let check_dimensions = |width, height| {
+        check_decode_pixels(width, height, options.max_decode_pixels)?;
+        match budget {
+            Some(ref budget) => budget.check_decode(width, height),
+            None => Ok(()),
+        }
+    };
+    let image = read_resized_image(&args.input, limits.clone(), &check_dimensions, options, resize_filter, budget.as_ref())?;
+    let image = preprocess::sharpen(image, options.sharpen.unwrap_or(0.0));
+    let image = preprocess::adjust_colors(image, &color_adjustments(options));
+
+    let (x_size, y_size) = image.dimensions();
+    if (x_size % 128 != 0) || (y_size % 128 != 0) {
+        return Err(Error::validation(format!(
+            "Image resolutions have to be multiples of 128 (Currently x:{0} y:{1})",
+            x_size, y_size
+        )));
+    }
+
+    let weights = read_palette_weights(options)?;
+    let quantize = |palette_generation: PaletteGeneration| {
+        image_to_poster::image_to_posters(
+            image.clone(),
+            |_, _, _, _| String::new(),
+            |_, _, _, _| String::new(),
+            &quantize_options(options, palette_generation, weights.as_deref()),
+        )
+        .map(|(poster_array, _)| poster_array)
+    };
+
+    let palette_generation = match (options.generate_palette, &options.palette_from) {
+        (Some(colors), _) => PaletteGeneration::KMeans {
+            colors,
+            seed: options.seed.unwrap_or_else(|| StdRng::from_entropy().gen()),
+        },
+        (None, Some(reference)) => PaletteGeneration::Fixed(read_palette_image(reference, limits, options)?.into()),
+        (None, None) => PaletteGeneration::MedianCut { colors: 63 },
+    };
+    status!("Quantizing {}", args.input.display());
+    // every color a poster uses, per poster quantization can give more than fit in one palette
+    let mut palette: Vec<u32> = Vec::new();
+    for color in quantize(palette_generation)?.pages.iter().flat_map(|page| &page.palette) {
+        if !palette.contains(color) {
+            palette.push(*color);
+        }
+    }
+    if palette.is_empty() {
+        // a fully transparent image with alpha-threshold leaves no color to pick
+        return Err(Error::validation("Posters use no colors, there is nothing to pick from"));
+    }
+    if palette.len() > 63 {
+        warn!("Posters use {} colors, only the first 63 can be picked from", palette.len());
+        palette.truncate(63);
+    }
+    let palette: Vec<Color> = palette
+        .iter()
+        .map(|color| Color::new((color >> 16) as u8, (color >> 8) as u8, *color as u8, 255))
+        .collect();
+
+    let picked = tui::pick_palette(&palette, |colors| {
+        let poster_array = quantize(PaletteGeneration::Fixed(colors.into()))?;
+        let error = stats::poster_array_stats(&poster_array, &image).mean_squared_error;
+        Ok((flatten_posters(&poster_array, 1, job_count(options)), error))
+    })?;
+    let Some(picked) = picked else {
+        status!("Quit without saving");
+        return Ok(());
+    };
+
+    let mut palette_image = RgbaImage::new(picked.len() as u32, 1);
+    for (pixel, color) in palette_image.pixels_mut().zip(&picked) {
+        *pixel = Rgba([color.r, color.g, color.b, 255]);
+    }
+    palette_image
+        .save(&palette_file)
+        .map_err(|error| Error::io(format!("Failed to save palette image: {}", error)))?;
+    status!("Saved {0} colors to {1}", picked.len(), palette_file.display());
+
+    let options = ConvertOptions {
+        palette_from: Some(palette_file),
+        generate_palette: None,
+        ..options.clone()
+    };
+    convert_frames(&args.input, Some(&args.output), args.preview.as_deref(), None, false, &options)
+}
+
+/// Parses the command line again with the flags from the config file in front of it, so
+/// explicitly given flags override the config.
+fn apply_config(cli: Cli) -> Result<Cli, Error> {
+    let (config_args, position) = match cli.command {
+        Some(Command::Batch(ref args)) => (&args.config, 2),
+        Some(Command::Bench(ref args)) => (&args.config, 2),
+        #[cfg(feature = "notify")]
+        Some(Command::Watch(ref args)) => (&args.config, 2),
+        #[cfg(feature = "tui")]
+        Some(Command::Tui(ref args)) => (&args.config, 2),
+        Some(Command::Info(_)) | Some(Command::Diff(_)) => return Ok(cli),
+        None => (&cli.config, 1),
+    };
+
+    let file = if config_args.no_config {
+        None
+    } else {
+        config_args.config.clone().or_else(config::find_config_file)
+    };
+    let Some(file) = file else {
+        return Ok(cli);
+    };
+
+    let args = config::read_config_args(&file)?;
+    if let Err(error) = ConfigFile::try_parse_from(&args) {
+        let message = error.to_string();
+        return Err(Error::usage(format!(
+            "Invalid config file {0}: {1}",
+            file.display(),
+            message.lines().next().unwrap_or_default().trim_start_matches("error: ")
+        )));
+    }
+
+    let mut command_line: Vec<OsString> = env::args_os().collect();
+    command_line.splice(position..position, args.into_iter().map(OsString::from));
+    Ok(Cli::parse_from(command_line))
+}
+
+/// Compares two poster files, returning whether they are identical.
+fn diff(args: &DiffArgs) -> Result<bool, Error> {
+    if let Some(ref image) = args.image {
+        check_image_output(image, "Diff image", args.force)?;
+    }
+
+    let old = read_poster_array(&args.old)?;
+    let new = read_poster_array(&args.new)?;
+    let pixels = diff::differing_pixels(&old, &new)?;
+    diff::print_diff(&new, &pixels);
+
+    if let Some(ref image) = args.image {
+        diff::render_diff(&new, &pixels)
+            .save(image)
+            .map_err(|error| Error::io(format!("Failed to save diff image: {}", error)))?;
+        log_written_size(image);
+    }
+
+    Ok(pixels.is_empty())
+}
+
+fn main() {
+    let cli = match apply_config(Cli::parse()) {
+        Ok(cli) => cli,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(error.kind.exit_code());
+        }
+    };
+
+    let options = match cli.command {
+        Some(Command::Batch(ref args)) => Some(&args.options),
+        Some(Command::Bench(ref args)) => Some(&args.options),
+        #[cfg(feature = "notify")]
+        Some(Command::Watch(ref args)) => Some(&args.options),
+        #[cfg(feature = "tui")]
+        Some(Command::Tui(ref args)) => Some(&args.options),
+        Some(Command::Info(_)) | Some(Command::Diff(_)) => None,
+        None => Some(&cli.options),
+    };
+    let verbose = options.is_some_and(|options| options.verbose);
+    let quiet = options.is_some_and(|options| options.quiet);
+    output::set_quiet(quiet || options.is_some_and(|options| options.json));
+    env_logger::Builder::new()
+        .filter_level(if verbose {
+            LevelFilter::Debug
+        } else if quiet {
+            LevelFilter::Error
+        } else {
+            LevelFilter::Warn
+        })
+        .parse_default_env()
+        .init();
+
+    let result = match cli.command {
+        Some(Command::Batch(ref args)) => batch(args),
+        Some(Command::Bench(ref args)) => bench(args),
+        #[cfg(feature = "notify")]
+        Some(Command::Watch(ref args)) => watch(args),
+        #[cfg(feature = "tui")]
+        Some(Command::Tui(ref args)) => tui(args),
+        Some(Command::Info(ref args)) => {
+            read_poster_array(&args.input).map(|poster_array| info::print_info(&poster_array, args.json))
+        }
+        Some(Command::Diff(ref args)) => match diff(args) {
+            Ok(false) => process::exit(1),
+            result => result.map(|_| ()),
+        },
+        None => {
+            let (input, output, preview, sheet, clipboard) =
+                (cli.input.clone().unwrap(), cli.output.clone(), cli.preview.clone(), cli.sheet.clone(), cli.clipboard);
+            with_timeout(&cli.options, move |options| {
+                convert_frames(&input, output.as_deref(), preview.as_deref(), sheet.as_deref(), clipboard, options)
+            })
+        }
+    };
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        process::exit(error.kind.exit_code());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Convert options of a command line, after the input and output.
+    fn convert_options(args: &[&str]) -> ConvertOptions {
+        let command_line = ["img2poster", "-i", "in.png", "-o", "out.2dja"].iter().chain(args);
+        Cli::try_parse_from(command_line).unwrap_or_else(|error| panic!("{}", error)).options
+    }
+
+    fn rejects(args: &[&str]) -> bool {
+        let command_line = ["img2poster", "-i", "in.png", "-o", "out.2dja"].iter().chain(args);
+        Cli::try_parse_from(command_line).is_err()
+    }
+
+    #[test]
+    fn autoscale_factor_has_to_be_above_zero() {
+        for factor in ["0", "0.0", "-0", "-1", "-0.5", "NaN", "inf", "-inf", "", "two"] {
+            assert!(parse_factor(factor).is_err(), "accepted factor {:?}", factor);
+            assert!(rejects(&[&format!("--autoscale={}", factor)]), "accepted --autoscale={}", factor);
+        }
+        for (factor, expected) in [("1", 1.0), ("0.5", 0.5), (" 2 ", 2.0), ("1e300", 1e300)] {
+            assert_eq!(parse_factor(factor), Ok(expected));
+        }
+    }
+
+    #[test]
+    fn scales_have_to_be_above_zero() {
+        for scale in ["0", "0px", "0t", "0%", "-5", "-5t", "-5%", "NaN%", "inf%", "33554432t", "big"] {
+            assert!(parse_scale(scale).is_err(), "accepted scale {:?}", scale);
+        }
+        assert!(matches!(parse_scale("33554431t"), Ok(Scale::Tiles(33554431))));
+        assert!(matches!(parse_scale("1e300%"), Ok(Scale::Percent(_))));
+    }
+
+    #[test]
+    fn tiny_autoscale_gives_one_poster() {
+        assert_eq!(autoscale_image(1000, 600, 1e-300, Rounding::Nearest), (128, 128));
+        assert_eq!(autoscale_image(1000, 600, f64::MIN_POSITIVE, Rounding::Up), (128, 128));
+    }
+
+    #[test]
+    fn huge_autoscale_doesnt_overflow() {
+        for rounding in [Rounding::Nearest, Rounding::Up, Rounding::Down] {
+            let (width, height) = autoscale_image(1000, 600, 1e300, rounding);
+            assert_eq!((width % 128, height % 128), (0, 0));
+            assert!(width >= u32::MAX - 255 && height >= u32::MAX - 255);
+        }
+    }
+
+    #[test]
+    fn huge_scales_fail_before_resizing() {
+        let image = DynamicImage::new_rgba8(2, 2);
+        for args in [&["-a", "1e300"][..], &["-a", "100000"], &["-x", "1e30%", "-y", "1e30%"], &["-x", "4000000t", "-y", "1t"]] {
+            let options = convert_options(args);
+            match resize_input(image.clone(), &options, ResizeFilter::default(), None) {
+                Err(error) => assert!(error.kind == ErrorKind::Validation, "{:?}: {}", args, error),
+                Ok(resized) => panic!("{:?} resized to {:?}", args, resized.dimensions()),
+            }
+        }
+    }
+
+    #[test]
+    fn appending_cant_exceed_max_posters() {
+        let directory = env::temp_dir().join(format!("img2poster-append-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let (image, existing) = (directory.join("in.png"), directory.join("existing.2dja"));
+        RgbaImage::from_pixel(128, 128, Rgba([200, 40, 40, 255])).save(&image).unwrap();
+        let append = || {
+            let options = convert_options(&["--max-posters", "2", "--append", existing.to_str().unwrap()]);
+            convert_frames(&image, Some(&existing), None, None, false, &options)
+        };
+
+        let converted = convert_frames(&image, Some(&existing), None, None, false, &convert_options(&[])).and_then(|()| append());
+        assert!(converted.is_ok(), "{}", converted.err().map(|error| error.to_string()).unwrap_or_default());
+        let result = append();
+        fs::remove_dir_all(&directory).unwrap();
+        match result {
+            Err(error) => assert!(error.kind == ErrorKind::Validation, "{}", error),
+            Ok(()) => panic!("appended a third poster with --max-posters 2"),
+        }
+    }
+
+    #[test]
+    fn writes_are_retried_only_while_locked() {
+        let path = Path::new("locked.png");
+        let write = |failures: &[io::ErrorKind]| {
+            let mut attempts = 0;
+            let result = write_output(path, 3, || {
+                attempts += 1;
+                match failures.get(attempts - 1) {
+                    Some(kind) => Err(io::Error::from(*kind)),
+                    None => Ok(()),
+                }
+            });
+            (result.is_ok(), attempts)
+        };
+
+        assert_eq!(write(&[io::ErrorKind::ResourceBusy, io::ErrorKind::ResourceBusy]), (true, 3));
+        assert_eq!(write(&[io::ErrorKind::ResourceBusy; 4]), (false, 4));
+        assert_eq!(write(&[io::ErrorKind::PermissionDenied]), (false, 1));
+    }
+
+    #[test]
+    fn timeout_finishes_a_write_in_progress() {
+        let written = Arc::new(AtomicBool::new(false));
+        let worker_written = written.clone();
+        let result = with_timeout(&convert_options(&["--timeout", "0.1"]), move |options| {
+            let _writing = start_writing(options)?;
+            thread::sleep(Duration::from_millis(300));
+            worker_written.store(true, Ordering::Relaxed);
+            Ok(())
+        });
 
-        output_image
-            .save(cli.output)
-            .expect("Failed to save image.");
+        assert!(matches!(result, Err(ref error) if error.kind == ErrorKind::Timeout));
+        assert!(written.load(Ordering::Relaxed));
     }
 }
@@ -0,0 +1,67 @@
+use crate::error::Error;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Label and tooltip of one poster, replacing the generated ones. Coordinates start at 1 and are
+/// the ones of the generated labels, so rows follow origin.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PosterOverride {
+    pub x: u32,
+    pub y: u32,
+    pub label: Option<String>,
+    pub tooltip: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestFile {
+    posters: Vec<PosterOverride>,
+}
+
+/// Labels and tooltips for specific posters of a print, read from a JSON file like
+/// `{"posters": [{"x": 1, "y": 2, "label": "Sunrise", "tooltip": "Panel 1"}]}`.
+pub struct Manifest {
+    overrides: HashMap<(u32, u32), PosterOverride>,
+}
+
+impl Manifest {
+    pub fn read(file: &Path) -> Result<Manifest, Error> {
+        let text = fs::read_to_string(file)
+            .map_err(|error| Error::io(format!("Failed to read manifest {0}: {1}", file.display(), error)))?;
+        let manifest: ManifestFile = serde_json::from_str(&text)
+            .map_err(|error| Error::decode(format!("Failed to parse manifest {0}: {1}", file.display(), error)))?;
+
+        let mut overrides = HashMap::new();
+        for poster in manifest.posters {
+            if poster.x == 0 || poster.y == 0 {
+                return Err(Error::validation(format!(
+                    "Manifest poster ({0},{1}) is invalid, coordinates start at 1",
+                    poster.x, poster.y
+                )));
+            }
+            let (x, y) = (poster.x, poster.y);
+            if overrides.insert((x, y), poster).is_some() {
+                return Err(Error::validation(format!("Manifest lists poster ({0},{1}) more than once", x, y)));
+            }
+        }
+        Ok(Manifest { overrides })
+    }
+
+    /// Every override, sorted by row and column.
+    pub fn overrides(&self) -> Vec<&PosterOverride> {
+        let mut overrides: Vec<&PosterOverride> = self.overrides.values().collect();
+        overrides.sort_by_key(|poster| (poster.y, poster.x));
+        overrides
+    }
+
+    pub fn label(&self, x: u32, y: u32) -> Option<&str> {
+        self.overrides.get(&(x, y)).and_then(|poster| poster.label.as_deref())
+    }
+
+    pub fn tooltip(&self, x: u32, y: u32) -> Option<&str> {
+        self.overrides.get(&(x, y)).and_then(|poster| poster.tooltip.as_deref())
+    }
+}
@@ -0,0 +1,98 @@
+use crate::error::Error;
+
+const MEGABYTE: u64 = 1024 * 1024;
+
+/// Bytes per pixel of a decoded image, enough for 16 bit RGBA, plus the 8 bit RGBA copy it's
+/// reduced to.
+const DECODED_BYTES: u64 = 8 + 4;
+/// Bytes per pixel of a resized image: the 8 bit RGBA result plus the 32 bit float RGBA buffer the
+/// resize filters keep between their horizontal and vertical pass.
+const RESIZED_BYTES: u64 = 4 + 16;
+/// Bytes per pixel while quantizing: the colors handed to the quantizer (4), a histogram entry for
+/// every distinct color (up to 16), the palette indices (1) and the poster pixels (1).
+const QUANTIZE_BYTES: u64 = 4 + 16 + 1 + 1;
+/// Bytes per pixel of drawn posters in 8 bit RGBA.
+const RENDER_BYTES: u64 = 4;
+
+fn pixels(width: u32, height: u32) -> u64 {
+    width as u64 * height as u64
+}
+
+/// Bytes to draw posters of this size at a pixel scale, and to resize the drawing if the scale
+/// (horizontal and vertical) isn't 1.
+fn render_bytes(width: u32, height: u32, pixel_scale: u32, scale: (f64, f64)) -> u64 {
+    let (width, height) = (width as u64 * pixel_scale as u64, height as u64 * pixel_scale as u64);
+    let mut bytes = width.saturating_mul(height).saturating_mul(RENDER_BYTES);
+    if scale != (1.0, 1.0) {
+        let scaled = ((width as f64 * scale.0).ceil() as u64).saturating_mul((height as f64 * scale.1).ceil() as u64);
+        bytes = bytes.saturating_add(scaled.saturating_mul(RESIZED_BYTES));
+    }
+    bytes
+}
+
+/// The most memory a conversion may allocate, from max-memory. Every estimate adds up all buffers
+/// as if they were alive at once, so it's higher than the real peak.
+pub struct Budget {
+    max_bytes: u64,
+    /// Pixel scale and horizontal and vertical scale of the preview, if one is saved
+    preview: Option<(u32, (f64, f64))>,
+}
+
+impl Budget {
+    pub fn new(max_megabytes: u64, preview: Option<(u32, (f64, f64))>) -> Budget {
+        Budget {
+            max_bytes: max_megabytes.saturating_mul(MEGABYTE),
+            preview,
+        }
+    }
+
+    fn preview_bytes(&self, width: u32, height: u32) -> u64 {
+        self.preview
+            .map_or(0, |(pixel_scale, scale)| render_bytes(width, height, pixel_scale, scale))
+    }
+
+    fn check_parts(&self, parts: &[(&str, u64)]) -> Result<(), Error> {
+        let total = parts.iter().fold(0u64, |total, (_, bytes)| total.saturating_add(*bytes));
+        if total <= self.max_bytes {
+            return Ok(());
+        }
+
+        let listed: Vec<String> = parts
+            .iter()
+            .filter(|(_, bytes)| *bytes > 0)
+            .map(|(name, bytes)| format!("{0} {1:.1} MB", name, *bytes as f64 / MEGABYTE as f64))
+            .collect();
+        Err(Error::validation(format!(
+            "Converting would need about {0:.1} MB ({1}), more than the max-memory of {2} MB",
+            total as f64 / MEGABYTE as f64,
+            listed.join(", "),
+            self.max_bytes / MEGABYTE
+        )))
+    }
+
+    /// Checks decoding an image, before its final size is known.
+    pub fn check_decode(&self, width: u32, height: u32) -> Result<(), Error> {
+        self.check_parts(&[("decoded image", pixels(width, height).saturating_mul(DECODED_BYTES))])
+    }
+
+    /// Checks converting an image of the input size to posters of the output size.
+    pub fn check(&self, input: (u32, u32), output: (u32, u32)) -> Result<(), Error> {
+        let (width, height) = output;
+        self.check_parts(&[
+            ("decoded image", pixels(input.0, input.1).saturating_mul(DECODED_BYTES)),
+            ("resized image", pixels(width, height).saturating_mul(RESIZED_BYTES)),
+            ("quantization", pixels(width, height).saturating_mul(QUANTIZE_BYTES)),
+            ("preview", self.preview_bytes(width, height)),
+        ])
+    }
+
+    /// Checks drawing posters that are already quantized, of this size in pixels, to the preview
+    /// and to an image output at the given pixel scale.
+    pub fn check_render(&self, width: u32, height: u32, image_pixel_scale: Option<u32>) -> Result<(), Error> {
+        self.check_parts(&[
+            ("posters", pixels(width, height)),
+            ("image", image_pixel_scale.map_or(0, |pixel_scale| render_bytes(width, height, pixel_scale, (1.0, 1.0)))),
+            ("preview", self.preview_bytes(width, height)),
+        ])
+    }
+}
@@ -0,0 +1,164 @@
+use crate::error::Error;
+use log::warn;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Placeholder {
+    Stem,
+    Frame,
+    Row,
+    Col,
+    Ext,
+}
+
+#[derive(Clone)]
+enum Part {
+    Text(String),
+    /// A placeholder, with the number of digits numbers are zero padded to
+    Placeholder(Placeholder, usize),
+}
+
+/// File name of the numbered outputs of a conversion, like `{stem}_f{frame:03}.{ext}`. Stem and
+/// ext are those of the output, frame is the page of a multi-page input and row and col are the
+/// part of a split print, all starting at 1.
+#[derive(Clone)]
+pub struct OutputTemplate {
+    parts: Vec<Part>,
+}
+
+impl OutputTemplate {
+    pub fn parse(template: &str) -> Result<OutputTemplate, String> {
+        if template.contains(['/', '\\']) {
+            return Err("has to be a file name, without a directory".to_string());
+        }
+
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                return Err(format!("unmatched }} in {}", template));
+            }
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| format!("unmatched {{ in {}", template))?;
+
+            let field = &rest[start + 1..end];
+            let (name, width) = match field.split_once(':') {
+                Some((name, format)) => {
+                    let width = format
+                        .strip_prefix('0')
+                        .and_then(|width| width.parse::<usize>().ok())
+                        .ok_or_else(|| format!("invalid format {{{}}}, numbers are padded like {{frame:03}}", field))?;
+                    (name, width)
+                }
+                None => (field, 0),
+            };
+            let placeholder = match name {
+                "stem" => Placeholder::Stem,
+                "frame" => Placeholder::Frame,
+                "row" => Placeholder::Row,
+                "col" => Placeholder::Col,
+                "ext" => Placeholder::Ext,
+                _ => {
+                    return Err(format!(
+                        "unknown placeholder {{{}}}, expected stem, frame, row, col or ext",
+                        name
+                    ))
+                }
+            };
+            if width > 0 && matches!(placeholder, Placeholder::Stem | Placeholder::Ext) {
+                return Err(format!("{{{}}} isn't a number and can't be padded", name));
+            }
+            parts.push(Part::Placeholder(placeholder, width));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+
+        if parts.is_empty() {
+            return Err("can't be empty".to_string());
+        }
+        Ok(OutputTemplate { parts })
+    }
+
+    pub fn uses(&self, placeholder: Placeholder) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Placeholder(used, _) if *used == placeholder))
+    }
+
+    /// Names an output next to the given one. Numbers that aren't known are 1: every input has a
+    /// first page and a print that isn't split is its own first part.
+    fn render(&self, output: &Path, frame: Option<u32>, part: Option<(u32, u32)>) -> PathBuf {
+        let (row, col) = part.unwrap_or((1, 1));
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => name.push_str(text),
+                Part::Placeholder(Placeholder::Stem, _) => name.push_str(&output.file_stem().unwrap_or_default().to_string_lossy()),
+                Part::Placeholder(Placeholder::Ext, _) => name.push_str(&output.extension().unwrap_or_default().to_string_lossy()),
+                Part::Placeholder(Placeholder::Frame, width) => name.push_str(&format!("{:0width$}", frame.unwrap_or(1))),
+                Part::Placeholder(Placeholder::Row, width) => name.push_str(&format!("{:0width$}", row)),
+                Part::Placeholder(Placeholder::Col, width) => name.push_str(&format!("{:0width$}", col)),
+            }
+        }
+        output.with_file_name(name)
+    }
+}
+
+/// Adds a suffix to the file name of a path, in front of its extension.
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!("{0}{1}.{2}", stem, suffix, extension.to_string_lossy())),
+        None => path.with_file_name(format!("{0}{1}", stem, suffix)),
+    }
+}
+
+/// Names the output of a page and part of a print. Without a template the page is added as
+/// `_p{frame}` and the part as `_r{row}_c{col}`, and an output that is neither stays as it is.
+pub fn output_name(output: &Path, template: Option<&OutputTemplate>, frame: Option<u32>, part: Option<(u32, u32)>) -> PathBuf {
+    if let Some(template) = template {
+        return template.render(output, frame, part);
+    }
+
+    let mut suffix = String::new();
+    if let Some(frame) = frame {
+        suffix.push_str(&format!("_p{}", frame));
+    }
+    if let Some((row, col)) = part {
+        suffix.push_str(&format!("_r{0}_c{1}", row, col));
+    }
+    with_suffix(output, &suffix)
+}
+
+/// Refuses outputs that would be written to the same path, each given with what it is the
+/// output of. Every collision is warned about before failing.
+pub fn check_unique(outputs: &[(String, PathBuf)]) -> Result<(), Error> {
+    let mut first_of: HashMap<&Path, &str> = HashMap::new();
+    let mut collided = false;
+    for (what, output) in outputs {
+        match first_of.get(output.as_path()) {
+            Some(first) => {
+                warn!("{0} and {1} would both be written to {2}", first, what, output.display());
+                collided = true;
+            }
+            None => {
+                first_of.insert(output, what);
+            }
+        }
+    }
+
+    if collided {
+        return Err(Error::validation(
+            "Outputs would overwrite each other, use an output template that tells them apart",
+        ));
+    }
+    Ok(())
+}
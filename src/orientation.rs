@@ -0,0 +1,227 @@
+use image::DynamicImage;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const EXIF_ORIENTATION_TAG: u16 = 0x0112;
+
+/// Reads the EXIF orientation (1-8) of a JPEG or PNG file. Files without one, or with an EXIF
+/// block that can't be read, are left as they are.
+pub fn read_orientation(file: &Path) -> Option<u8> {
+    let mut reader = BufReader::new(File::open(file).ok()?);
+    let mut signature = [0u8; 8];
+    reader.read_exact(&mut signature[..2]).ok()?;
+
+    let exif = if signature[..2] == [0xFF, 0xD8] {
+        jpeg_exif(&mut reader)?
+    } else {
+        reader.read_exact(&mut signature[2..]).ok()?;
+        if signature != *b"\x89PNG\r\n\x1a\n" {
+            return None;
+        }
+        png_exif(&mut reader)?
+    };
+
+    exif_orientation(&exif).filter(|orientation| (1..=8).contains(orientation))
+}
+
+/// Finds the EXIF block in the APP1 segments of a JPEG, stopping at the image data.
+fn jpeg_exif<R: Read + Seek>(reader: &mut R) -> Option<Vec<u8>> {
+    let mut header = [0u8; 4];
+    loop {
+        reader.read_exact(&mut header).ok()?;
+        if header[0] != 0xFF || header[1] == 0xDA || header[1] == 0xD9 {
+            return None;
+        }
+
+        let length = u16::from_be_bytes([header[2], header[3]]).checked_sub(2)? as usize;
+        if header[1] == 0xE1 {
+            let mut segment = vec![0u8; length];
+            reader.read_exact(&mut segment).ok()?;
+            if let Some(exif) = segment.strip_prefix(b"Exif\0\0") {
+                return Some(exif.to_vec());
+            }
+        } else {
+            reader.seek(SeekFrom::Current(length as i64)).ok()?;
+        }
+    }
+}
+
+/// Finds the eXIf chunk of a PNG.
+fn png_exif<R: Read + Seek>(reader: &mut R) -> Option<Vec<u8>> {
+    let mut header = [0u8; 8];
+    loop {
+        reader.read_exact(&mut header).ok()?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        match &header[4..] {
+            b"eXIf" => {
+                let mut chunk = vec![0u8; length];
+                reader.read_exact(&mut chunk).ok()?;
+                return Some(chunk);
+            }
+            b"IEND" => return None,
+            _ => {
+                reader.seek(SeekFrom::Current(length as i64 + 4)).ok()?;
+            }
+        }
+    }
+}
+
+/// Reads the orientation tag from the first IFD of a TIFF structured EXIF block.
+fn exif_orientation(exif: &[u8]) -> Option<u8> {
+    let big_endian = match exif.get(..4)? {
+        b"MM\0*" => true,
+        b"II*\0" => false,
+        _ => return None,
+    };
+    let read_u16 = |offset: usize| {
+        let bytes = [*exif.get(offset)?, *exif.get(offset + 1)?];
+        Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    };
+    let read_u32 = |offset: usize| {
+        let bytes = [*exif.get(offset)?, *exif.get(offset + 1)?, *exif.get(offset + 2)?, *exif.get(offset + 3)?];
+        Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    };
+
+    let ifd = read_u32(4)? as usize;
+    let entries = read_u16(ifd)? as usize;
+    (0..entries)
+        .map(|entry| ifd + 2 + entry * 12)
+        .find(|entry| read_u16(*entry) == Some(EXIF_ORIENTATION_TAG))
+        .and_then(|entry| read_u16(entry + 8))
+        .map(|orientation| orientation as u8)
+}
+
+/// Rotates and flips an image so it is displayed upright, for EXIF orientations 2-8.
+pub fn apply_orientation(image: DynamicImage, orientation: u8) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    /// TIFF structured EXIF block with a Make tag and then the orientation tag.
+    fn exif(orientation: u16, big_endian: bool) -> Vec<u8> {
+        let u16_bytes = |value: u16| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        let u32_bytes = |value: u32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+
+        let mut exif = if big_endian { b"MM\0*".to_vec() } else { b"II*\0".to_vec() };
+        exif.extend(u32_bytes(8));
+        exif.extend(u16_bytes(2));
+        // Make, ASCII, 4 bytes ("img" and its terminator) stored in the entry
+        exif.extend(u16_bytes(0x010F));
+        exif.extend(u16_bytes(2));
+        exif.extend(u32_bytes(4));
+        exif.extend(b"img\0");
+        // Orientation, SHORT, 1 value, padded to 4 bytes
+        exif.extend(u16_bytes(EXIF_ORIENTATION_TAG));
+        exif.extend(u16_bytes(3));
+        exif.extend(u32_bytes(1));
+        exif.extend(u16_bytes(orientation));
+        exif.extend([0, 0]);
+        exif.extend(u32_bytes(0));
+        exif
+    }
+
+    /// JPEG header with a JFIF segment and the EXIF block, up to the start of the image data.
+    fn jpeg(exif: &[u8]) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend([0xFF, 0xE0, 0x00, 0x10]);
+        jpeg.extend(b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        jpeg.extend([0xFF, 0xE1]);
+        jpeg.extend((exif.len() as u16 + 8).to_be_bytes());
+        jpeg.extend(b"Exif\0\0");
+        jpeg.extend(exif);
+        jpeg.extend([0xFF, 0xDA, 0x00, 0x02]);
+        jpeg
+    }
+
+    /// PNG chunks with the EXIF block in an eXIf chunk after the header (checksums aren't read).
+    fn png(exif: &[u8]) -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut chunk = |kind: &[u8], data: &[u8]| {
+            png.extend((data.len() as u32).to_be_bytes());
+            png.extend(kind);
+            png.extend(data);
+            png.extend([0, 0, 0, 0]);
+        };
+        chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 0]);
+        chunk(b"eXIf", exif);
+        chunk(b"IEND", &[]);
+        png
+    }
+
+    /// Reads the orientation of a file with the given contents.
+    fn orientation_of(contents: &[u8], name: &str) -> Option<u8> {
+        let file: PathBuf = std::env::temp_dir().join(format!("img2poster-orientation-{0}-{1}", process::id(), name));
+        fs::write(&file, contents).unwrap();
+        let orientation = read_orientation(&file);
+        fs::remove_file(&file).unwrap();
+        orientation
+    }
+
+    #[test]
+    fn reads_every_orientation_from_jpeg_and_png() {
+        for orientation in 1..=8 {
+            for big_endian in [false, true] {
+                let exif = exif(orientation, big_endian);
+                let name = format!("{0}-{1}", orientation, big_endian);
+                assert_eq!(orientation_of(&jpeg(&exif), &format!("{}.jpg", name)), Some(orientation as u8));
+                assert_eq!(orientation_of(&png(&exif), &format!("{}.png", name)), Some(orientation as u8));
+            }
+        }
+    }
+
+    #[test]
+    fn ignores_invalid_or_missing_orientations() {
+        assert_eq!(orientation_of(&jpeg(&exif(0, false)), "zero.jpg"), None);
+        assert_eq!(orientation_of(&jpeg(&exif(9, true)), "nine.jpg"), None);
+        assert_eq!(orientation_of(&jpeg(b"not exif"), "garbage.jpg"), None);
+        assert_eq!(orientation_of(&[0xFF, 0xD8, 0xFF, 0xDA, 0x00, 0x02], "none.jpg"), None);
+        assert_eq!(orientation_of(b"GIF89a", "other.gif"), None);
+    }
+
+    #[test]
+    fn applies_every_orientation() {
+        // a 3x2 image with every pixel different, as stored in the file
+        const WIDTH: u32 = 3;
+        const HEIGHT: u32 = 2;
+        let stored = RgbaImage::from_fn(WIDTH, HEIGHT, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+
+        // where pixel x, y of the upright image is stored, per the EXIF specification
+        let stored_position = |orientation: u8, x: u32, y: u32| match orientation {
+            1 => (x, y),
+            2 => (WIDTH - 1 - x, y),
+            3 => (WIDTH - 1 - x, HEIGHT - 1 - y),
+            4 => (x, HEIGHT - 1 - y),
+            5 => (y, x),
+            6 => (y, HEIGHT - 1 - x),
+            7 => (WIDTH - 1 - y, HEIGHT - 1 - x),
+            _ => (WIDTH - 1 - y, x),
+        };
+        for orientation in 1..=8 {
+            // 5-8 turn the image on its side
+            let (upright_width, upright_height) = if orientation >= 5 { (HEIGHT, WIDTH) } else { (WIDTH, HEIGHT) };
+            let expected = RgbaImage::from_fn(upright_width, upright_height, |x, y| {
+                let (x, y) = stored_position(orientation, x, y);
+                *stored.get_pixel(x, y)
+            });
+            let actual = apply_orientation(DynamicImage::ImageRgba8(stored.clone()), orientation).to_rgba8();
+            assert!(actual == expected, "orientation {} isn't upright", orientation);
+        }
+    }
+}
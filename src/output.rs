@@ -0,0 +1,70 @@
+use crate::stats::InkReport;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Turns progress lines and notes off (or back on) for the rest of the run.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Prints a progress line to stdout, unless output is quiet.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Prints the start of a progress line to stdout, unless output is quiet.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! status_start {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            print!($($arg)*);
+        }
+    };
+}
+
+/// Prints a note to stderr, unless output is quiet.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! note {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub use crate::{note, status, status_start};
+
+/// Summary of a conversion, printed as a single line of JSON with the json flag.
+#[derive(Serialize)]
+pub struct Summary {
+    pub input: String,
+    pub output: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+    pub poster_count: usize,
+    pub palette_size: usize,
+    pub elapsed_ms: u128,
+    /// Only with ink-report
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ink: Option<InkReport>,
+}
+
+pub fn print_summary(summary: &Summary) {
+    println!("{}", serde_json::to_string(summary).unwrap());
+}
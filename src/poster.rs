@@ -1,6 +1,21 @@
-use exoquant::{convert_to_indexed, ditherer, optimizer, Color};
+use crate::binary;
+use crate::error::Error;
+use crate::indexed::IndexedImage;
+use crate::quantizer;
+#[cfg(feature = "simd")]
+use crate::simd;
+use exoquant::{
+    optimizer, sort_palette, Color, ColorMap, ColorSpace, Colorf, SimpleColorSpace,
+};
 use image::{DynamicImage, ImageBuffer, Pixel, Rgba};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
 
 #[derive(Serialize, Deserialize)]
 pub struct PosterTooltip {
@@ -11,9 +26,12 @@ pub struct PosterTooltip {
     pub pos_x: u32,
     pub pos_y: u32,
     pub info: String,
+    /// File name of the image the print was made from, left out unless tooltip-source is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
 pub struct Poster {
     pub label: String,
     pub tooltip: String,
@@ -24,7 +42,7 @@ pub struct Poster {
     pub height: u32,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, PartialEq)]
 pub struct PosterArray {
     pub pages: Vec<Poster>,
     pub width: u32,
@@ -32,76 +50,1271 @@ pub struct PosterArray {
     pub title: String
 }
 
+/// Groups the pages that have the same image (palette and pixels, every page being 128x128,
+/// labels and tooltips aside). Only groups of two or more are returned, each sorted and in the order of its first page.
+pub fn duplicate_pages(poster_array: &PosterArray) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut group_of: HashMap<(&[u32], &[u8]), usize> = HashMap::new();
+    for (index, page) in poster_array.pages.iter().enumerate() {
+        let key = (page.palette.as_slice(), page.pixels.as_slice());
+        match group_of.get(&key) {
+            Some(group) => groups[*group].push(index),
+            None => {
+                group_of.insert(key, groups.len());
+                groups.push(vec![index]);
+            }
+        }
+    }
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+/// Splits a poster array into parts of at most `max_width` by `max_height` posters, row by row.
+/// Every part is returned with its row and column.
+pub fn split_poster_array(poster_array: &PosterArray, max_width: u32, max_height: u32) -> Vec<(u32, u32, PosterArray)> {
+    let mut parts = Vec::new();
+
+    for (row, top) in (0..poster_array.height).step_by(max_height as usize).enumerate() {
+        for (column, left) in (0..poster_array.width).step_by(max_width as usize).enumerate() {
+            let width = u32::min(max_width, poster_array.width - left);
+            let height = u32::min(max_height, poster_array.height - top);
+
+            let pages = (top..top + height)
+                .flat_map(|y| (left..left + width).map(move |x| (y * poster_array.width + x) as usize))
+                .map(|index| poster_array.pages[index].clone())
+                .collect();
+
+            parts.push((
+                row as u32,
+                column as u32,
+                PosterArray {
+                    pages,
+                    width,
+                    height,
+                    title: poster_array.title.clone(),
+                },
+            ));
+        }
+    }
+
+    parts
+}
+
+/// Appends the pages of `new` after the pages of `existing`, keeping the title of `existing`.
+///
+/// Without a grid, a single row of posters grows wider, and other arrays keep their width when the
+/// pages still fill whole rows. Any other layout needs an explicit grid of exactly as many posters.
+pub fn append_poster_array(existing: PosterArray, new: PosterArray, grid: Option<(u32, u32)>) -> Result<PosterArray, Error> {
+    if let Some(page) = new.pages.iter().find(|page| (page.width, page.height) != (128, 128)) {
+        return Err(Error::validation(format!(
+            "Can't append a {0}x{1} page, every page has to be 128x128",
+            page.width, page.height
+        )));
+    }
+
+    let page_count = (existing.pages.len() + new.pages.len()) as u32;
+    let (width, height) = match grid {
+        Some((width, height)) if width * height == page_count => (width, height),
+        Some((width, height)) => {
+            return Err(Error::validation(format!(
+                "Grid {0}x{1} doesn't fit the {2} posters after appending",
+                width, height, page_count
+            )));
+        }
+        None if existing.height <= 1 => (page_count, 1),
+        None if page_count.is_multiple_of(existing.width) => (existing.width, page_count / existing.width),
+        None => {
+            return Err(Error::validation(format!(
+                "{0} posters don't fill whole rows of the existing {1}x{2} grid, give a grid for the result",
+                page_count, existing.width, existing.height
+            )));
+        }
+    };
+
+    let mut pages = existing.pages;
+    pages.extend(new.pages);
+    Ok(PosterArray {
+        pages,
+        width,
+        height,
+        title: existing.title,
+    })
+}
+
+/// The poster file formats, each one read and written with the methods of this type.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PosterFormat {
+    /// 2dj, a single poster
+    Poster,
+    /// 2dja, a poster array
+    PosterArray,
+    /// 2djb, a binary poster array
+    Binary,
+    /// 2dji, a poster array as one indexed image
+    Indexed,
+}
+
+impl PosterFormat {
+    pub const ALL: [PosterFormat; 4] = [PosterFormat::Poster, PosterFormat::PosterArray, PosterFormat::Binary, PosterFormat::Indexed];
+
+    /// Looks up the format of a lowercase extension, None if it isn't a poster format.
+    pub fn from_extension(extension: &str) -> Option<PosterFormat> {
+        PosterFormat::ALL.into_iter().find(|format| format.extension() == extension)
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            PosterFormat::Poster => "2dj",
+            PosterFormat::PosterArray => "2dja",
+            PosterFormat::Binary => "2djb",
+            PosterFormat::Indexed => "2dji",
+        }
+    }
+
+    /// Reads a poster file in this format, whatever its own extension is.
+    pub fn read(self, file: &Path) -> Result<PosterArray, Error> {
+        let reader = BufReader::new(
+            File::open(file).map_err(|error| Error::io(format!("Failed to open input file: {}", error)))?,
+        );
+        self.parse(reader)
+    }
+
+    /// Parses posters in this format from any reader, like a downloaded file.
+    pub fn parse<R: Read>(self, reader: R) -> Result<PosterArray, Error> {
+        let poster_array = match self {
+            PosterFormat::Poster => PosterArray {
+                pages: vec![parse_json(&read_bytes(reader)?)?],
+                width: 1,
+                height: 1,
+                title: "untitled".to_string(),
+            },
+            PosterFormat::PosterArray => parse_json(&read_bytes(reader)?)?,
+            PosterFormat::Binary => binary::read_binary(reader)?,
+            PosterFormat::Indexed => parse_json::<IndexedImage>(&read_bytes(reader)?)?.into_poster_array()?,
+        };
+
+        validate_poster_array(&poster_array)?;
+        Ok(poster_array)
+    }
+
+    /// Writes posters in this format. dedup stores posters that repeat an earlier image as
+    /// references, which only 2djb can, the JSON formats ignore it.
+    pub fn write<W: Write>(self, poster_array: &PosterArray, dedup: bool, writer: W) -> Result<(), Error> {
+        let write_error = |error: &dyn std::fmt::Display| Error::io(format!("Failed to write posters: {}", error));
+        match self {
+            PosterFormat::Poster => {
+                if poster_array.pages.len() > 1 {
+                    return Err(Error::validation("Format 2dj doesn't support multi poster images."));
+                }
+                serde_json::to_writer(writer, &poster_array.pages[0]).map_err(|error| write_error(&error))
+            }
+            PosterFormat::PosterArray => serde_json::to_writer(writer, poster_array).map_err(|error| write_error(&error)),
+            PosterFormat::Indexed => serde_json::to_writer(writer, &IndexedImage::from_poster_array(poster_array)?)
+                .map_err(|error| write_error(&error)),
+            PosterFormat::Binary => binary::write_binary(poster_array, dedup, writer).map_err(|error| write_error(&error)),
+        }
+    }
+}
+
+fn read_bytes<R: Read>(mut reader: R) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    reader
+        .read_to_end(&mut bytes)
+        .map_err(|error| Error::io(format!("Failed to read input file: {}", error)))?;
+    Ok(bytes)
+}
+
+/// Reads a poster file in any of the poster formats, depending on its extension.
+pub fn read_poster_array(file: &Path) -> Result<PosterArray, Error> {
+    let extension = file
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    match extension.as_deref().and_then(PosterFormat::from_extension) {
+        Some(format) => format.read(file),
+        None => Err(Error::format(format!("Unsupported poster format: {}", file.display()))),
+    }
+}
+
+/// Bytes of JSON shown on each side of where parsing failed.
+const SNIPPET_CONTEXT: usize = 32;
+
+/// Parses JSON poster input. A file that fails to parse, like a truncated or hand-edited one, is
+/// reported with the byte offset it failed at and the JSON around it, since poster files are
+/// usually a single very long line.
+fn parse_json<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    serde_json::from_slice(bytes).map_err(|error| {
+        // serde_json counts lines and columns from 1, and columns in bytes
+        let line_start: usize = bytes
+            .split(|byte| *byte == b'\n')
+            .take(error.line().saturating_sub(1))
+            .map(|line| line.len() + 1)
+            .sum();
+        let offset = (line_start + error.column().saturating_sub(1)).min(bytes.len());
+
+        let before = String::from_utf8_lossy(&bytes[offset.saturating_sub(SNIPPET_CONTEXT)..offset]).replace(char::is_whitespace, " ");
+        let after = String::from_utf8_lossy(&bytes[offset..(offset + SNIPPET_CONTEXT).min(bytes.len())]).replace(char::is_whitespace, " ");
+        let message = error.to_string();
+        let message = message
+            .strip_suffix(&format!(" at line {} column {}", error.line(), error.column()))
+            .unwrap_or(&message);
+        let end = if error.is_eof() { ", the file ends early" } else { "" };
+
+        Error::decode(format!(
+            "Failed to parse json in input file at byte {0} (line {1}, column {2}){3}: {4}\n  {5}{6}\n  {7}^",
+            offset,
+            error.line(),
+            error.column(),
+            end,
+            message,
+            before,
+            after,
+            " ".repeat(before.chars().count())
+        ))
+    })
+}
+
+/// Checks that the grid size of a poster array matches its pages, and that every page is a
+/// complete 128x128 poster, so hand-edited files fail with specifics instead of a broken preview.
+fn validate_poster_array(poster_array: &PosterArray) -> Result<(), Error> {
+    let expected_pages = poster_array.width as u64 * poster_array.height as u64;
+    if expected_pages != poster_array.pages.len() as u64 {
+        return Err(Error::decode(format!(
+            "Invalid poster array: {0}x{1} posters should have {2} pages, found {3}",
+            poster_array.width,
+            poster_array.height,
+            expected_pages,
+            poster_array.pages.len()
+        )));
+    }
+
+    for (index, page) in poster_array.pages.iter().enumerate() {
+        if page.width != 128 || page.height != 128 {
+            return Err(Error::decode(format!(
+                "Invalid poster array: page {0} is {1}x{2}, expected 128x128",
+                index + 1,
+                page.width,
+                page.height
+            )));
+        }
+        if page.pixels.len() != 128 * 128 {
+            return Err(Error::decode(format!(
+                "Invalid poster array: page {0} has {1} pixels, expected {2}",
+                index + 1,
+                page.pixels.len(),
+                128 * 128
+            )));
+        }
+    }
+
+    Ok(())
+}
+
 fn rgb_to_hex(red: u8, green: u8, blue: u8) -> u32 {
     ((red as u32) << 16) | ((green as u32) << 8) | blue as u32
 }
 
-pub fn posters_to_dynamic_image(poster_array: &PosterArray) -> DynamicImage {
-    let poster_width = poster_array.width;
-    let poster_height = poster_array.height;
-    let mut image_buffer = vec![0u8; (poster_width * 128 * poster_height * 128 * 4) as usize];
+/// Color of a palette index of a poster, index 0 being transparent (no ink).
+pub fn pixel_color(poster: &Poster, palette_index: u8) -> Rgba<u8> {
+    let palette_index = palette_index as usize;
+    if palette_index == 0 {
+        return Rgba([0u8, 0u8, 0u8, 0u8]);
+    }
 
-    for (poster_index, poster) in poster_array.pages.iter().enumerate() {
-        let offset_x = (poster_index as u32 % poster_width) * 128;
-        let offset_y = (poster_index as u32 / poster_width) * 128;
+    if poster.palette.len() < palette_index {
+        panic!("Invalid poster: found color {}, but poster only contains {} palette colors.", palette_index, poster.palette.len());
+    }
+    let color_value = poster.palette[palette_index - 1];
+    Rgba([
+        ((color_value >> 16) & 0xFF) as u8,
+        ((color_value >> 8) & 0xFF) as u8,
+        (color_value & 0xFF) as u8,
+        255u8,
+    ])
+}
 
-        for (pixel_index, pixel) in poster.pixels.iter().enumerate() {
-            let palette_index = *pixel as usize;
-            let color;
+/// Flattens posters into one image. Every row of posters fills its own band of the buffer, so the
+/// rows are drawn on up to `threads` threads without any locking.
+pub fn posters_to_dynamic_image(poster_array: &PosterArray, threads: u32) -> DynamicImage {
+    let poster_width = poster_array.width as usize;
+    let poster_height = poster_array.height as usize;
+    let row_size = poster_width * 128 * 128 * 4;
+    let mut image_buffer = vec![0u8; row_size * poster_height];
 
-            if palette_index == 0 {
-                color = Rgba {
-                    0: [0u8,0u8,0u8,0u8]
-                };
-            } else {
-                if poster.palette.len() < palette_index {
-                    panic!("Invalid poster: found color {}, but poster only contains {} palette colors.", palette_index, poster.palette.len());
+    let draw_row = |poster_y: usize, band: &mut [u8]| {
+        for poster_x in 0..poster_width {
+            let Some(poster) = poster_array.pages.get(poster_y * poster_width + poster_x) else {
+                break;
+            };
+
+            for (pixel_index, pixel) in poster.pixels.iter().enumerate() {
+                let color = pixel_color(poster, *pixel);
+
+                let x = poster_x * 128 + pixel_index % 128;
+                let y = pixel_index / 128;
+                let index = (y * poster_width * 128 + x) * 4;
+
+                band[index..index + 4].copy_from_slice(color.channels());
+            }
+        }
+    };
+    let draw_row = &draw_row;
+
+    let threads = usize::clamp(threads as usize, 1, usize::max(1, poster_height));
+    let rows_per_thread = poster_height.div_ceil(threads);
+    thread::scope(|scope| {
+        for (chunk_index, chunk) in image_buffer.chunks_mut(usize::max(1, row_size * rows_per_thread)).enumerate() {
+            scope.spawn(move || {
+                for (row_index, band) in chunk.chunks_mut(row_size).enumerate() {
+                    draw_row(chunk_index * rows_per_thread + row_index, band);
                 }
-                let color_value = poster.palette[palette_index-1];
-                color = Rgba {
-                    0: [
-                        ((color_value >> 16) & 0xFF) as u8,
-                        ((color_value >> 8) & 0xFF) as u8,
-                        (color_value & 0xFF) as u8,
-                        255u8,
-                    ]
+            });
+        }
+    });
+
+    let image = ImageBuffer::from_raw(poster_array.width * 128, poster_array.height * 128, image_buffer)
+        .expect("Failed to create image buffer");
+
+    DynamicImage::ImageRgba8(image)
+}
+
+/// Writes posters as an RGBA PNG, with every poster pixel upscaled to a pixel_scale block.
+///
+/// Unlike `posters_to_dynamic_image` the image is never held in memory as a whole, it is encoded
+/// one pixel row at a time, so memory use only depends on the width of the print.
+pub fn write_posters_png<W: Write>(poster_array: &PosterArray, pixel_scale: u32, writer: W) -> Result<(), png::EncodingError> {
+    let (width, height) = (poster_array.width * 128, poster_array.height * 128);
+    let mut encoder = png::Encoder::new(writer, width * pixel_scale, height * pixel_scale);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer()?;
+
+    let block_size = pixel_scale as usize * 4;
+    let mut row = vec![0u8; width as usize * block_size];
+    for y in 0..height {
+        for poster_x in 0..poster_array.width {
+            let page = poster_array.pages.get(((y / 128) * poster_array.width + poster_x) as usize);
+
+            for x in 0..128 {
+                let color = match page {
+                    Some(poster) => pixel_color(poster, poster.pixels[((y % 128) * 128 + x) as usize]),
+                    None => Rgba([0u8, 0u8, 0u8, 0u8]),
                 };
+                let start = (poster_x * 128 + x) as usize * block_size;
+                for pixel in row[start..start + block_size].chunks_exact_mut(4) {
+                    pixel.copy_from_slice(color.channels());
+                }
+            }
+        }
+
+        for _ in 0..pixel_scale {
+            stream.write_all(&row)?;
+        }
+    }
+
+    stream.finish()
+}
+
+/// Metric used to pick the nearest palette entry for a pixel.
+#[derive(clap::ValueEnum, Clone, Copy, Default)]
+pub enum ColorDistance {
+    /// Plain euclidean distance in sRGB
+    Rgb,
+    /// Gamma-adjusted RGB with per-channel weights
+    #[default]
+    WeightedRgb,
+    /// Euclidean distance in CIELAB
+    #[value(alias = "lab")]
+    Cie76,
+}
+
+const LAB_WHITE: [f64; 3] = [0.95047, 1.0, 1.08883];
+const LAB_EPSILON: f64 = 216.0 / 24389.0;
+const LAB_KAPPA: f64 = 24389.0 / 27.0;
+
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Converts an sRGB color with channels in 0..1 to CIELAB (D65).
+fn rgb_to_lab(red: f64, green: f64, blue: f64) -> (f64, f64, f64) {
+    let (r, g, b) = (srgb_to_linear(red), srgb_to_linear(green), srgb_to_linear(blue));
+
+    let xyz = [
+        0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+        0.2126729 * r + 0.7151522 * g + 0.0721750 * b,
+        0.0193339 * r + 0.1191920 * g + 0.9503041 * b,
+    ];
+
+    let f = |i: usize| {
+        let t = xyz[i] / LAB_WHITE[i];
+        if t > LAB_EPSILON {
+            t.cbrt()
+        } else {
+            (LAB_KAPPA * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(0), f(1), f(2));
+
+    (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+/// Converts a CIELAB (D65) color back to sRGB with channels in 0..1.
+fn lab_to_rgb(l: f64, a: f64, b: f64) -> (f64, f64, f64) {
+    let fy = (l + 16.0) / 116.0;
+    let fx = fy + a / 500.0;
+    let fz = fy - b / 200.0;
+
+    let f_inv = |t: f64| {
+        if t.powi(3) > LAB_EPSILON {
+            t.powi(3)
+        } else {
+            (116.0 * t - 16.0) / LAB_KAPPA
+        }
+    };
+    let (x, y, z) = (
+        f_inv(fx) * LAB_WHITE[0],
+        f_inv(fy) * LAB_WHITE[1],
+        f_inv(fz) * LAB_WHITE[2],
+    );
+
+    (
+        linear_to_srgb(3.2404542 * x - 1.5371385 * y - 0.4985314 * z),
+        linear_to_srgb(-0.9692660 * x + 1.8760108 * y + 0.0415560 * z),
+        linear_to_srgb(0.0556434 * x - 0.2040259 * y + 1.0572252 * z),
+    )
+}
+
+impl ColorDistance {
+    fn simple_color_space(self) -> SimpleColorSpace {
+        match self {
+            ColorDistance::Rgb => SimpleColorSpace {
+                gamma: 1.0,
+                dither_gamma: 1.0,
+                scale: Colorf {
+                    r: 1.0,
+                    g: 1.0,
+                    b: 1.0,
+                    a: 0.75,
+                },
+                ..SimpleColorSpace::default()
+            },
+            _ => SimpleColorSpace::default(),
+        }
+    }
+}
+
+/// Each metric is euclidean distance after mapping colors into its own colorspace, so
+/// `to_float` is the distance computation the quantizer and ditherer minimize.
+impl ColorSpace for ColorDistance {
+    fn to_linear(&self, color: Colorf) -> Colorf {
+        match self {
+            ColorDistance::Cie76 => {
+                let (l, a, b) = rgb_to_lab(color.r, color.g, color.b);
+                Colorf {
+                    r: l / 100.0,
+                    g: a / 100.0,
+                    b: b / 100.0,
+                    a: color.a,
+                }
             }
+            _ => self.simple_color_space().to_linear(color),
+        }
+    }
 
-            let x = offset_x + (pixel_index as u32 % 128);
-            let y = offset_y + (pixel_index as u32 / 128);
-            let index = ((y * (poster_width * 128) + x) * 4) as usize;
+    fn from_linear(&self, color: Colorf) -> Colorf {
+        match self {
+            ColorDistance::Cie76 => {
+                let (r, g, b) = lab_to_rgb(color.r * 100.0, color.g * 100.0, color.b * 100.0);
+                Colorf { r, g, b, a: color.a }
+            }
+            _ => self.simple_color_space().from_linear(color),
+        }
+    }
 
-            image_buffer[index..index + 4].copy_from_slice(&color.channels());
+    fn to_dither(&self, color: Colorf) -> Colorf {
+        match self {
+            ColorDistance::Cie76 => color,
+            _ => self.simple_color_space().to_dither(color),
         }
     }
 
-    let image = ImageBuffer::from_raw(poster_width * 128, poster_height * 128, image_buffer)
-        .expect("Failed to create image buffer");
+    fn from_dither(&self, color: Colorf) -> Colorf {
+        match self {
+            ColorDistance::Cie76 => color,
+            _ => self.simple_color_space().from_dither(color),
+        }
+    }
+}
 
-    return DynamicImage::ImageRgba8(image)
+/// How the palette is derived from the image colors.
+#[derive(Clone)]
+pub enum PaletteGeneration {
+    /// Median cut with K-Means refinement in the remapping colorspace, using up to the given number
+    /// of colors
+    MedianCut { colors: usize },
+    /// Seeded k-means clustering in CIELAB with the given number of colors
+    KMeans { colors: usize, seed: u64 },
+    /// Exactly the given colors, taken from a reference image
+    Fixed(Arc<[Color]>),
 }
 
-pub fn dither(image_data: Vec<Color>, width: usize) -> (Vec<u8>, Vec<u32>) {
-    let image_data_slice: &[Color] = &image_data;
-    let (palette, indexed_data) = convert_to_indexed(
-        image_data_slice,
-        width,
-        63,
-        &optimizer::KMeans,
-        &ditherer::FloydSteinberg::new(),
+impl PaletteGeneration {
+    /// The same palette generation with at most `colors` colors. A fixed palette keeps the colors
+    /// a poster quantized with it (`pixels` and `palette`) used most.
+    pub fn capped(&self, colors: usize, pixels: &[u8], palette: &[u32]) -> PaletteGeneration {
+        match *self {
+            PaletteGeneration::MedianCut { colors: uncapped } => PaletteGeneration::MedianCut {
+                colors: usize::min(uncapped, colors),
+            },
+            PaletteGeneration::KMeans { colors: uncapped, seed } => PaletteGeneration::KMeans {
+                colors: usize::min(uncapped, colors),
+                seed,
+            },
+            PaletteGeneration::Fixed(_) => {
+                let mut counts = vec![0usize; palette.len() + 1];
+                for pixel in pixels {
+                    counts[*pixel as usize] += 1;
+                }
+                let mut order: Vec<usize> = (0..palette.len()).collect();
+                // stable, so equally used colors keep their palette order
+                order.sort_by_key(|index| std::cmp::Reverse(counts[index + 1]));
+                let kept = order
+                    .into_iter()
+                    .take(colors)
+                    .map(|index| {
+                        let color = palette[index];
+                        Color::new((color >> 16) as u8, (color >> 8) as u8, color as u8, 255)
+                    })
+                    .collect();
+                PaletteGeneration::Fixed(kept)
+            }
+        }
+    }
+}
+
+/// Collects the distinct opaque colors of an image, in the order they first appear.
+pub fn palette_from_image(image: &DynamicImage) -> Vec<Color> {
+    let mut seen = HashSet::new();
+    image
+        .to_rgba8()
+        .pixels()
+        .filter(|pixel| pixel[3] != 0)
+        .filter(|pixel| seen.insert((pixel[0], pixel[1], pixel[2])))
+        .map(|pixel| Color::new(pixel[0], pixel[1], pixel[2], 255))
+        .collect()
+}
+
+/// Moves every pixel within tolerance of a palette color on each channel exactly onto it, onto the
+/// closest one if several are. Alpha is kept.
+fn snap_to_palette(image_data: &[Color], palette: &[Color], tolerance: u8) -> Vec<Color> {
+    image_data
+        .iter()
+        .map(|color| {
+            let distance = |entry: &&Color| {
+                u8::max(
+                    color.r.abs_diff(entry.r),
+                    u8::max(color.g.abs_diff(entry.g), color.b.abs_diff(entry.b)),
+                )
+            };
+            match palette.iter().filter(|entry| distance(entry) <= tolerance).min_by_key(distance) {
+                Some(entry) => Color::new(entry.r, entry.g, entry.b, color.a),
+                None => *color,
+            }
+        })
+        .collect()
+}
+
+/// Cost multipliers of palette colors, to prefer cheaper colors when remapping. A palette entry
+/// is chosen by its distance plus a penalty of (weight - 1) times the threshold, so a color with
+/// weight 0.5 still wins against an unweighted one up to half the threshold farther away.
+/// Colors without a weight have weight 1 and no penalty.
+pub struct PaletteWeights {
+    weights: HashMap<u32, f64>,
+    /// Levels of gray (0-255) the penalty of a weight of 2 is worth
+    threshold: u8,
+}
+
+impl PaletteWeights {
+    /// Weights keyed by color (0xRRGGBB).
+    pub fn new(weights: HashMap<u32, f64>, threshold: u8) -> Self {
+        PaletteWeights { weights, threshold }
+    }
+
+    /// Penalty of every palette entry in the distances of the colorspace, `None` if all of them
+    /// are 0 and the distances are unchanged. The threshold is measured from mid gray.
+    fn penalties<C: ColorSpace>(&self, palette: &[Color], colorspace: &C) -> Option<Vec<f64>> {
+        let gray = |level: u8| colorspace.to_float(Color::new(level, level, level, 255));
+        let difference = gray(128 + self.threshold / 2) - gray(128 - self.threshold.div_ceil(2));
+        let threshold = difference.dot(&difference).sqrt();
+
+        let penalties: Vec<f64> = palette
+            .iter()
+            .map(|color| {
+                let weight = *self.weights.get(&rgb_to_hex(color.r, color.g, color.b)).unwrap_or(&1.0);
+                (weight - 1.0) * threshold
+            })
+            .collect();
+        penalties.iter().any(|penalty| *penalty != 0.0).then_some(penalties)
+    }
+}
+
+/// Index of the palette color with the lowest distance to `color` plus its penalty, the lowest
+/// index on a tie.
+fn find_weighted_nearest(map: &ColorMap, color: Colorf, penalties: &[f64]) -> usize {
+    let mut nearest = (0, f64::INFINITY);
+    for (index, penalty) in penalties.iter().enumerate() {
+        let difference = color - map.float_color(index);
+        let distance = difference.dot(&difference).sqrt() + penalty;
+        if distance < nearest.1 {
+            nearest = (index, distance);
+        }
+    }
+    nearest.0
+}
+
+/// Most dithered colors the nearest palette entry is remembered for in one remap, about 5MB.
+const NEAREST_CACHE_ENTRIES: usize = 1 << 16;
+
+/// Index of the palette color nearest to `color`. The kd-tree returns whichever of equally near
+/// colors it visits first, so on a tie the lowest palette index wins instead, the same on every
+/// machine. The simd feature replaces it with `simd::LanePalette`.
+#[cfg(any(test, not(feature = "simd")))]
+fn find_nearest(map: &ColorMap, color: Colorf) -> usize {
+    let distance = |index: usize| {
+        let difference = color - map.float_color(index);
+        difference.dot(&difference).sqrt()
+    };
+    let nearest = map.find_nearest(color);
+    let nearest_distance = distance(nearest);
+    (0..nearest)
+        .find(|index| distance(*index) == nearest_distance)
+        .unwrap_or(nearest)
+}
+
+/// Dithers like exoquant's `FloydSteinberg::new()`, with ties going to the lowest palette index.
+///
+/// With `cache`, it remembers what every source color converts to and which palette entry every
+/// dithered color is nearest to. Wherever no error is spread into a pixel, as in the flat areas of
+/// pixel art, the same colors come up again and the search is skipped. The cache only lives for
+/// this palette, and doesn't change the result.
+///
+/// With `only_exact`, pixels that already are a palette color keep it: no error is spread into
+/// them, and they spread none to their neighbours. With `weights`, the nearest palette entry is
+/// the one with the lowest distance plus penalty.
+fn remap<C: ColorSpace>(image_data: &[Color], width: usize, palette: &[Color], colorspace: &C, cache: bool, only_exact: bool, weights: Option<&PaletteWeights>) -> Vec<u8> {
+    const RIGHT: f64 = 7.0 / 16.0;
+    const BELOW_LEFT: f64 = 3.0 / 16.0;
+    const BELOW: f64 = 5.0 / 16.0;
+    const BELOW_RIGHT: f64 = 1.0 / 16.0;
+    const ERROR_DAMPING: f64 = 0.8;
+
+    let map = ColorMap::new(palette, colorspace);
+    // with the simd feature, four palette colors are compared at once instead of searching the kd-tree
+    #[cfg(feature = "simd")]
+    let lanes = simd::LanePalette::new(&map);
+    #[cfg(feature = "simd")]
+    let find_nearest = |_: &ColorMap, color: Colorf| lanes.find_nearest(color);
+    let mut source_colors: HashMap<Color, Colorf> = HashMap::new();
+    let mut nearest: HashMap<[u64; 4], usize> = HashMap::new();
+    let mut errors = vec![Colorf::zero(); width * 2];
+    let penalties = weights.and_then(|weights| weights.penalties(palette, colorspace));
+    let mut exact: HashMap<(u8, u8, u8), usize> = HashMap::new();
+    if only_exact {
+        // the first of equal palette colors, like find_nearest
+        for (index, color) in palette.iter().enumerate().rev() {
+            exact.insert((color.r, color.g, color.b), index);
+        }
+    }
+
+    image_data
+        .iter()
+        .enumerate()
+        .map(|(i, color)| {
+            let x = i % width;
+            let row = ((i / width) & 1) * width;
+            let other = (((i / width) & 1) ^ 1) * width;
+
+            let (index, error) = match exact.get(&(color.r, color.g, color.b)) {
+                Some(index) => (*index, Colorf::zero()),
+                None => {
+                    let c = if cache {
+                        *source_colors
+                            .entry(*color)
+                            .or_insert_with(|| colorspace.to_dither(colorspace.to_float(*color)))
+                    } else {
+                        colorspace.to_dither(colorspace.to_float(*color))
+                    };
+                    let needle = colorspace.from_dither(c + errors[row + x]);
+                    let key = [needle.r.to_bits(), needle.g.to_bits(), needle.b.to_bits(), needle.a.to_bits()];
+                    let index = match nearest.get(&key) {
+                        Some(index) => *index,
+                        None => {
+                            let index = match penalties {
+                                Some(ref penalties) => find_weighted_nearest(&map, needle, penalties),
+                                None => find_nearest(&map, needle),
+                            };
+                            if cache && nearest.len() < NEAREST_CACHE_ENTRIES {
+                                nearest.insert(key, index);
+                            }
+                            index
+                        }
+                    };
+
+                    (index, c + errors[row + x] * ERROR_DAMPING - colorspace.to_dither(map.float_color(index)))
+                }
+            };
+
+            errors[row + (x + 1) % width] += error * RIGHT;
+            errors[other + (x + 1) % width] = error * BELOW_RIGHT;
+            errors[other + x] += error * BELOW;
+            errors[other + (x + width - 1) % width] += error * BELOW_LEFT;
+            index as u8
+        })
+        .collect()
+}
+
+fn quantize<C: ColorSpace>(histogram_data: &[Color], image_data: &[Color], width: usize, colorspace: &C, palette_generation: &PaletteGeneration, search: PaletteSearch) -> (Vec<Color>, Vec<u8>) {
+    let palette = match *palette_generation {
+        PaletteGeneration::MedianCut { colors } => {
+            let histogram = quantizer::color_counts(histogram_data, colorspace);
+
+            let palette = quantizer::generate_palette(&histogram, colorspace, &optimizer::KMeans, colors);
+            quantizer::optimize_palette(colorspace, &palette, &histogram, &optimizer::KMeans, 8)
+        }
+        PaletteGeneration::KMeans { colors, seed } => {
+            let histogram = quantizer::color_counts(histogram_data, &ColorDistance::Cie76);
+            quantizer::kmeans_palette(&histogram, &ColorDistance::Cie76, colors, seed)
+        }
+        PaletteGeneration::Fixed(ref colors) => colors.to_vec(),
+    };
+
+    let snapped;
+    let image_data = if search.snap_tolerance > 0 {
+        snapped = snap_to_palette(image_data, &palette, search.snap_tolerance);
+        &snapped
+    } else {
+        image_data
+    };
+
+    let indexed_data = remap(image_data, width, &palette, colorspace, search.distance_cache, search.only_exact, search.weights);
+
+    sort_palette(&palette, &indexed_data)
+}
+
+/// How pixels are mapped onto the palette once it is known, see `dither`.
+#[derive(Clone, Copy, Default)]
+pub struct PaletteSearch<'a> {
+    /// Pixels within this distance of a palette color on every channel are snapped onto it before
+    /// dithering, 0 leaves them as they are
+    pub snap_tolerance: u8,
+    /// Pixels that exactly are a palette color (snapped or not) are kept as they are, only the rest
+    /// is dithered
+    pub only_exact: bool,
+    /// Remembers the nearest palette entry of colors that repeat, which is faster on flat images
+    /// and gives the same result
+    pub distance_cache: bool,
+    /// Makes palette colors cheaper or dearer to pick, see `PaletteWeights`
+    pub weights: Option<&'a PaletteWeights>,
+}
+
+/// Quantizes and dithers pixels to a palette of up to 63 colors, generated from the opaque pixels.
+///
+/// Pixels with an alpha of 0 are left out of the palette and stored as index 0 (no ink). They are
+/// still dithered as if they were opaque, so their error spreads into the neighbouring pixels.
+/// `search` is how pixels are mapped onto the palette, see `PaletteSearch`.
+pub fn dither(image_data: Vec<Color>, width: usize, color_distance: ColorDistance, palette_generation: &PaletteGeneration, search: PaletteSearch) -> (Vec<u8>, Vec<u32>) {
+    let opaque_data: Vec<Color> = image_data.iter().filter(|color| color.a != 0).cloned().collect();
+    if opaque_data.is_empty() {
+        return (vec![0; image_data.len()], Vec::new());
+    }
+
+    let (palette, indexed_data) = if opaque_data.len() == image_data.len() {
+        quantize(&image_data, &image_data, width, &color_distance, palette_generation, search)
+    } else {
+        let opaque_image_data: Vec<Color> = image_data
+            .iter()
+            .map(|color| Color::new(color.r, color.g, color.b, 255))
+            .collect();
+        quantize(&opaque_data, &opaque_image_data, width, &color_distance, palette_generation, search)
+    };
+
+    let colors: Vec<u32> = palette
+        .iter()
+        .map(|color| rgb_to_hex(color.r, color.g, color.b))
+        .collect();
+
+    let ret_indexed_data = indexed_data
+        .iter()
+        .zip(image_data.iter())
+        .map(|(index, color)| if color.a == 0 { 0 } else { index + 1 })
+        .collect();
+
+    (ret_indexed_data, colors)
+}
+
+/// Hue of a color in degrees (0-360), 0 for grays.
+fn hue(color: u32) -> f64 {
+    let (red, green, blue) = (
+        ((color >> 16) & 0xFF) as f64,
+        ((color >> 8) & 0xFF) as f64,
+        (color & 0xFF) as f64,
     );
+    let max = red.max(green).max(blue);
+    let delta = max - red.min(green).min(blue);
+    if delta == 0.0 {
+        return 0.0;
+    }
+
+    let hue = if max == red {
+        (green - blue) / delta
+    } else if max == green {
+        (blue - red) / delta + 2.0
+    } else {
+        (red - green) / delta + 4.0
+    };
+    (hue * 60.0).rem_euclid(360.0)
+}
+
+/// Orders the palette of a poster by luminance, then hue (and the color value for exact ties),
+/// and remaps the pixel indices to match. The same colors always end up in the same order.
+pub fn sort_poster_palette(poster: &mut Poster) {
+    let luminance = |color: u32| 2126 * ((color >> 16) & 0xFF) + 7152 * ((color >> 8) & 0xFF) + 722 * (color & 0xFF);
+
+    let mut order: Vec<usize> = (0..poster.palette.len()).collect();
+    order.sort_by(|a, b| {
+        let (a, b) = (poster.palette[*a], poster.palette[*b]);
+        luminance(a)
+            .cmp(&luminance(b))
+            .then(hue(a).total_cmp(&hue(b)))
+            .then(a.cmp(&b))
+    });
+
+    let mut remapped_indices = vec![0u8; poster.palette.len() + 1];
+    for (new_index, old_index) in order.iter().enumerate() {
+        remapped_indices[old_index + 1] = new_index as u8 + 1;
+    }
 
+    poster.palette = order.iter().map(|index| poster.palette[*index]).collect();
+    for pixel in poster.pixels.iter_mut() {
+        *pixel = remapped_indices[*pixel as usize];
+    }
+}
+
+/// Reduces a palette to the colors a poster actually uses, keeping their relative order,
+/// and remaps the pixel indices to match.
+pub fn subset_palette(pixels: &[u8], palette: &[u32]) -> (Vec<u8>, Vec<u32>) {
+    let mut used = vec![false; palette.len() + 1];
+    for pixel in pixels {
+        used[*pixel as usize] = true;
+    }
+
+    let mut remapped_indices = vec![0u8; palette.len() + 1];
     let mut colors: Vec<u32> = Vec::new();
-    for i in 0..palette.len() {
-        let color = palette[i];
-        colors.push(rgb_to_hex(color.r, color.g, color.b));
+    for (index, color) in palette.iter().enumerate() {
+        if used[index + 1] {
+            colors.push(*color);
+            remapped_indices[index + 1] = colors.len() as u8;
+        }
     }
 
-    let mut ret_indexed_data = Vec::new();
-    for i in 0..indexed_data.len() {
-        ret_indexed_data.push(indexed_data[i] + 1)
+    let remapped_pixels = pixels
+        .iter()
+        .map(|pixel| remapped_indices[*pixel as usize])
+        .collect();
+
+    (remapped_pixels, colors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Distance of two colors under a metric, the way remapping measures it.
+    fn distance(metric: ColorDistance, a: Color, b: Color) -> f64 {
+        let difference = metric.to_float(a) - metric.to_float(b);
+        difference.dot(&difference).sqrt()
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() <= tolerance, "{} isn't within {} of {}", actual, tolerance, expected);
+    }
+
+    /// sRGB colors and their CIELAB (D65) values.
+    const LAB_REFERENCES: [([u8; 3], [f64; 3]); 6] = [
+        ([0, 0, 0], [0.0, 0.0, 0.0]),
+        ([255, 255, 255], [100.0, 0.0, 0.0]),
+        ([255, 0, 0], [53.2408, 80.0925, 67.2032]),
+        ([0, 255, 0], [87.7347, -86.1827, 83.1793]),
+        ([0, 0, 255], [32.2970, 79.1875, -107.8602]),
+        ([128, 128, 128], [53.5850, 0.0, 0.0]),
+    ];
+
+    #[test]
+    fn rgb_to_lab_matches_references() {
+        for ([r, g, b], [l, a, lab_b]) in LAB_REFERENCES {
+            let lab = rgb_to_lab(r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+            assert_close(lab.0, l, 0.01);
+            assert_close(lab.1, a, 0.01);
+            assert_close(lab.2, lab_b, 0.01);
+        }
+    }
+
+    #[test]
+    fn lab_to_rgb_reverses_rgb_to_lab() {
+        for ([r, g, b], [l, a, lab_b]) in LAB_REFERENCES {
+            let rgb = lab_to_rgb(l, a, lab_b);
+            assert_close(rgb.0 * 255.0, r as f64, 0.05);
+            assert_close(rgb.1 * 255.0, g as f64, 0.05);
+            assert_close(rgb.2 * 255.0, b as f64, 0.05);
+        }
+    }
+
+    #[test]
+    fn cie76_is_lab_delta_e() {
+        // CIE76 of every reference pair, in the colorspace's unit of 100 delta E
+        for ([r1, g1, b1], lab1) in LAB_REFERENCES {
+            for ([r2, g2, b2], lab2) in LAB_REFERENCES {
+                let delta_e = ((lab1[0] - lab2[0]).powi(2) + (lab1[1] - lab2[1]).powi(2) + (lab1[2] - lab2[2]).powi(2)).sqrt();
+                let actual = distance(ColorDistance::Cie76, Color::new(r1, g1, b1, 255), Color::new(r2, g2, b2, 255));
+                assert_close(actual * 100.0, delta_e, 0.02);
+            }
+        }
+    }
+
+    /// Scale of the RGB channels of an opaque color in the simple colorspaces: alpha is weighted
+    /// by 0.75 and colors are scaled by their weighted alpha, plus 1% (exoquant's transparency scale).
+    const OPAQUE_SCALE: f64 = 0.75 * 0.99 + 0.01;
+
+    #[test]
+    fn rgb_is_euclidean_in_srgb() {
+        let distance = |a: [u8; 3], b: [u8; 3]| {
+            distance(ColorDistance::Rgb, Color::new(a[0], a[1], a[2], 255), Color::new(b[0], b[1], b[2], 255)) / OPAQUE_SCALE
+        };
+        assert_close(distance([0, 0, 0], [255, 255, 255]), 3f64.sqrt(), 1e-9);
+        assert_close(distance([0, 0, 0], [255, 0, 0]), 1.0, 1e-9);
+        assert_close(distance([0, 0, 0], [51, 0, 0]), 0.2, 1e-9);
+        assert_close(distance([10, 20, 30], [13, 24, 30]), 5.0 / 255.0, 1e-9);
     }
 
-    return (ret_indexed_data, colors);
+    #[test]
+    fn weighted_rgb_weights_green_over_blue() {
+        let distance = |a: [u8; 3], b: [u8; 3]| {
+            distance(ColorDistance::WeightedRgb, Color::new(a[0], a[1], a[2], 255), Color::new(b[0], b[1], b[2], 255)) / OPAQUE_SCALE
+        };
+        // full channels don't feel the gamma, only the weights of 1, 1.2 and 0.8
+        assert_close(distance([0, 0, 0], [255, 0, 0]), 1.0, 1e-9);
+        assert_close(distance([0, 0, 0], [0, 255, 0]), 1.2, 1e-9);
+        assert_close(distance([0, 0, 0], [0, 0, 255]), 0.8, 1e-9);
+        assert_close(distance([0, 0, 0], [255, 255, 255]), 3.08f64.sqrt(), 1e-9);
+        // other values are raised to gamma 1.145 first
+        assert_close(distance([0, 0, 0], [128, 0, 0]), (128.0f64 / 255.0).powf(1.145), 1e-9);
+    }
+
+    #[test]
+    fn metric_decides_the_nearest_color() {
+        // gray 119 is nearer to black in sRGB, but a bit lighter than L 50 so nearer to white in CIELAB
+        let palette = [Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255)];
+        let pixel = Color::new(119, 119, 119, 255);
+        let nearest = |metric: ColorDistance| {
+            let map = exoquant::ColorMap::new(&palette, &metric);
+            map.find_nearest(metric.to_float(pixel))
+        };
+        assert_eq!(nearest(ColorDistance::Rgb), 0);
+        assert_eq!(nearest(ColorDistance::Cie76), 1);
+    }
+
+    /// A 16x16 poster whose pixels use every color of the palette, and transparency.
+    fn poster_with_palette(palette: Vec<u32>) -> Poster {
+        let pixels = (0..256).map(|index| (index * 7 % (palette.len() + 1)) as u8).collect();
+        Poster {
+            label: String::new(),
+            tooltip: String::new(),
+            palette,
+            pixels,
+            width: 16,
+            height: 16,
+        }
+    }
+
+    /// Colors of the pixels of a poster, `None` for transparent ones.
+    fn pixel_colors(poster: &Poster) -> Vec<Option<u32>> {
+        poster
+            .pixels
+            .iter()
+            .map(|pixel| pixel.checked_sub(1).map(|index| poster.palette[index as usize]))
+            .collect()
+    }
+
+    #[test]
+    fn sorted_palette_doesnt_depend_on_color_order() {
+        use rand::rngs::StdRng;
+        use rand::seq::SliceRandom;
+        use rand::SeedableRng;
+
+        // grays, primaries, and three colors with the same luminance that only differ in hue
+        let palette = vec![
+            0x000000, 0xFFFFFF, 0x808080, 0xFF0000, 0x00FF00, 0x0000FF, 0x000AAA, 0x0E1700, 0x1100DB, 0x7F7F7F, 0x123456, 0xFEDCBA,
+        ];
+        let mut expected = poster_with_palette(palette.clone());
+        let colors = pixel_colors(&expected);
+        sort_poster_palette(&mut expected);
+        assert_eq!(pixel_colors(&expected), colors);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20 {
+            // the same pixels with the palette shuffled and the indices remapped to match
+            let mut order: Vec<usize> = (0..palette.len()).collect();
+            order.shuffle(&mut rng);
+            let mut shuffled = poster_with_palette(order.iter().map(|index| palette[*index]).collect());
+            shuffled.pixels = pixels_for(&colors, &shuffled.palette);
+
+            sort_poster_palette(&mut shuffled);
+            assert!(shuffled == expected, "sorting palette order {:?} gave another poster", order);
+        }
+    }
+
+    /// Pixel indices that give the colors with a palette.
+    fn pixels_for(colors: &[Option<u32>], palette: &[u32]) -> Vec<u8> {
+        colors
+            .iter()
+            .map(|color| match color {
+                Some(color) => palette.iter().position(|entry| entry == color).unwrap() as u8 + 1,
+                None => 0,
+            })
+            .collect()
+    }
+
+    /// A print of random 128x128 posters, each with its own palette of up to 8 colors.
+    fn random_poster_array(width: u32, height: u32, seed: u64) -> PosterArray {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let pages = (0..width * height)
+            .map(|_| {
+                let palette: Vec<u32> = (0..rng.gen_range(1..=8)).map(|_| rng.gen_range(0..=0xFFFFFF)).collect();
+                let pixels = (0..128 * 128).map(|_| rng.gen_range(0..=palette.len()) as u8).collect();
+                Poster {
+                    label: String::new(),
+                    tooltip: String::new(),
+                    palette,
+                    pixels,
+                    width: 128,
+                    height: 128,
+                }
+            })
+            .collect();
+        PosterArray {
+            pages,
+            width,
+            height,
+            title: String::new(),
+        }
+    }
+
+    #[test]
+    fn flattening_on_threads_matches_serial() {
+        for (width, height) in [(1, 1), (3, 5), (4, 2)] {
+            let poster_array = random_poster_array(width, height, (width * 10 + height) as u64);
+
+            // serial reference, every page drawn pixel by pixel into its place
+            let mut expected = image::RgbaImage::new(width * 128, height * 128);
+            for (page_index, page) in poster_array.pages.iter().enumerate() {
+                let (left, top) = (page_index as u32 % width * 128, page_index as u32 / width * 128);
+                for (pixel_index, pixel) in page.pixels.iter().enumerate() {
+                    let (x, y) = (left + pixel_index as u32 % 128, top + pixel_index as u32 / 128);
+                    expected.put_pixel(x, y, pixel_color(page, *pixel));
+                }
+            }
+
+            for threads in [0, 1, 2, 3, 4, 8, 64] {
+                let actual = posters_to_dynamic_image(&poster_array, threads).to_rgba8();
+                assert!(actual == expected, "{}x{} posters on {} threads differ from serial", width, height, threads);
+            }
+        }
+    }
+
+    #[test]
+    fn equidistant_pixel_goes_to_lowest_palette_index() {
+        // 0,5,5 is exactly as far from 0,0,10 as from 0,10,0 in every channel weighting that treats
+        // green and blue the same, so in plain RGB
+        let pixel = Color::new(0, 5, 5, 255);
+        let (blue, green) = (Color::new(0, 0, 10, 255), Color::new(0, 10, 0, 255));
+        let far: Vec<Color> = (1..=12).map(|value| Color::new(value * 20, 255, value * 20, 255)).collect();
+
+        for (palette, expected) in [
+            (vec![blue, green], 0),
+            (vec![green, blue], 0),
+            (vec![green, green], 0),
+            ([&far[..6], &[green], &far[6..], &[blue]].concat(), 6),
+            ([&far[..3], &[blue], &far[3..9], &[green], &far[9..]].concat(), 3),
+        ] {
+            let map = ColorMap::new(&palette, &ColorDistance::Rgb);
+            let needle = ColorDistance::Rgb.to_float(pixel);
+            assert_eq!(find_nearest(&map, needle), expected);
+            assert_eq!(find_weighted_nearest(&map, needle, &vec![0.0; palette.len()]), expected);
+            for cache in [false, true] {
+                let remapped = remap(&[pixel, pixel], 2, &palette, &ColorDistance::Rgb, cache, false, None);
+                assert_eq!(remapped[0] as usize, expected);
+            }
+        }
+    }
+
+    /// Color of every pixel of dithered output, `None` for transparent ones.
+    fn dithered_colors(dithered: &(Vec<u8>, Vec<u32>)) -> Vec<Option<u32>> {
+        let (pixels, palette) = dithered;
+        pixels.iter().map(|pixel| pixel.checked_sub(1).map(|index| palette[index as usize])).collect()
+    }
+
+    #[test]
+    fn only_exact_keeps_exact_palette_pixels() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let palette = [Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255), Color::new(200, 40, 40, 255), Color::new(40, 40, 200, 255)];
+        let palette_generation = PaletteGeneration::Fixed(palette.to_vec().into());
+
+        // every third pixel is exactly a palette color, the rest are random colors off the palette
+        let mut rng = StdRng::seed_from_u64(5);
+        let image: Vec<Color> = (0..32 * 32)
+            .map(|index| {
+                if index % 3 == 0 {
+                    palette[index / 3 % palette.len()]
+                } else {
+                    Color::new(rng.gen_range(60..190), rng.gen_range(60..190), rng.gen_range(60..190), 255)
+                }
+            })
+            .collect();
+        let exact_pixels = || image.iter().enumerate().filter(|(index, _)| index % 3 == 0);
+
+        let kept = dither(image.clone(), 32, ColorDistance::default(), &palette_generation, PaletteSearch { only_exact: true, ..Default::default() });
+        let kept_colors = dithered_colors(&kept);
+        for (index, color) in exact_pixels() {
+            assert_eq!(kept_colors[index], Some(rgb_to_hex(color.r, color.g, color.b)), "exact pixel {} changed", index);
+        }
+
+        // plain dithering spreads error into some of them, and the off-palette pixels still get
+        // dithered with only exact
+        let dithered_colors = dithered_colors(&dither(image.clone(), 32, ColorDistance::default(), &palette_generation, PaletteSearch::default()));
+        assert!(exact_pixels().any(|(index, color)| dithered_colors[index] != Some(rgb_to_hex(color.r, color.g, color.b))));
+        let used: HashSet<Option<u32>> = kept_colors.iter().enumerate().filter(|(index, _)| index % 3 != 0).map(|(_, color)| *color).collect();
+        assert!(used.len() > 1);
+    }
+
+    #[test]
+    fn only_exact_keeps_snapped_pixels_and_leaves_transparency() {
+        let palette = [Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255)];
+        let palette_generation = PaletteGeneration::Fixed(palette.to_vec().into());
+        let image = vec![
+            Color::new(253, 254, 255, 255),
+            Color::new(128, 128, 128, 255),
+            Color::new(2, 1, 0, 255),
+            Color::new(0, 0, 0, 0),
+            Color::new(255, 255, 255, 255),
+            Color::new(127, 127, 127, 255),
+        ];
+
+        let kept = dithered_colors(&dither(image, 3, ColorDistance::default(), &palette_generation, PaletteSearch { snap_tolerance: 2, only_exact: true, ..Default::default() }));
+        assert_eq!(kept[0], Some(0xFFFFFF));
+        assert_eq!(kept[2], Some(0x000000));
+        assert_eq!(kept[3], None);
+        assert_eq!(kept[4], Some(0xFFFFFF));
+    }
+
+    /// Nearest palette color by checking every entry in order, the lowest index on a tie. This is
+    /// the correctness oracle for the nearest color searches.
+    fn find_nearest_scalar(map: &ColorMap, color: Colorf) -> usize {
+        let mut nearest = (0, f64::INFINITY);
+        for index in 0..map.num_colors() {
+            let difference = color - map.float_color(index);
+            let distance = difference.dot(&difference);
+            if distance < nearest.1 {
+                nearest = (index, distance);
+            }
+        }
+        nearest.0
+    }
+
+    /// Checks a nearest color search against the scalar oracle, on random palettes of 1 to 63
+    /// colors (with duplicates) and random colors, under every metric.
+    fn assert_matches_scalar(search: impl Fn(&ColorMap, Colorf) -> usize) {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(9);
+        let random_color = |rng: &mut StdRng| Color::new(rng.gen(), rng.gen(), rng.gen(), 255);
+        for metric in [ColorDistance::Rgb, ColorDistance::WeightedRgb, ColorDistance::Cie76] {
+            for size in [1, 2, 3, 8, 16, 63] {
+                let mut palette: Vec<Color> = (0..size).map(|_| random_color(&mut rng)).collect();
+                if size > 2 {
+                    palette[size - 1] = palette[size / 2];
+                }
+                let map = ColorMap::new(&palette, &metric);
+
+                for _ in 0..500 {
+                    // random colors, palette colors and colors pushed out of range by dither error
+                    let color = match rng.gen_range(0..3) {
+                        0 => metric.to_float(random_color(&mut rng)),
+                        1 => map.float_color(rng.gen_range(0..size)),
+                        _ => metric.to_float(random_color(&mut rng)) * rng.gen_range(-0.5..1.5),
+                    };
+                    assert_eq!(search(&map, color), find_nearest_scalar(&map, color), "{} colors, {:?}", size, [color.r, color.g, color.b]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_color_search_matches_scalar() {
+        assert_matches_scalar(find_nearest);
+    }
+
+    #[test]
+    fn weighted_search_without_penalties_matches_scalar() {
+        assert_matches_scalar(|map, color| find_weighted_nearest(map, color, &vec![0.0; map.num_colors()]));
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn simd_search_matches_scalar() {
+        assert_matches_scalar(|map, color| simd::LanePalette::new(map).find_nearest(color));
+    }
+
+    #[test]
+    fn every_poster_format_reads_back_what_it_writes() {
+        let poster = |color: u32| Poster {
+            label: String::new(),
+            tooltip: String::new(),
+            palette: vec![color],
+            pixels: vec![1; 128 * 128],
+            width: 128,
+            height: 128,
+        };
+        let single = PosterArray { pages: vec![poster(0xff0000)], width: 1, height: 1, title: "untitled".to_string() };
+        let pair = PosterArray { pages: vec![poster(0xff0000), poster(0x00ff00)], width: 2, height: 1, title: "untitled".to_string() };
+
+        for format in PosterFormat::ALL {
+            assert!(PosterFormat::from_extension(format.extension()) == Some(format));
+            let poster_array = if format == PosterFormat::Poster { &single } else { &pair };
+            let mut bytes = Vec::new();
+            assert!(format.write(poster_array, false, &mut bytes).is_ok(), "{:?} failed to write", format);
+            assert!(format.parse(bytes.as_slice()).ok().as_ref() == Some(poster_array), "{:?} read back other posters", format);
+        }
+        assert!(PosterFormat::Poster.write(&pair, false, Vec::new()).is_err());
+    }
 }
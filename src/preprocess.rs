@@ -0,0 +1,530 @@
+use crate::output::status;
+use image::{DynamicImage, Rgba, RgbaImage};
+
+/// Reduces images with more than 8 bits per channel to 8 bit RGBA before quantization.
+///
+/// Channels are rounded to the nearest 8 bit value, `round(c * 255 / 65535)` for 16 bit images
+/// and `round(clamp(c, 0, 1) * 255)` for floating point ones.
+pub fn reduce_bit_depth(image: DynamicImage) -> DynamicImage {
+    let color = image.color();
+    let bits_per_channel = color.bits_per_pixel() / color.channel_count() as u16;
+    if bits_per_channel <= 8 {
+        return image;
+    }
+
+    status!(
+        "Reducing {0}-bit image to 8 bits per channel (rounding to nearest)",
+        bits_per_channel
+    );
+    DynamicImage::ImageRgba8(image.to_rgba8())
+}
+
+/// Alpha-blends every pixel onto a background color. An opaque background leaves a fully opaque
+/// image.
+pub fn flatten_alpha(image: DynamicImage, background: Rgba<u8>) -> DynamicImage {
+    let mut image = image.into_rgba8();
+    let background_alpha = background[3] as u32;
+
+    for pixel in image.pixels_mut() {
+        let alpha = pixel[3] as u32;
+        // the resulting alpha times 255
+        let coverage = alpha * 255 + background_alpha * (255 - alpha);
+        if coverage == 0 {
+            pixel.0 = [0, 0, 0, 0];
+            continue;
+        }
+        for channel in 0..3 {
+            pixel[channel] = ((pixel[channel] as u32 * alpha * 255
+                + background[channel] as u32 * background_alpha * (255 - alpha)
+                + coverage / 2)
+                / coverage) as u8;
+        }
+        pixel[3] = ((coverage + 127) / 255) as u8;
+    }
+
+    DynamicImage::ImageRgba8(image)
+}
+
+/// Makes the pixels of a key color transparent, like a green or magenta sprite background. Colors
+/// within tolerance of the key on every channel become fully transparent. Up to twice the
+/// tolerance they become partly transparent, with the key color taken out of them, so anti-aliased
+/// edges blend into whatever is behind them instead of keeping a fringe of the key. The alpha of
+/// the key is ignored.
+pub fn chroma_key(image: DynamicImage, key: Rgba<u8>, tolerance: u8) -> DynamicImage {
+    let mut image = image.into_rgba8();
+    let tolerance = tolerance as f64;
+
+    for pixel in image.pixels_mut() {
+        let distance = (0..3)
+            .map(|channel| pixel[channel].abs_diff(key[channel]))
+            .max()
+            .unwrap() as f64;
+        if distance <= tolerance {
+            pixel.0 = [0, 0, 0, 0];
+        } else if distance < tolerance * 2.0 {
+            // the pixel is taken to be the foreground mixed with the key, coverage of it visible
+            let coverage = (distance - tolerance) / tolerance;
+            for channel in 0..3 {
+                let foreground = key[channel] as f64 + (pixel[channel] as f64 - key[channel] as f64) / coverage;
+                pixel[channel] = foreground.round().clamp(0.0, 255.0) as u8;
+            }
+            pixel[3] = (pixel[3] as f64 * coverage).round() as u8;
+        }
+    }
+
+    DynamicImage::ImageRgba8(image)
+}
+
+/// Pixels removed from every side of an image by `trim_borders`.
+pub struct Trimmed {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+/// Crops away margins of the color of the top left pixel, matching colors within tolerance on
+/// every channel (alpha included). An image that is a single color all over is left as it is, and
+/// `None` returned.
+pub fn trim_borders(image: DynamicImage, tolerance: u8) -> (DynamicImage, Option<Trimmed>) {
+    let rgba = image.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let border = *rgba.get_pixel(0, 0);
+    let is_border = |x: u32, y: u32| {
+        let pixel = rgba.get_pixel(x, y);
+        (0..4).all(|channel| pixel[channel].abs_diff(border[channel]) <= tolerance)
+    };
+    let row_is_border = |y: u32| (0..width).all(|x| is_border(x, y));
+    let column_is_border = |x: u32, rows: std::ops::Range<u32>| rows.clone().all(|y| is_border(x, y));
+
+    let Some(top) = (0..height).find(|y| !row_is_border(*y)) else {
+        return (image, None);
+    };
+    let bottom = (0..height).rev().find(|y| !row_is_border(*y)).unwrap();
+    let left = (0..width).find(|x| !column_is_border(*x, top..bottom + 1)).unwrap();
+    let right = (0..width).rev().find(|x| !column_is_border(*x, top..bottom + 1)).unwrap();
+
+    let trimmed = Trimmed {
+        top,
+        bottom: height - 1 - bottom,
+        left,
+        right: width - 1 - right,
+    };
+    (image.crop_imm(left, top, right - left + 1, bottom - top + 1), Some(trimmed))
+}
+
+/// How the block of source pixels under every pixel of a downsampled image is combined into it.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum DownsampleMode {
+    /// Mean of the block, weighted by alpha so transparent pixels don't darken it
+    Average,
+    /// Most frequent color of the block, which keeps the exact colors of pixel art
+    Mode,
+    /// Median of every channel of the block
+    Median,
+}
+
+/// Scales an image to `width` by `height` by combining the block of source pixels every output
+/// pixel covers, instead of filtering. When enlarging, every block is the single nearest pixel.
+pub fn downsample(image: &DynamicImage, width: u32, height: u32, mode: DownsampleMode) -> DynamicImage {
+    let source = image.to_rgba8();
+    let (source_width, source_height) = source.dimensions();
+    let span = |index: u32, size: u32, source_size: u32| {
+        let start = (index as u64 * source_size as u64 / size as u64) as u32;
+        let end = ((index as u64 + 1) * source_size as u64 / size as u64) as u32;
+        start..u32::max(end, start + 1)
+    };
+
+    let mut block: Vec<[u8; 4]> = Vec::new();
+    let downsampled = RgbaImage::from_fn(width, height, |x, y| {
+        block.clear();
+        for source_y in span(y, height, source_height) {
+            for source_x in span(x, width, source_width) {
+                block.push(source.get_pixel(source_x, source_y).0);
+            }
+        }
+        image::Rgba(combine_block(&mut block, mode))
+    });
+
+    DynamicImage::ImageRgba8(downsampled)
+}
+
+fn combine_block(block: &mut [[u8; 4]], mode: DownsampleMode) -> [u8; 4] {
+    match mode {
+        DownsampleMode::Average => {
+            let alpha: u64 = block.iter().map(|pixel| pixel[3] as u64).sum();
+            let mut combined = [0u8; 4];
+            for (channel, value) in combined.iter_mut().take(3).enumerate() {
+                let weighted: u64 = block.iter().map(|pixel| pixel[channel] as u64 * pixel[3] as u64).sum();
+                *value = (weighted + alpha / 2).checked_div(alpha).unwrap_or(0) as u8;
+            }
+            let count = block.len() as u64;
+            combined[3] = ((alpha + count / 2) / count) as u8;
+            combined
+        }
+        DownsampleMode::Mode => {
+            // sorted, so equal colors are next to each other and ties go to the lowest color
+            block.sort_unstable();
+            let mut best = (block[0], 0);
+            let mut run = (block[0], 0);
+            for pixel in block.iter() {
+                run = if *pixel == run.0 { (run.0, run.1 + 1) } else { (*pixel, 1) };
+                if run.1 > best.1 {
+                    best = run;
+                }
+            }
+            best.0
+        }
+        DownsampleMode::Median => {
+            let mut combined = [0u8; 4];
+            let mut values: Vec<u8> = Vec::with_capacity(block.len());
+            for (channel, value) in combined.iter_mut().enumerate() {
+                values.clear();
+                values.extend(block.iter().map(|pixel| pixel[channel]));
+                values.sort_unstable();
+                *value = values[values.len() / 2];
+            }
+            combined
+        }
+    }
+}
+
+/// Standard deviation in pixels of the blur the unsharp mask of sharpen compares against.
+const SHARPEN_SIGMA: f32 = 1.0;
+
+/// Sharpens an image with an unsharp mask: every color channel moves away from a slightly blurred
+/// copy of the image by `amount` times the difference, which brings back edges softened by
+/// resizing. Alpha is kept as it is, and an amount of 0 leaves the image untouched.
+pub fn sharpen(image: DynamicImage, amount: f64) -> DynamicImage {
+    if amount == 0.0 {
+        return image;
+    }
+
+    let mut image = image.into_rgba8();
+    let blurred = image::imageops::blur(&image, SHARPEN_SIGMA);
+    for (pixel, blurred) in image.pixels_mut().zip(blurred.pixels()) {
+        for channel in 0..3 {
+            let value = pixel[channel] as f64;
+            let sharpened = value + amount * (value - blurred[channel] as f64);
+            pixel[channel] = sharpened.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    DynamicImage::ImageRgba8(image)
+}
+
+/// Curve that rolls highlights off in linear light instead of clipping them to white.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum Tonemap {
+    /// Extended Reinhard, which leaves images without overbright values unchanged
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve, which also adds contrast to the midtones
+    Aces,
+}
+
+impl Tonemap {
+    /// Maps linear light from 0 to white onto 0 to 1.
+    fn apply(self, value: f64, white: f64) -> f64 {
+        let aces = |value: f64| (value * (2.51 * value + 0.03)) / (value * (2.43 * value + 0.59) + 0.14);
+        match self {
+            Tonemap::Reinhard => value * (1.0 + value / (white * white)) / (1.0 + value),
+            Tonemap::Aces => aces(value) / aces(white),
+        }
+    }
+}
+
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Scales the saturation (HSV) of every pixel, keeping hue and value. Saturation is capped at 1.
+fn adjust_saturation(pixel: &mut [u8], factor: f64) {
+    let channels = [pixel[0] as f64, pixel[1] as f64, pixel[2] as f64];
+    let max = channels.iter().cloned().fold(0.0, f64::max);
+    let min = channels.iter().cloned().fold(255.0, f64::min);
+    if max == min {
+        return;
+    }
+
+    let factor = f64::min(factor, max / (max - min));
+    for channel in 0..3 {
+        pixel[channel] = (max - (max - channels[channel]) * factor).round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+/// Rotates the hue (HSV) of a pixel by a number of degrees, keeping saturation and value.
+fn rotate_hue(pixel: &mut [u8], degrees: f64) {
+    let channels = [pixel[0] as f64, pixel[1] as f64, pixel[2] as f64];
+    let max = channels.iter().cloned().fold(0.0, f64::max);
+    let min = channels.iter().cloned().fold(255.0, f64::min);
+    let delta = max - min;
+    if delta == 0.0 {
+        return;
+    }
+
+    let hue = if max == channels[0] {
+        (channels[1] - channels[2]) / delta
+    } else if max == channels[1] {
+        (channels[2] - channels[0]) / delta + 2.0
+    } else {
+        (channels[0] - channels[1]) / delta + 4.0
+    };
+    let hue = (hue + degrees / 60.0).rem_euclid(6.0);
+
+    let x = delta * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (red, green, blue) = match hue as u32 {
+        0 => (delta, x, 0.0),
+        1 => (x, delta, 0.0),
+        2 => (0.0, delta, x),
+        3 => (0.0, x, delta),
+        4 => (x, 0.0, delta),
+        _ => (delta, 0.0, x),
+    };
+    for (channel, value) in [red, green, blue].into_iter().enumerate() {
+        pixel[channel] = (value + min).round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+/// Replaces one color with another, matching every color within tolerance of it on each channel,
+/// alpha included.
+#[derive(Clone, Copy, Debug)]
+pub struct ColorReplacement {
+    pub from: Rgba<u8>,
+    pub to: Rgba<u8>,
+    pub tolerance: u8,
+}
+
+impl ColorReplacement {
+    fn matches(&self, pixel: &[u8]) -> bool {
+        (0..4).all(|channel| pixel[channel].abs_diff(self.from[channel]) <= self.tolerance)
+    }
+}
+
+/// Color adjustments applied to an image before quantization.
+pub struct ColorAdjustments {
+    pub replace_colors: Vec<ColorReplacement>,
+    pub grayscale: bool,
+    pub hue_rotate: f64,
+    pub saturation: f64,
+    pub brightness: f64,
+    pub tonemap: Option<Tonemap>,
+    pub contrast: f64,
+    pub invert: bool,
+    pub posterize: Option<u8>,
+}
+
+impl ColorAdjustments {
+    fn is_neutral(&self) -> bool {
+        self.replace_colors.is_empty()
+            && !self.grayscale
+            && self.hue_rotate == 0.0
+            && self.saturation == 1.0
+            && self.brightness == 1.0
+            && self.tonemap.is_none()
+            && self.contrast == 1.0
+            && !self.invert
+            && self.posterize.is_none()
+    }
+}
+
+/// Applies the color adjustments in the order replace colors, grayscale, hue rotation, saturation,
+/// brightness, tone mapping, contrast, invert, posterize.
+///
+/// Color replacements are tried in the order given and only the first match is applied. Grayscale
+/// replaces every pixel with its luma (Rec. 709), hue rotation turns the HSV hue, saturation scales
+/// the HSV saturation,
+/// brightness multiplies the linear light intensity, tone mapping compresses it from 0 up to the
+/// brightest value brightness can give onto 0 to 1 instead of clipping it, contrast scales the distance of every sRGB
+/// channel from mid gray, invert flips every channel and posterize rounds every channel to the
+/// nearest of N evenly spaced levels. Neutral adjustments leave the image untouched.
+pub fn adjust_colors(image: DynamicImage, adjustments: &ColorAdjustments) -> DynamicImage {
+    if adjustments.is_neutral() {
+        return image;
+    }
+
+    let mut lookup = [0u8; 256];
+    for (value, entry) in lookup.iter_mut().enumerate() {
+        let mut value = value as f64 / 255.0;
+        if adjustments.brightness != 1.0 || adjustments.tonemap.is_some() {
+            let mut linear = srgb_to_linear(value) * adjustments.brightness;
+            if let Some(tonemap) = adjustments.tonemap {
+                linear = tonemap.apply(linear, f64::max(adjustments.brightness, 1.0));
+            }
+            value = linear_to_srgb(linear.clamp(0.0, 1.0));
+        }
+        if adjustments.contrast != 1.0 {
+            value = ((value - 0.5) * adjustments.contrast + 0.5).clamp(0.0, 1.0);
+        }
+        if adjustments.invert {
+            value = 1.0 - value;
+        }
+        if let Some(levels) = adjustments.posterize {
+            let steps = (levels - 1) as f64;
+            value = (value * steps).round() / steps;
+        }
+        *entry = (value * 255.0).round() as u8;
+    }
+
+    let mut image = image.into_rgba8();
+    for pixel in image.pixels_mut() {
+        if let Some(replacement) = adjustments.replace_colors.iter().find(|replacement| replacement.matches(&pixel.0)) {
+            pixel.0 = replacement.to.0;
+        }
+        if adjustments.grayscale {
+            let luma = ((2126 * pixel[0] as u32 + 7152 * pixel[1] as u32 + 722 * pixel[2] as u32 + 5000) / 10000) as u8;
+            pixel[0] = luma;
+            pixel[1] = luma;
+            pixel[2] = luma;
+        }
+        if adjustments.hue_rotate != 0.0 {
+            rotate_hue(&mut pixel.0, adjustments.hue_rotate);
+        }
+        if adjustments.saturation != 1.0 {
+            adjust_saturation(&mut pixel.0, adjustments.saturation);
+        }
+        for channel in 0..3 {
+            pixel[channel] = lookup[pixel[channel] as usize];
+        }
+    }
+
+    DynamicImage::ImageRgba8(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, ImageOutputFormat, Rgba, RgbaImage};
+    use std::io::Cursor;
+
+    fn neutral_adjustments() -> ColorAdjustments {
+        ColorAdjustments {
+            replace_colors: Vec::new(),
+            grayscale: false,
+            hue_rotate: 0.0,
+            saturation: 1.0,
+            brightness: 1.0,
+            tonemap: None,
+            contrast: 1.0,
+            invert: false,
+            posterize: None,
+        }
+    }
+
+    /// Every color with channels in steps of 17, with alpha in steps of 85.
+    fn color_cube() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+            let index = y * 64 + x;
+            Rgba([
+                (index % 16 * 17) as u8,
+                (index / 16 % 16 * 17) as u8,
+                (index / 256 * 17) as u8,
+                (index % 4 * 85) as u8,
+            ])
+        }))
+    }
+
+    #[test]
+    fn neutral_adjustments_leave_pixels_unchanged() {
+        let image = color_cube();
+        assert!(adjust_colors(image.clone(), &neutral_adjustments()) == image);
+    }
+
+    #[test]
+    fn neutral_values_leave_channels_unchanged_when_adjusting() {
+        // invert alone takes the adjusting path, where the neutral values must not touch a channel
+        let image = color_cube();
+        let inverted = adjust_colors(
+            image.clone(),
+            &ColorAdjustments {
+                invert: true,
+                ..neutral_adjustments()
+            },
+        );
+
+        let mut expected = image.to_rgba8();
+        for pixel in expected.pixels_mut() {
+            for channel in 0..3 {
+                pixel[channel] = 255 - pixel[channel];
+            }
+        }
+        assert!(inverted.to_rgba8() == expected);
+    }
+
+    #[test]
+    fn neutral_saturation_keeps_every_pixel() {
+        for pixel in color_cube().to_rgba8().pixels() {
+            let mut adjusted = pixel.0;
+            adjust_saturation(&mut adjusted, 1.0);
+            assert_eq!(adjusted, pixel.0);
+        }
+    }
+
+    #[test]
+    fn sharpen_zero_is_a_no_op() {
+        let image = color_cube();
+        assert!(sharpen(image.clone(), 0.0) == image);
+        assert!(sharpen(image.clone(), -0.0) == image);
+
+        // not even converted to RGBA
+        let rgb = DynamicImage::ImageRgb8(ImageBuffer::from_fn(8, 8, |x, y| image::Rgb([x as u8 * 30, y as u8 * 30, 0])));
+        assert!(sharpen(rgb.clone(), 0.0) == rgb);
+    }
+
+    #[test]
+    fn sharpen_keeps_flat_areas_and_alpha() {
+        let flat = DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 16, Rgba([90, 120, 200, 77])));
+        assert!(sharpen(flat.clone(), 1.5) == flat);
+
+        // an edge from dark to light gets darker on the dark side and lighter on the light one
+        let edge = RgbaImage::from_fn(16, 4, |x, _| if x < 8 { Rgba([64, 64, 64, 200]) } else { Rgba([192, 192, 192, 200]) });
+        let sharpened = sharpen(DynamicImage::ImageRgba8(edge), 1.0).into_rgba8();
+        assert!(sharpened.get_pixel(7, 1)[0] < 64);
+        assert!(sharpened.get_pixel(8, 1)[0] > 192);
+        assert!(sharpened.pixels().all(|pixel| pixel[3] == 200));
+    }
+
+    #[test]
+    fn sixteen_bit_gradient_rounds_to_nearest() {
+        // every 16 bit value from 0 to 65535 in steps of 64, plus the last one, with alpha falling
+        // as red rises
+        let values: Vec<u16> = (0..=65535u32).step_by(64).chain([65535]).map(|value| value as u16).collect();
+        let gradient: ImageBuffer<Rgba<u16>, Vec<u16>> = ImageBuffer::from_fn(values.len() as u32, 1, |x, _| {
+            let value = values[x as usize];
+            Rgba([value, value / 2, 65535 - value, 65535 - value])
+        });
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba16(gradient)
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+            .unwrap();
+
+        let decoded = image::load_from_memory(&png).unwrap();
+        assert!(matches!(decoded, DynamicImage::ImageRgba16(_)));
+        let reduced = reduce_bit_depth(decoded);
+        let DynamicImage::ImageRgba8(reduced) = reduced else {
+            panic!("reduced image isn't 8 bit RGBA");
+        };
+
+        let round = |value: u16| ((value as u32 * 255 + 65535 / 2) / 65535) as u8;
+        for (x, value) in values.iter().enumerate() {
+            let expected = [round(*value), round(value / 2), round(65535 - value), round(65535 - value)];
+            assert_eq!(reduced.get_pixel(x as u32, 0).0, expected, "16 bit value {}", value);
+        }
+    }
+
+    #[test]
+    fn eight_bit_images_stay_as_they_are() {
+        let image = DynamicImage::ImageRgb8(ImageBuffer::from_fn(4, 4, |x, y| image::Rgb([x as u8 * 60, y as u8 * 60, 7])));
+        assert!(reduce_bit_depth(image.clone()) == image);
+    }
+}
@@ -0,0 +1,271 @@
+use exoquant::optimizer::Optimizer;
+use exoquant::{Color, ColorCount, ColorMap, ColorSpace, Colorf};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Counts the occurrences of every color, sorted by color.
+///
+/// exoquant keeps its histogram in a `HashMap`, so the order colors are visited in (and with it
+/// the palette) changes between runs. Working on a sorted histogram instead makes the same input
+/// always produce the same palette.
+pub fn color_counts<C: ColorSpace>(data: &[Color], colorspace: &C) -> Vec<ColorCount> {
+    let mut colors = data.to_vec();
+    colors.sort_unstable_by_key(|color| (color.r, color.g, color.b, color.a));
+
+    let mut counts: Vec<ColorCount> = Vec::new();
+    let mut previous: Option<Color> = None;
+    for color in colors {
+        if previous == Some(color) {
+            counts.last_mut().unwrap().count += 1;
+        } else {
+            counts.push(ColorCount {
+                color: colorspace.to_float(color),
+                count: 1,
+            });
+            previous = Some(color);
+        }
+    }
+
+    counts
+}
+
+struct QuantizerNode {
+    histogram: Vec<ColorCount>,
+    avg: Colorf,
+    vdif: f64,
+    split: usize,
+}
+
+impl QuantizerNode {
+    fn new(mut histogram: Vec<ColorCount>) -> QuantizerNode {
+        let mut n = 0usize;
+        let mut fsum = Colorf::zero();
+        let mut fsum2 = Colorf::zero();
+
+        for entry in &histogram {
+            n += entry.count;
+            fsum += entry.color * entry.count as f64;
+            fsum2 += entry.color * entry.color * entry.count as f64;
+        }
+
+        if n == 0 {
+            return QuantizerNode {
+                histogram,
+                avg: Colorf::zero(),
+                vdif: 0.0,
+                split: 0,
+            };
+        }
+
+        let avg = fsum * (1.0 / n as f64);
+        let vc = fsum2 - fsum * avg;
+        let v = vc.r + vc.g + vc.b + vc.a;
+
+        // Sort by the channel with the largest variance
+        if vc.r > vc.g && vc.r > vc.b && vc.r > vc.a {
+            histogram.sort_by(|a, b| a.color.r.total_cmp(&b.color.r));
+        } else if vc.g > vc.b && vc.g > vc.a {
+            histogram.sort_by(|a, b| a.color.g.total_cmp(&b.color.g));
+        } else if vc.b > vc.a {
+            histogram.sort_by(|a, b| a.color.b.total_cmp(&b.color.b));
+        } else {
+            histogram.sort_by(|a, b| a.color.a.total_cmp(&b.color.a));
+        }
+
+        // Primary vector of the distribution
+        let mut dir = Colorf::zero();
+        for entry in &histogram {
+            let mut tmp = (entry.color - avg) * entry.count as f64;
+            if tmp.dot(&dir) < 0.0 {
+                tmp *= -1.0;
+            }
+            dir += tmp;
+        }
+
+        let length = dir.dot(&dir).sqrt();
+        dir *= if length < 0.000000001 { 1.0 } else { 1.0 / length };
+
+        histogram.sort_by(|a, b| a.color.dot(&dir).total_cmp(&b.color.dot(&dir)));
+
+        // Split index with the lowest total variance
+        let mut sum = Colorf::zero();
+        let mut sum2 = Colorf::zero();
+        let mut vdif = -v;
+        let mut n2 = 0;
+        let mut split = 0usize;
+        for (i, entry) in histogram.iter().enumerate() {
+            n2 += entry.count;
+            sum += entry.color * entry.count as f64;
+            sum2 += entry.color * entry.color * entry.count as f64;
+
+            if n2 < n {
+                let tmp = sum2 - sum * sum * (1.0 / n2 as f64);
+                let dif_sum = fsum - sum;
+                let tmp2 = (fsum2 - sum2) - dif_sum * dif_sum * (1.0 / (n - n2) as f64);
+                let nv = tmp.r + tmp.g + tmp.b + tmp.a + tmp2.r + tmp2.g + tmp2.b + tmp2.a;
+                if -nv > vdif {
+                    vdif = -nv;
+                    split = i + 1;
+                }
+            }
+        }
+
+        QuantizerNode {
+            histogram,
+            avg,
+            vdif: vdif + v,
+            split,
+        }
+    }
+}
+
+fn step(nodes: &mut Vec<QuantizerNode>) {
+    let mut best_i = 0;
+    let mut best_e = 0.0;
+    for (i, node) in nodes.iter().enumerate() {
+        if node.vdif >= best_e {
+            best_e = node.vdif;
+            best_i = i;
+        }
+    }
+
+    let node = nodes.swap_remove(best_i);
+    let mut colors1 = node.histogram;
+    let colors2 = colors1.split_off(node.split);
+    nodes.push(QuantizerNode::new(colors1));
+    nodes.push(QuantizerNode::new(colors2));
+}
+
+fn optimize<O: Optimizer>(nodes: Vec<QuantizerNode>, optimizer: &O, num_iterations: usize) -> Vec<QuantizerNode> {
+    let (mut colors, histograms): (Vec<Colorf>, Vec<Vec<ColorCount>>) =
+        nodes.into_iter().map(|node| (node.avg, node.histogram)).unzip();
+    let histogram: Vec<ColorCount> = histograms.into_iter().flatten().collect();
+    for _ in 0..num_iterations {
+        colors = optimizer.step(colors, &histogram);
+    }
+
+    let mut histograms: Vec<Vec<ColorCount>> = (0..colors.len()).map(|_| Vec::new()).collect();
+    let map = ColorMap::from_float_colors(colors);
+    for color in histogram {
+        histograms[map.find_nearest(color.color)].push(color);
+    }
+    histograms.into_iter().map(QuantizerNode::new).collect()
+}
+
+/// Median cut palette generation, following exoquant's `generate_palette`, including its K-Means
+/// optimizations between steps.
+pub fn generate_palette<C: ColorSpace, O: Optimizer>(
+    histogram: &[ColorCount],
+    colorspace: &C,
+    optimizer: &O,
+    num_colors: usize,
+) -> Vec<Color> {
+    let mut nodes = vec![QuantizerNode::new(histogram.to_vec())];
+    let kmeans_step = if num_colors > 64 {
+        num_colors
+    } else if num_colors <= 16 {
+        1
+    } else {
+        (num_colors as f64).sqrt().round() as usize
+    };
+
+    while nodes.len() < num_colors {
+        step(&mut nodes);
+        if nodes.len() % kmeans_step == 0 {
+            nodes = optimize(nodes, optimizer, 4);
+        }
+    }
+
+    nodes.iter().map(|node| colorspace.from_float(node.avg)).collect()
+}
+
+/// Runs K-Means iterations on a palette.
+pub fn optimize_palette<C: ColorSpace, O: Optimizer>(
+    colorspace: &C,
+    palette: &[Color],
+    histogram: &[ColorCount],
+    optimizer: &O,
+    num_iterations: usize,
+) -> Vec<Color> {
+    let mut colors = palette.iter().map(|color| colorspace.to_float(*color)).collect();
+    for _ in 0..num_iterations {
+        colors = optimizer.step(colors, histogram);
+    }
+    colors.into_iter().map(|color| colorspace.from_float(color)).collect()
+}
+
+const KMEANS_ITERATIONS: usize = 16;
+
+/// Picks the initial centroids with k-means++, each one chosen with a probability proportional
+/// to its count times the squared distance to the closest centroid so far.
+fn kmeans_plus_plus(histogram: &[ColorCount], num_colors: usize, rng: &mut StdRng) -> Vec<Colorf> {
+    let mut centroids: Vec<Colorf> = Vec::with_capacity(num_colors);
+    let mut distances = vec![f64::INFINITY; histogram.len()];
+
+    while centroids.len() < num_colors {
+        let weights: Vec<f64> = histogram
+            .iter()
+            .zip(&distances)
+            .map(|(entry, distance)| {
+                if centroids.is_empty() {
+                    entry.count as f64
+                } else {
+                    entry.count as f64 * distance
+                }
+            })
+            .collect();
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            // fewer distinct colors than requested
+            break;
+        }
+
+        let mut target = rng.gen_range(0.0..total);
+        let mut chosen = histogram.len() - 1;
+        for (i, weight) in weights.iter().enumerate() {
+            if target < *weight {
+                chosen = i;
+                break;
+            }
+            target -= weight;
+        }
+
+        let centroid = histogram[chosen].color;
+        centroids.push(centroid);
+        for (distance, entry) in distances.iter_mut().zip(histogram) {
+            let diff = entry.color - centroid;
+            *distance = distance.min(diff.dot(&diff));
+        }
+    }
+
+    centroids
+}
+
+/// Generates a palette with k-means clustering, seeded so the same seed always produces the same
+/// palette. Centroids that end up without any colors keep their previous position.
+pub fn kmeans_palette<C: ColorSpace>(histogram: &[ColorCount], colorspace: &C, num_colors: usize, seed: u64) -> Vec<Color> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut centroids = kmeans_plus_plus(histogram, num_colors, &mut rng);
+
+    for _ in 0..KMEANS_ITERATIONS {
+        let map = ColorMap::from_float_colors(centroids.clone());
+        let mut sums = vec![Colorf::zero(); centroids.len()];
+        let mut weights = vec![0usize; centroids.len()];
+        for entry in histogram {
+            let index = map.find_nearest(entry.color);
+            sums[index] += entry.color * entry.count as f64;
+            weights[index] += entry.count;
+        }
+
+        for (centroid, (sum, weight)) in centroids.iter_mut().zip(sums.into_iter().zip(weights)) {
+            if weight > 0 {
+                *centroid = sum * (1.0 / weight as f64);
+            }
+        }
+    }
+
+    centroids
+        .into_iter()
+        .map(|centroid| colorspace.from_float(centroid))
+        .collect()
+}
@@ -0,0 +1,200 @@
+use crate::error::Error;
+use serde_json::Value;
+
+/// JSON Schema of the 2dja format, with the 2dj format (a single poster) under `$defs/poster`.
+pub const DEFAULT_SCHEMA: &str = include_str!("poster.schema.json");
+
+/// How many violations are listed, the rest are only counted.
+const LISTED_VIOLATIONS: usize = 20;
+
+/// A part of a document that doesn't match its schema, at a JSON pointer like `/pages/0/pixels/17`.
+pub struct Violation {
+    pub pointer: String,
+    pub message: String,
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_i64() || number.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "number" => value.is_number(),
+        "integer" => value.as_f64().is_some_and(|number| number.fract() == 0.0),
+        _ => type_name(value) == name,
+    }
+}
+
+fn escape_pointer(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Checks documents against a subset of JSON Schema (2020-12): `$ref` to the same schema, `type`,
+/// `enum`, `const`, `anyOf`, `minimum`, `maximum`, `exclusiveMinimum`, `exclusiveMaximum`,
+/// `minLength`, `maxLength`, `items`, `minItems`, `maxItems`, `properties`, `required` and
+/// `additionalProperties`. Other keywords are ignored, like validators do with unknown ones.
+struct Validator<'a> {
+    root: &'a Value,
+    violations: Vec<Violation>,
+}
+
+impl<'a> Validator<'a> {
+    fn violation(&mut self, pointer: &str, message: String) {
+        self.violations.push(Violation {
+            pointer: pointer.to_string(),
+            message,
+        });
+    }
+
+    fn validate(&mut self, schema: &'a Value, value: &Value, pointer: &str) {
+        let schema = match schema {
+            Value::Bool(true) => return,
+            Value::Bool(false) => return self.violation(pointer, "no value is allowed here".to_string()),
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match reference.strip_prefix('#').and_then(|path| self.root.pointer(path)) {
+                Some(referenced) => self.validate(referenced, value, pointer),
+                None => self.violation(pointer, format!("schema reference {} not found", reference)),
+            }
+        }
+
+        let types: Vec<&str> = match schema.get("type") {
+            Some(Value::String(name)) => vec![name.as_str()],
+            Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|name| has_type(value, name)) {
+            // the other keywords would only repeat that the type is wrong
+            return self.violation(pointer, format!("expected {0}, found {1}", types.join(" or "), type_name(value)));
+        }
+
+        if let Some(Value::Array(allowed)) = schema.get("enum") {
+            if !allowed.contains(value) {
+                let allowed: Vec<String> = allowed.iter().map(Value::to_string).collect();
+                self.violation(pointer, format!("{0} is not one of {1}", value, allowed.join(", ")));
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if expected != value {
+                self.violation(pointer, format!("expected {0}, found {1}", expected, value));
+            }
+        }
+        if let Some(Value::Array(options)) = schema.get("anyOf") {
+            let matches_any = options.iter().any(|option| {
+                let mut validator = Validator {
+                    root: self.root,
+                    violations: Vec::new(),
+                };
+                validator.validate(option, value, pointer);
+                validator.violations.is_empty()
+            });
+            if !matches_any {
+                self.violation(pointer, "doesn't match any of the allowed schemas".to_string());
+            }
+        }
+
+        if let Some(number) = value.as_f64() {
+            let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+            if let Some(minimum) = bound("minimum").filter(|minimum| number < *minimum) {
+                self.violation(pointer, format!("{0} is less than the minimum {1}", value, minimum));
+            }
+            if let Some(maximum) = bound("maximum").filter(|maximum| number > *maximum) {
+                self.violation(pointer, format!("{0} is more than the maximum {1}", value, maximum));
+            }
+            if let Some(minimum) = bound("exclusiveMinimum").filter(|minimum| number <= *minimum) {
+                self.violation(pointer, format!("{0} has to be more than {1}", value, minimum));
+            }
+            if let Some(maximum) = bound("exclusiveMaximum").filter(|maximum| number >= *maximum) {
+                self.violation(pointer, format!("{0} has to be less than {1}", value, maximum));
+            }
+        }
+
+        let length_bound = |keyword: &str| schema.get(keyword).and_then(Value::as_u64).map(|bound| bound as usize);
+        if let Value::String(string) = value {
+            let length = string.chars().count();
+            if let Some(minimum) = length_bound("minLength").filter(|minimum| length < *minimum) {
+                self.violation(pointer, format!("text is {0} characters long, less than {1}", length, minimum));
+            }
+            if let Some(maximum) = length_bound("maxLength").filter(|maximum| length > *maximum) {
+                self.violation(pointer, format!("text is {0} characters long, more than {1}", length, maximum));
+            }
+        }
+
+        if let Value::Array(items) = value {
+            if let Some(minimum) = length_bound("minItems").filter(|minimum| items.len() < *minimum) {
+                self.violation(pointer, format!("has {0} items, less than {1}", items.len(), minimum));
+            }
+            if let Some(maximum) = length_bound("maxItems").filter(|maximum| items.len() > *maximum) {
+                self.violation(pointer, format!("has {0} items, more than {1}", items.len(), maximum));
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    self.validate(item_schema, item, &format!("{0}/{1}", pointer, index));
+                }
+            }
+        }
+
+        if let Value::Object(object) = value {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for key in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(key) {
+                        self.violation(pointer, format!("missing required property {}", key));
+                    }
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (key, property) in object {
+                let property_pointer = format!("{0}/{1}", pointer, escape_pointer(key));
+                match properties.and_then(|properties| properties.get(key)) {
+                    Some(property_schema) => self.validate(property_schema, property, &property_pointer),
+                    None => {
+                        if let Some(additional) = schema.get("additionalProperties") {
+                            self.validate(additional, property, &property_pointer);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Checks a document against the part of a schema at a JSON pointer (`""` for the whole schema),
+/// returning every violation.
+pub fn validate(schema: &Value, path: &str, document: &Value) -> Result<Vec<Violation>, Error> {
+    let target = schema
+        .pointer(path)
+        .ok_or_else(|| Error::usage(format!("Schema has nothing at {}", path)))?;
+    let mut validator = Validator {
+        root: schema,
+        violations: Vec::new(),
+    };
+    validator.validate(target, document, "");
+    Ok(validator.violations)
+}
+
+/// Lists violations one per line, as `pointer: message`.
+pub fn format_violations(violations: &[Violation]) -> String {
+    let mut lines: Vec<String> = violations
+        .iter()
+        .take(LISTED_VIOLATIONS)
+        .map(|violation| {
+            let pointer = if violation.pointer.is_empty() { "/" } else { &violation.pointer };
+            format!("{0}: {1}", pointer, violation.message)
+        })
+        .collect();
+    if violations.len() > LISTED_VIOLATIONS {
+        lines.push(format!("... and {} more", violations.len() - LISTED_VIOLATIONS));
+    }
+    lines.join("\n")
+}
@@ -0,0 +1,326 @@
+use crate::poster::{posters_to_dynamic_image, PosterArray};
+use image::{GenericImageView, Rgba, RgbaImage};
+
+/// 5x7 font for printable ASCII (32-126), one byte per column with the top row in the lowest bit.
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // #
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x55, 0x22, 0x50], // &
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // )
+    [0x08, 0x2A, 0x1C, 0x2A, 0x08], // *
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // +
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x60, 0x60, 0x00, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // 0
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x45, 0x4B, 0x31], // 3
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], // 6
+    [0x01, 0x71, 0x09, 0x05, 0x03], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x06, 0x49, 0x49, 0x29, 0x1E], // 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], // :
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x00, 0x41, 0x22, 0x14, 0x08], // >
+    [0x02, 0x01, 0x51, 0x09, 0x06], // ?
+    [0x32, 0x49, 0x79, 0x41, 0x3E], // @
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], // A
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], // D
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7F, 0x09, 0x09, 0x09, 0x01], // F
+    [0x3E, 0x41, 0x49, 0x49, 0x7A], // G
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // H
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // J
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F], // M
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // N
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // O
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // Q
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // R
+    [0x46, 0x49, 0x49, 0x49, 0x31], // S
+    [0x01, 0x01, 0x7F, 0x01, 0x01], // T
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // U
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // V
+    [0x3F, 0x40, 0x38, 0x40, 0x3F], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x07, 0x08, 0x70, 0x08, 0x07], // Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], // Z
+    [0x00, 0x7F, 0x41, 0x41, 0x00], // [
+    [0x02, 0x04, 0x08, 0x10, 0x20], // \
+    [0x00, 0x41, 0x41, 0x7F, 0x00], // ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], // ^
+    [0x40, 0x40, 0x40, 0x40, 0x40], // _
+    [0x00, 0x01, 0x02, 0x04, 0x00], // `
+    [0x20, 0x54, 0x54, 0x54, 0x78], // a
+    [0x7F, 0x48, 0x44, 0x44, 0x38], // b
+    [0x38, 0x44, 0x44, 0x44, 0x20], // c
+    [0x38, 0x44, 0x44, 0x48, 0x7F], // d
+    [0x38, 0x54, 0x54, 0x54, 0x18], // e
+    [0x08, 0x7E, 0x09, 0x01, 0x02], // f
+    [0x0C, 0x52, 0x52, 0x52, 0x3E], // g
+    [0x7F, 0x08, 0x04, 0x04, 0x78], // h
+    [0x00, 0x44, 0x7D, 0x40, 0x00], // i
+    [0x20, 0x40, 0x44, 0x3D, 0x00], // j
+    [0x7F, 0x10, 0x28, 0x44, 0x00], // k
+    [0x00, 0x41, 0x7F, 0x40, 0x00], // l
+    [0x7C, 0x04, 0x18, 0x04, 0x78], // m
+    [0x7C, 0x08, 0x04, 0x04, 0x78], // n
+    [0x38, 0x44, 0x44, 0x44, 0x38], // o
+    [0x7C, 0x14, 0x14, 0x14, 0x08], // p
+    [0x08, 0x14, 0x14, 0x18, 0x7C], // q
+    [0x7C, 0x08, 0x04, 0x04, 0x08], // r
+    [0x48, 0x54, 0x54, 0x54, 0x20], // s
+    [0x04, 0x3F, 0x44, 0x40, 0x20], // t
+    [0x3C, 0x40, 0x40, 0x20, 0x7C], // u
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], // v
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], // w
+    [0x44, 0x28, 0x10, 0x28, 0x44], // x
+    [0x0C, 0x50, 0x50, 0x50, 0x3C], // y
+    [0x44, 0x64, 0x54, 0x4C, 0x44], // z
+    [0x00, 0x08, 0x36, 0x41, 0x00], // {
+    [0x00, 0x00, 0x7F, 0x00, 0x00], // |
+    [0x00, 0x41, 0x36, 0x08, 0x00], // }
+    [0x08, 0x04, 0x08, 0x10, 0x08], // ~
+];
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+const GLYPH_ADVANCE: u32 = GLYPH_WIDTH + 1;
+const LINE_HEIGHT: u32 = GLYPH_HEIGHT + 2;
+
+const PAGE_SIZE: u32 = 128;
+const BORDER: u32 = 1;
+const SPACING: u32 = 8;
+
+const SWATCH_SIZE: u32 = 40;
+const SWATCH_COLUMNS: u32 = 8;
+
+const BACKGROUND: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const BORDER_COLOR: Rgba<u8> = Rgba([64, 64, 64, 255]);
+const NO_INK_COLOR: Rgba<u8> = Rgba([192, 192, 192, 255]);
+const TEXT_COLOR: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// Splits a label into lines that fit below a page, unknown characters are drawn as '?'.
+fn wrap_caption(label: &str) -> Vec<String> {
+    wrap_text(label, PAGE_SIZE + 2 * BORDER)
+}
+
+/// Splits text into lines of at most width pixels, unknown characters are drawn as '?'.
+fn wrap_text(text: &str, width: u32) -> Vec<String> {
+    let max_chars = usize::max(1, (width / GLYPH_ADVANCE) as usize);
+    let characters: Vec<char> = text
+        .chars()
+        .map(|character| if (' '..='~').contains(&character) { character } else { '?' })
+        .collect();
+
+    characters
+        .chunks(max_chars)
+        .map(|line| line.iter().collect::<String>())
+        .collect()
+}
+
+fn draw_text(image: &mut RgbaImage, text: &str, x: u32, y: u32) {
+    for (index, character) in text.chars().enumerate() {
+        let glyph = FONT[character as usize - ' ' as usize];
+        let glyph_x = x + index as u32 * GLYPH_ADVANCE;
+
+        for (column, bits) in glyph.iter().enumerate() {
+            for row in 0..GLYPH_HEIGHT {
+                if bits & (1 << row) != 0 {
+                    image.put_pixel(glyph_x + column as u32, y + row, TEXT_COLOR);
+                }
+            }
+        }
+    }
+}
+
+/// Lays out every page of a poster array in a grid with a border and its label below it, like a
+/// contact sheet.
+pub fn render_sheet(poster_array: &PosterArray, columns: u32, threads: u32) -> RgbaImage {
+    let flattened = posters_to_dynamic_image(poster_array, threads);
+
+    let captions: Vec<Vec<String>> = poster_array
+        .pages
+        .iter()
+        .map(|page| wrap_caption(&page.label))
+        .collect();
+    let caption_lines = captions.iter().map(|lines| lines.len()).max().unwrap_or(0) as u32;
+
+    let page_count = poster_array.pages.len() as u32;
+    let columns = u32::max(1, u32::min(columns, page_count));
+    let rows = page_count.div_ceil(columns);
+
+    let cell_width = PAGE_SIZE + 2 * BORDER;
+    let cell_height = PAGE_SIZE + 2 * BORDER + caption_lines * LINE_HEIGHT;
+    let mut sheet = RgbaImage::from_pixel(
+        SPACING + columns * (cell_width + SPACING),
+        SPACING + rows * (cell_height + SPACING),
+        BACKGROUND,
+    );
+
+    for (index, caption) in captions.iter().enumerate() {
+        let cell_x = SPACING + (index as u32 % columns) * (cell_width + SPACING);
+        let cell_y = SPACING + (index as u32 / columns) * (cell_height + SPACING);
+
+        for y in 0..cell_width {
+            for x in 0..cell_width {
+                let color = if x < BORDER || y < BORDER || x >= PAGE_SIZE + BORDER || y >= PAGE_SIZE + BORDER {
+                    BORDER_COLOR
+                } else {
+                    NO_INK_COLOR
+                };
+                sheet.put_pixel(cell_x + x, cell_y + y, color);
+            }
+        }
+
+        // pages are stored row by row in the poster array grid
+        let page_x = (index as u32 % poster_array.width) * PAGE_SIZE;
+        let page_y = (index as u32 / poster_array.width) * PAGE_SIZE;
+        let page = flattened.view(page_x, page_y, PAGE_SIZE, PAGE_SIZE);
+        for (x, y, pixel) in page.pixels() {
+            if pixel[3] != 0 {
+                sheet.put_pixel(cell_x + BORDER + x, cell_y + BORDER + y, pixel);
+            }
+        }
+
+        for (line_index, line) in caption.iter().enumerate() {
+            draw_text(
+                &mut sheet,
+                line,
+                cell_x,
+                cell_y + cell_width + 2 + line_index as u32 * LINE_HEIGHT,
+            );
+        }
+    }
+
+    sheet
+}
+
+/// Draws every color of a palette (0xRRGGBB) as a square with its hex value below it, in rows of
+/// 8 colors.
+pub fn render_swatch(palette: &[u32]) -> RgbaImage {
+    let color_count = palette.len() as u32;
+    let columns = u32::max(1, u32::min(SWATCH_COLUMNS, color_count));
+    let rows = u32::max(1, color_count.div_ceil(columns));
+
+    let cell_width = SWATCH_SIZE + 2 * BORDER;
+    let cell_height = cell_width + LINE_HEIGHT;
+    let mut swatch = RgbaImage::from_pixel(
+        SPACING + columns * (cell_width + SPACING),
+        SPACING + rows * (cell_height + SPACING),
+        BACKGROUND,
+    );
+
+    for (index, color) in palette.iter().enumerate() {
+        let cell_x = SPACING + (index as u32 % columns) * (cell_width + SPACING);
+        let cell_y = SPACING + (index as u32 / columns) * (cell_height + SPACING);
+        let fill = Rgba([(color >> 16) as u8, (color >> 8) as u8, *color as u8, 255]);
+
+        for y in 0..cell_width {
+            for x in 0..cell_width {
+                let border = x < BORDER || y < BORDER || x >= SWATCH_SIZE + BORDER || y >= SWATCH_SIZE + BORDER;
+                swatch.put_pixel(cell_x + x, cell_y + y, if border { BORDER_COLOR } else { fill });
+            }
+        }
+
+        draw_text(&mut swatch, &format!("#{:06X}", color), cell_x, cell_y + cell_width + 2);
+    }
+
+    swatch
+}
+
+/// Draws the whole print with a line between the posters and every poster tagged with its number
+/// and label, under a title, to show where each poster goes on the wall. `numbers` holds the
+/// number of every page.
+pub fn render_assembly_guide(poster_array: &PosterArray, title: &str, numbers: &[u32], threads: u32) -> RgbaImage {
+    let flattened = posters_to_dynamic_image(poster_array, threads);
+
+    let grid_width = BORDER + poster_array.width * (PAGE_SIZE + BORDER);
+    let grid_height = BORDER + poster_array.height * (PAGE_SIZE + BORDER);
+    let heading = wrap_text(
+        &format!("{0} - {1}x{2} posters", title, poster_array.width, poster_array.height),
+        grid_width,
+    );
+    let grid_y = SPACING + heading.len() as u32 * LINE_HEIGHT + SPACING;
+    let mut guide = RgbaImage::from_pixel(SPACING * 2 + grid_width, grid_y + grid_height + SPACING, BACKGROUND);
+
+    for (line_index, line) in heading.iter().enumerate() {
+        draw_text(&mut guide, line, SPACING, SPACING + line_index as u32 * LINE_HEIGHT);
+    }
+    for y in 0..grid_height {
+        for x in 0..grid_width {
+            guide.put_pixel(SPACING + x, grid_y + y, BORDER_COLOR);
+        }
+    }
+
+    for (index, page) in poster_array.pages.iter().enumerate() {
+        let (column, row) = (index as u32 % poster_array.width, index as u32 / poster_array.width);
+        let tile_x = SPACING + BORDER + column * (PAGE_SIZE + BORDER);
+        let tile_y = grid_y + BORDER + row * (PAGE_SIZE + BORDER);
+
+        let tile = flattened.view(column * PAGE_SIZE, row * PAGE_SIZE, PAGE_SIZE, PAGE_SIZE);
+        for (x, y, pixel) in tile.pixels() {
+            let color = if pixel[3] != 0 { pixel } else { NO_INK_COLOR };
+            guide.put_pixel(tile_x + x, tile_y + y, color);
+        }
+
+        // the tag is a box in the top left corner, wide enough for its longest line
+        let mut tag = vec![format!("#{}", numbers[index])];
+        tag.extend(wrap_text(&page.label, PAGE_SIZE - 2));
+        let tag_width = tag.iter().map(|line| line.len() as u32).max().unwrap_or(0) * GLYPH_ADVANCE + 1;
+        let tag_height = u32::min(tag.len() as u32 * LINE_HEIGHT, PAGE_SIZE);
+        for y in 0..tag_height {
+            for x in 0..u32::min(tag_width, PAGE_SIZE) {
+                guide.put_pixel(tile_x + x, tile_y + y, BACKGROUND);
+            }
+        }
+        for (line_index, line) in tag.iter().enumerate().take((PAGE_SIZE / LINE_HEIGHT) as usize) {
+            draw_text(&mut guide, line, tile_x + 1, tile_y + 1 + line_index as u32 * LINE_HEIGHT);
+        }
+    }
+
+    guide
+}
+
+/// The assembly guide as text: the title, the grid of poster numbers and the label of every
+/// poster by number.
+pub fn assembly_guide_text(poster_array: &PosterArray, title: &str, numbers: &[u32]) -> String {
+    let cell_width = numbers.iter().max().map_or(1, |number| number.to_string().len());
+    let separator = format!("+{}\n", format!("{}+", "-".repeat(cell_width + 2)).repeat(poster_array.width as usize));
+
+    let mut text = format!("{0}\n{1}x{2} posters, top row first\n\n", title, poster_array.width, poster_array.height);
+    for row in numbers.chunks(poster_array.width as usize) {
+        text.push_str(&separator);
+        text.push('|');
+        for number in row {
+            text.push_str(&format!(" {:>width$} |", number, width = cell_width));
+        }
+        text.push('\n');
+    }
+    text.push_str(&separator);
+    text.push('\n');
+
+    let mut labels: Vec<(u32, &str)> = numbers.iter().copied().zip(poster_array.pages.iter().map(|page| page.label.as_str())).collect();
+    labels.sort_by_key(|(number, _)| *number);
+    for (number, label) in labels {
+        text.push_str(&format!("{:>width$}: {}\n", number, label, width = cell_width));
+    }
+    text
+}
@@ -0,0 +1,57 @@
+use exoquant::{ColorMap, Colorf};
+use wide::f64x4;
+
+/// Lanes of a vector, the palette colors compared at once.
+const LANES: usize = 4;
+
+/// The colors of a palette split into groups of four, with one vector per channel, so the distance
+/// to four palette colors is computed at once. The last group is padded with infinite colors that
+/// are never nearest.
+pub struct LanePalette {
+    groups: Vec<[f64x4; 4]>,
+}
+
+impl LanePalette {
+    pub fn new(map: &ColorMap) -> LanePalette {
+        let colors = map.num_colors();
+        let groups = (0..colors)
+            .step_by(LANES)
+            .map(|start| {
+                let lanes: [Colorf; LANES] = std::array::from_fn(|lane| match start + lane {
+                    index if index < colors => map.float_color(index),
+                    _ => Colorf {
+                        r: f64::INFINITY,
+                        g: f64::INFINITY,
+                        b: f64::INFINITY,
+                        a: f64::INFINITY,
+                    },
+                });
+                [
+                    f64x4::new(lanes.map(|color| color.r)),
+                    f64x4::new(lanes.map(|color| color.g)),
+                    f64x4::new(lanes.map(|color| color.b)),
+                    f64x4::new(lanes.map(|color| color.a)),
+                ]
+            })
+            .collect();
+        LanePalette { groups }
+    }
+
+    /// Index of the palette color nearest to `color`, the lowest index on a tie. Every lane does
+    /// the same operations in the same order as comparing one color at a time, so the result is
+    /// the same as the scalar search.
+    pub fn find_nearest(&self, color: Colorf) -> usize {
+        let (r, g, b, a) = (f64x4::splat(color.r), f64x4::splat(color.g), f64x4::splat(color.b), f64x4::splat(color.a));
+        let mut nearest = (0, f64::INFINITY);
+        for (group, [palette_r, palette_g, palette_b, palette_a]) in self.groups.iter().enumerate() {
+            let (dr, dg, db, da) = (r - *palette_r, g - *palette_g, b - *palette_b, a - *palette_a);
+            let distances = dr * dr + dg * dg + db * db + da * da;
+            for (lane, distance) in distances.to_array().into_iter().enumerate() {
+                if distance < nearest.1 {
+                    nearest = (group * LANES + lane, distance);
+                }
+            }
+        }
+        nearest.0
+    }
+}
@@ -0,0 +1,203 @@
+use crate::poster::PosterArray;
+use image::{DynamicImage, GenericImageView, Pixel};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+pub struct PosterStats {
+    pub x: u32,
+    pub y: u32,
+    pub used_colors: usize,
+    pub mean_squared_error: f64,
+}
+
+pub struct PosterArrayStats {
+    pub used_colors: usize,
+    pub mean_squared_error: f64,
+    pub posters: Vec<PosterStats>,
+}
+
+/// Compares the quantized posters against the image they were generated from.
+///
+/// The error is averaged over the red, green and blue channels of every pixel, transparent
+/// (index 0) pixels are skipped.
+pub fn poster_array_stats(poster_array: &PosterArray, original: &DynamicImage) -> PosterArrayStats {
+    let mut used_colors: HashSet<u32> = HashSet::new();
+    let mut total_error: f64 = 0.0;
+    let mut total_pixels: usize = 0;
+    let mut posters: Vec<PosterStats> = Vec::with_capacity(poster_array.pages.len());
+
+    for (poster_index, poster) in poster_array.pages.iter().enumerate() {
+        let x = poster_index as u32 % poster_array.width;
+        let y = poster_index as u32 / poster_array.width;
+
+        let mut poster_colors: HashSet<u8> = HashSet::new();
+        let mut poster_error: f64 = 0.0;
+        let mut poster_pixels: usize = 0;
+
+        for (pixel_index, pixel) in poster.pixels.iter().enumerate() {
+            if *pixel == 0 {
+                continue;
+            }
+
+            let color_value = poster.palette[*pixel as usize - 1];
+            poster_colors.insert(*pixel);
+            used_colors.insert(color_value);
+
+            let source = original
+                .get_pixel(
+                    x * poster.width + pixel_index as u32 % poster.width,
+                    y * poster.height + pixel_index as u32 / poster.width,
+                )
+                .to_rgb();
+            let quantized = [
+                (color_value >> 16) & 0xFF,
+                (color_value >> 8) & 0xFF,
+                color_value & 0xFF,
+            ];
+
+            for channel in 0..3 {
+                let difference = source[channel] as f64 - quantized[channel] as f64;
+                poster_error += difference * difference / 3.0;
+            }
+            poster_pixels += 1;
+        }
+
+        total_error += poster_error;
+        total_pixels += poster_pixels;
+        posters.push(PosterStats {
+            x,
+            y,
+            used_colors: poster_colors.len(),
+            mean_squared_error: poster_error / poster_pixels.max(1) as f64,
+        });
+    }
+
+    PosterArrayStats {
+        used_colors: used_colors.len(),
+        mean_squared_error: total_error / total_pixels.max(1) as f64,
+        posters,
+    }
+}
+
+pub fn print_stats(stats: &PosterArrayStats) {
+    println!("Distinct palette colors used: {}", stats.used_colors);
+    println!("Mean squared error: {:.2}", stats.mean_squared_error);
+    for poster in &stats.posters {
+        println!(
+            "Poster ({0},{1}): {2} colors, mean squared error {3:.2}",
+            poster.x + 1,
+            poster.y + 1,
+            poster.used_colors,
+            poster.mean_squared_error
+        );
+    }
+}
+
+/// Share of the pixels a poster may have in a single palette color before it counts as flat.
+pub const FLAT_THRESHOLD: f64 = 0.95;
+
+pub struct FlatPoster {
+    pub x: u32,
+    pub y: u32,
+    pub color: u32,
+    pub share: f64,
+}
+
+/// Checks whether more than `FLAT_THRESHOLD` of the pixels of a poster share one palette color,
+/// which usually means a wrong palette or an image that ended up as a flat blob. Transparent
+/// pixels don't count as a color.
+pub fn flat_poster(pixels: &[u8], palette: &[u32], x: u32, y: u32) -> Option<FlatPoster> {
+    let mut counts = [0usize; 256];
+    for pixel in pixels {
+        counts[*pixel as usize] += 1;
+    }
+
+    let (index, count) = counts.iter().enumerate().skip(1).max_by_key(|(_, count)| **count)?;
+    let share = *count as f64 / pixels.len().max(1) as f64;
+    if share <= FLAT_THRESHOLD {
+        return None;
+    }
+
+    Some(FlatPoster {
+        x,
+        y,
+        color: palette[index - 1],
+        share,
+    })
+}
+
+#[derive(Serialize)]
+pub struct ColorInk {
+    pub color: u32,
+    pub pixels: usize,
+}
+
+#[derive(Serialize)]
+pub struct PosterInk {
+    pub x: u32,
+    pub y: u32,
+    pub pixels: usize,
+    pub colors: usize,
+}
+
+/// Ink needed to print a poster array, one unit per inked pixel.
+#[derive(Serialize)]
+pub struct InkReport {
+    pub pixels: usize,
+    pub colors: Vec<ColorInk>,
+    pub posters: Vec<PosterInk>,
+}
+
+/// Tallies how many pixels of every palette color the posters use, in total and per poster.
+/// Transparent (index 0) pixels need no ink. Colors are sorted by usage, most used first.
+pub fn ink_report(poster_array: &PosterArray) -> InkReport {
+    let mut colors: BTreeMap<u32, usize> = BTreeMap::new();
+    let mut posters: Vec<PosterInk> = Vec::with_capacity(poster_array.pages.len());
+
+    for (poster_index, poster) in poster_array.pages.iter().enumerate() {
+        let mut counts = vec![0usize; poster.palette.len() + 1];
+        for pixel in &poster.pixels {
+            counts[*pixel as usize] += 1;
+        }
+
+        for (color, count) in poster.palette.iter().zip(&counts[1..]) {
+            if *count > 0 {
+                *colors.entry(*color).or_default() += count;
+            }
+        }
+        posters.push(PosterInk {
+            x: poster_index as u32 % poster_array.width,
+            y: poster_index as u32 / poster_array.width,
+            pixels: counts[1..].iter().sum(),
+            colors: counts[1..].iter().filter(|count| **count > 0).count(),
+        });
+    }
+
+    let mut colors: Vec<ColorInk> = colors
+        .into_iter()
+        .map(|(color, pixels)| ColorInk { color, pixels })
+        .collect();
+    colors.sort_by(|a, b| b.pixels.cmp(&a.pixels).then(a.color.cmp(&b.color)));
+
+    InkReport {
+        pixels: posters.iter().map(|poster| poster.pixels).sum(),
+        colors,
+        posters,
+    }
+}
+
+pub fn print_ink_report(report: &InkReport) {
+    println!("Ink needed: {0} pixels in {1} colors", report.pixels, report.colors.len());
+    for color in &report.colors {
+        println!("  #{0:06X}: {1} pixels", color.color, color.pixels);
+    }
+    for poster in &report.posters {
+        println!(
+            "Poster ({0},{1}): {2} pixels of ink in {3} colors",
+            poster.x + 1,
+            poster.y + 1,
+            poster.pixels,
+            poster.colors
+        );
+    }
+}
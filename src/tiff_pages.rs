@@ -0,0 +1,164 @@
+use crate::error::Error;
+use image::{DynamicImage, ImageBuffer, Rgba};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use tiff::decoder::{ChunkType, Decoder, DecodingResult, Limits};
+use tiff::ColorType;
+
+fn open(file: &Path, limits: Limits) -> Result<Decoder<BufReader<File>>, Error> {
+    let reader = File::open(file)
+        .map(BufReader::new)
+        .map_err(|error| Error::io(format!("Failed to open image: {}", error)))?;
+    Decoder::new(reader)
+        .map(|decoder| decoder.with_limits(limits))
+        .map_err(|error| Error::decode(format!("Failed to decode image: {}", error)))
+}
+
+/// Counts the pages (images) of a TIFF file.
+pub fn page_count(file: &Path) -> Result<usize, Error> {
+    let mut decoder = open(file, Limits::default())?;
+    let mut count = 1;
+    while decoder.more_images() {
+        decoder
+            .next_image()
+            .map_err(|error| Error::decode(format!("Failed to decode image: {}", error)))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Decodes a single page of a multi-page TIFF, which the image crate only reads the first page of.
+/// Only 8 and 16 bit gray and RGB pages, with or without alpha, are supported.
+pub fn read_page(
+    file: &Path,
+    page: usize,
+    unlimited: bool,
+    check_dimensions: impl Fn(u32, u32) -> Result<(), Error>,
+) -> Result<DynamicImage, Error> {
+    let decode_error = |error: tiff::TiffError| Error::decode(format!("Failed to decode page {0} of image: {1}", page + 1, error));
+
+    let mut decoder = open(file, if unlimited { Limits::unlimited() } else { Limits::default() })?;
+    decoder.seek_to_image(page).map_err(decode_error)?;
+    let (width, height) = decoder.dimensions().map_err(decode_error)?;
+    check_dimensions(width, height)?;
+
+    let color_type = decoder.colortype().map_err(decode_error)?;
+    let page_image = match (color_type, decoder.read_image().map_err(decode_error)?) {
+        (ColorType::Gray(8), DecodingResult::U8(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageLuma8),
+        (ColorType::GrayA(8), DecodingResult::U8(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageLumaA8),
+        (ColorType::RGB(8), DecodingResult::U8(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageRgb8),
+        (ColorType::RGBA(8), DecodingResult::U8(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageRgba8),
+        (ColorType::Gray(16), DecodingResult::U16(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageLuma16),
+        (ColorType::GrayA(16), DecodingResult::U16(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageLumaA16),
+        (ColorType::RGB(16), DecodingResult::U16(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageRgb16),
+        (ColorType::RGBA(16), DecodingResult::U16(buffer)) => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageRgba16),
+        _ => {
+            return Err(Error::decode(format!(
+                "Failed to decode page {0} of image: unsupported color type {1:?}",
+                page + 1,
+                color_type
+            )));
+        }
+    };
+    page_image.ok_or_else(|| Error::decode(format!("Failed to decode page {} of image: unexpected buffer size", page + 1)))
+}
+
+/// Reads the size of a page of a TIFF without decoding it.
+pub fn page_dimensions(file: &Path, page: usize) -> Result<(u32, u32), Error> {
+    let decode_error = |error: tiff::TiffError| Error::decode(format!("Failed to decode page {0} of image: {1}", page + 1, error));
+
+    let mut decoder = open(file, Limits::default())?;
+    decoder.seek_to_image(page).map_err(decode_error)?;
+    decoder.dimensions().map_err(decode_error)
+}
+
+/// Decodes a TIFF page one chunk (tile or strip) at a time, averaging every `factor` by `factor`
+/// block of pixels into one, so only the reduced image and a single chunk are in memory instead of
+/// the whole page. Blocks at the right and bottom edge average the pixels they have.
+///
+/// Returns `None` for pages that can't be read like this (color types tiff_pages doesn't support
+/// and planar configurations with a chunk per channel), which have to be decoded whole.
+pub fn read_reduced(
+    file: &Path,
+    page: usize,
+    factor: u32,
+    unlimited: bool,
+    check_dimensions: impl Fn(u32, u32) -> Result<(), Error>,
+) -> Result<Option<DynamicImage>, Error> {
+    let decode_error = |error: tiff::TiffError| Error::decode(format!("Failed to decode page {0} of image: {1}", page + 1, error));
+
+    let mut decoder = open(file, if unlimited { Limits::unlimited() } else { Limits::default() })?;
+    decoder.seek_to_image(page).map_err(decode_error)?;
+    let (width, height) = decoder.dimensions().map_err(decode_error)?;
+    let (channels, sixteen_bit) = match decoder.colortype().map_err(decode_error)? {
+        ColorType::Gray(8) => (1, false),
+        ColorType::GrayA(8) => (2, false),
+        ColorType::RGB(8) => (3, false),
+        ColorType::RGBA(8) => (4, false),
+        ColorType::Gray(16) => (1, true),
+        ColorType::GrayA(16) => (2, true),
+        ColorType::RGB(16) => (3, true),
+        ColorType::RGBA(16) => (4, true),
+        _ => return Ok(None),
+    };
+
+    let (chunk_width, chunk_height) = decoder.chunk_dimensions();
+    let (across, down) = (width.div_ceil(chunk_width), height.div_ceil(chunk_height));
+    let chunk_count = match decoder.get_chunk_type() {
+        ChunkType::Strip => decoder.strip_count(),
+        ChunkType::Tile => decoder.tile_count(),
+    }
+    .map_err(decode_error)?;
+    if chunk_count != across * down {
+        return Ok(None);
+    }
+
+    let (reduced_width, reduced_height) = (width.div_ceil(factor), height.div_ceil(factor));
+    check_dimensions(reduced_width, reduced_height)?;
+    let pixels = reduced_width as usize * reduced_height as usize;
+    let mut sums = vec![0u64; pixels * 4];
+    let mut counts = vec![0u32; pixels];
+
+    for chunk_index in 0..chunk_count {
+        let (data_width, data_height) = decoder.chunk_data_dimensions(chunk_index);
+        let samples: Vec<u64> = match decoder.read_chunk(chunk_index).map_err(decode_error)? {
+            DecodingResult::U8(buffer) => buffer.into_iter().map(u64::from).collect(),
+            DecodingResult::U16(buffer) => buffer.into_iter().map(u64::from).collect(),
+            _ => return Ok(None),
+        };
+        let (left, top) = ((chunk_index % across) * chunk_width, (chunk_index / across) * chunk_height);
+
+        for (row, line) in samples.chunks_exact(data_width as usize * channels).take(data_height as usize).enumerate() {
+            let reduced_row = (top + row as u32) / factor;
+            for (column, pixel) in line.chunks_exact(channels).enumerate() {
+                let index = (reduced_row * reduced_width + (left + column as u32) / factor) as usize;
+                // gray is spread to red, green and blue, and a missing alpha is opaque
+                let rgba = match *pixel {
+                    [gray] => [gray, gray, gray, if sixteen_bit { 65535 } else { 255 }],
+                    [gray, alpha] => [gray, gray, gray, alpha],
+                    [red, green, blue] => [red, green, blue, if sixteen_bit { 65535 } else { 255 }],
+                    [red, green, blue, alpha] => [red, green, blue, alpha],
+                    _ => unreachable!(),
+                };
+                for (sum, sample) in sums[index * 4..index * 4 + 4].iter_mut().zip(rgba) {
+                    *sum += sample;
+                }
+                counts[index] += 1;
+            }
+        }
+    }
+
+    let average = |index: usize| {
+        let count = u64::from(counts[index / 4].max(1));
+        (sums[index] + count / 2) / count
+    };
+    let reduced = if sixteen_bit {
+        let buffer = (0..pixels * 4).map(|index| average(index) as u16).collect();
+        ImageBuffer::<Rgba<u16>, Vec<u16>>::from_raw(reduced_width, reduced_height, buffer).map(DynamicImage::ImageRgba16)
+    } else {
+        let buffer = (0..pixels * 4).map(|index| average(index) as u8).collect();
+        ImageBuffer::<Rgba<u8>, Vec<u8>>::from_raw(reduced_width, reduced_height, buffer).map(DynamicImage::ImageRgba8)
+    };
+    Ok(reduced)
+}
@@ -0,0 +1,340 @@
+use crate::error::Error;
+use exoquant::Color;
+use image::{imageops::FilterType, DynamicImage};
+use ratatui::{
+    buffer::Buffer,
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color as TermColor, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, ListState, Paragraph, Widget},
+    DefaultTerminal, Frame,
+};
+use std::io::{self, IsTerminal};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait for a key before checking for a finished quantization.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Columns of the palette list: the cursor, the check box, a swatch, the hex code and the borders.
+const PALETTE_WIDTH: u16 = 20;
+/// Color transparent pixels are drawn with.
+const TRANSPARENT: TermColor = TermColor::Rgb(48, 48, 48);
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Save,
+    Quit,
+}
+
+/// Draws an image with half blocks, two pixels per cell, centered in the area.
+struct Preview<'a>(&'a DynamicImage);
+
+impl Widget for Preview<'_> {
+    fn render(self, area: Rect, buffer: &mut Buffer) {
+        if area.is_empty() || self.0.width() == 0 || self.0.height() == 0 {
+            return;
+        }
+        let image = self
+            .0
+            .resize(area.width as u32, area.height as u32 * 2, FilterType::Triangle)
+            .into_rgba8();
+        let color = |x, y| {
+            let pixel = image.get_pixel(x, y);
+            if pixel[3] == 0 {
+                TRANSPARENT
+            } else {
+                TermColor::Rgb(pixel[0], pixel[1], pixel[2])
+            }
+        };
+
+        let left = area.x + (area.width - image.width() as u16) / 2;
+        let top = area.y + (area.height - image.height().div_ceil(2) as u16) / 2;
+        for y in (0..image.height()).step_by(2) {
+            for x in 0..image.width() {
+                if let Some(cell) = buffer.cell_mut((left + x as u16, top + (y / 2) as u16)) {
+                    cell.set_char('▀').set_fg(color(x, y));
+                    if y + 1 < image.height() {
+                        cell.set_bg(color(x, y + 1));
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Picker<'a> {
+    palette: &'a [Color],
+    enabled: Vec<bool>,
+    list: ListState,
+    preview: DynamicImage,
+    error: f64,
+    message: String,
+    /// The colors of the last change were sent to be quantized, and the result isn't back yet.
+    quantizing: bool,
+}
+
+impl Picker<'_> {
+    fn picked(&self) -> Vec<Color> {
+        self.palette
+            .iter()
+            .zip(&self.enabled)
+            .filter(|(_, enabled)| **enabled)
+            .map(|(color, _)| *color)
+            .collect()
+    }
+
+    /// Handles a key press. Returns whether the switched on colors changed, or the action that ends picking.
+    fn key(&mut self, key: KeyEvent) -> Result<bool, Action> {
+        self.message.clear();
+        let mut changed = self.enabled.clone();
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Err(Action::Quit),
+            KeyCode::Char('q') | KeyCode::Esc => return Err(Action::Quit),
+            KeyCode::Char('s') => return Err(Action::Save),
+            KeyCode::Up | KeyCode::Char('k') => self.list.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.list.select_next(),
+            KeyCode::Home | KeyCode::Char('g') => self.list.select_first(),
+            KeyCode::End | KeyCode::Char('G') => self.list.select_last(),
+            KeyCode::Char('a') => changed.fill(true),
+            KeyCode::Char(' ') | KeyCode::Enter => {
+                let entry = self.list.selected().unwrap_or(0).min(changed.len() - 1);
+                changed[entry] = !changed[entry];
+            }
+            _ => {}
+        }
+
+        if !changed.contains(&true) {
+            self.message = "At least one color has to stay on".to_string();
+            return Ok(false);
+        }
+        let is_changed = changed != self.enabled;
+        self.enabled = changed;
+        Ok(is_changed)
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status, help] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1), Constraint::Length(1)]).areas(frame.area());
+        let [preview, palette] =
+            Layout::horizontal([Constraint::Min(1), Constraint::Length(PALETTE_WIDTH)]).areas(main);
+
+        let block = Block::bordered().title(" Preview ");
+        frame.render_widget(Preview(&self.preview), block.inner(preview));
+        frame.render_widget(block, preview);
+
+        let items = self.palette.iter().zip(&self.enabled).map(|(color, enabled)| {
+            let swatch = Style::new().fg(TermColor::Rgb(color.r, color.g, color.b));
+            let hex = format!(" #{:02X}{:02X}{:02X}", color.r, color.g, color.b);
+            ListItem::new(if *enabled {
+                Line::from(vec![Span::raw("[x] "), Span::styled("██", swatch), Span::raw(hex)])
+            } else {
+                Line::from(vec![
+                    Span::raw("[ ] "),
+                    Span::styled("░░", swatch),
+                    Span::styled(hex, Style::new().add_modifier(Modifier::DIM)),
+                ])
+            })
+        });
+        let list = List::new(items)
+            .block(Block::bordered().title(" Palette "))
+            .highlight_symbol("> ")
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, palette, &mut self.list);
+
+        let mut line = format!(
+            "{0} of {1} colors, mean squared error {2:.2}",
+            self.enabled.iter().filter(|enabled| **enabled).count(),
+            self.palette.len(),
+            self.error
+        );
+        if self.quantizing {
+            line.push_str(" - quantizing...");
+        }
+        if !self.message.is_empty() {
+            line.push_str(&format!(" - {}", self.message));
+        }
+        frame.render_widget(Paragraph::new(line), status);
+        frame.render_widget(
+            Paragraph::new("↑/↓ move, space switches a color, a switches all on, s saves, q quits")
+                .style(Style::new().add_modifier(Modifier::DIM)),
+            help,
+        );
+    }
+}
+
+/// Checks that the input and output are a terminal the palette can be picked in.
+pub fn check_terminal() -> Result<(), Error> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return Err(Error::usage("tui needs an interactive terminal"));
+    }
+    Ok(())
+}
+
+/// Lets the user switch colors of a palette on and off in the terminal. After every change the
+/// switched on colors are handed to `quantize` on another thread, which returns the preview of the
+/// posters and their mean squared error, or the error quantizing failed with. Returns the switched
+/// on colors when the user saves, `None` when they quit.
+pub fn pick_palette<F>(palette: &[Color], mut quantize: F) -> Result<Option<Vec<Color>>, Error>
+where
+    F: FnMut(&[Color]) -> Result<(DynamicImage, f64), Error> + Send,
+{
+    let (preview, error) = quantize(palette)?;
+    let mut picker = Picker {
+        palette,
+        enabled: vec![true; palette.len()],
+        list: ListState::default().with_selected(Some(0)),
+        preview,
+        error,
+        message: String::new(),
+        quantizing: false,
+    };
+
+    let mut terminal =
+        ratatui::try_init().map_err(|error| Error::io(format!("Failed to set up the terminal: {}", error)))?;
+    let picked = thread::scope(|scope| {
+        let (colors_sender, colors_receiver) = mpsc::channel::<(usize, Vec<Color>)>();
+        let (result_sender, result_receiver) = mpsc::channel();
+        scope.spawn(move || {
+            while let Ok(mut change) = colors_receiver.recv() {
+                // only the newest change is worth quantizing
+                while let Ok(newer) = colors_receiver.try_recv() {
+                    change = newer;
+                }
+                if result_sender.send((change.0, quantize(&change.1))).is_err() {
+                    break;
+                }
+            }
+        });
+        let picked = run(&mut terminal, &mut picker, &colors_sender, &result_receiver);
+        // lets the quantizing thread finish, so the scope can end
+        drop(colors_sender);
+        picked
+    });
+    ratatui::try_restore().map_err(|error| Error::io(format!("Failed to restore the terminal: {}", error)))?;
+    picked
+}
+
+type QuantizeResult = Result<(DynamicImage, f64), Error>;
+
+fn run(
+    terminal: &mut DefaultTerminal,
+    picker: &mut Picker,
+    colors_sender: &mpsc::Sender<(usize, Vec<Color>)>,
+    result_receiver: &mpsc::Receiver<(usize, QuantizeResult)>,
+) -> Result<Option<Vec<Color>>, Error> {
+    let draw_error = |error: io::Error| Error::io(format!("Failed to draw: {}", error));
+    let read_error = |error: io::Error| Error::io(format!("Failed to read input: {}", error));
+    let mut change = 0;
+    let mut redraw = true;
+
+    loop {
+        if redraw {
+            terminal.draw(|frame| picker.draw(frame)).map_err(draw_error)?;
+            redraw = false;
+        }
+
+        if let Ok((quantized, result)) = result_receiver.try_recv() {
+            (picker.preview, picker.error) = result?;
+            picker.quantizing = quantized != change;
+            redraw = true;
+            continue;
+        }
+        if !event::poll(POLL_INTERVAL).map_err(read_error)? {
+            continue;
+        }
+        match event::read().map_err(read_error)? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => match picker.key(key) {
+                Ok(true) => {
+                    change += 1;
+                    picker.quantizing = true;
+                    // the thread only stops when the sender is dropped, so sending can't fail
+                    let _ = colors_sender.send((change, picker.picked()));
+                    redraw = true;
+                }
+                Ok(false) => redraw = true,
+                Err(Action::Save) => return Ok(Some(picker.picked())),
+                Err(Action::Quit) => return Ok(None),
+            },
+            Event::Resize(_, _) => redraw = true,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn picker(palette: &[Color]) -> Picker<'_> {
+        Picker {
+            palette,
+            enabled: vec![true; palette.len()],
+            list: ListState::default().with_selected(Some(0)),
+            preview: DynamicImage::new_rgba8(1, 1),
+            error: 0.0,
+            message: String::new(),
+            quantizing: false,
+        }
+    }
+
+    fn press(picker: &mut Picker, code: KeyCode) -> Result<bool, Action> {
+        picker.key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn keys_switch_colors() {
+        let palette = [Color::new(255, 0, 0, 255), Color::new(0, 255, 0, 255), Color::new(0, 0, 255, 255)];
+        let mut picker = picker(&palette);
+
+        assert_eq!(press(&mut picker, KeyCode::Char(' ')), Ok(true));
+        assert_eq!(press(&mut picker, KeyCode::Down), Ok(false));
+        assert_eq!(press(&mut picker, KeyCode::Enter), Ok(true));
+        assert_eq!(picker.enabled, [false, false, true]);
+
+        // the last color can't be switched off
+        assert_eq!(press(&mut picker, KeyCode::Char('j')), Ok(false));
+        assert_eq!(press(&mut picker, KeyCode::Char(' ')), Ok(false));
+        assert_eq!(picker.enabled, [false, false, true]);
+        assert!(!picker.message.is_empty());
+
+        assert_eq!(press(&mut picker, KeyCode::Char('a')), Ok(true));
+        assert_eq!(picker.enabled, [true; 3]);
+        assert!(picker.message.is_empty());
+
+        assert_eq!(press(&mut picker, KeyCode::Char('s')), Err(Action::Save));
+        assert_eq!(press(&mut picker, KeyCode::Esc), Err(Action::Quit));
+        assert_eq!(
+            picker.key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+            Err(Action::Quit)
+        );
+    }
+
+    #[test]
+    fn preview_draws_two_pixels_per_cell() {
+        let mut image = RgbaImage::new(2, 4);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            *pixel = Rgba([x as u8 * 100, y as u8 * 50, 7, if (x, y) == (1, 3) { 0 } else { 255 }]);
+        }
+        let mut buffer = Buffer::empty(Rect::new(0, 0, 4, 2));
+        Preview(&DynamicImage::ImageRgba8(image)).render(Rect::new(0, 0, 4, 2), &mut buffer);
+
+        // the 2x4 image keeps its size and is centered in the 4 columns
+        for x in [0, 3] {
+            assert_eq!(buffer[(x, 0)].symbol(), " ");
+        }
+        for (x, y) in [(1, 0), (2, 0), (1, 1), (2, 1)] {
+            let cell = &buffer[(x, y)];
+            let (pixel_x, pixel_y) = (x as u8 - 1, y as u8 * 2);
+            assert_eq!(cell.symbol(), "▀");
+            assert_eq!(cell.fg, TermColor::Rgb(pixel_x * 100, pixel_y * 50, 7));
+            if (pixel_x, pixel_y + 1) == (1, 3) {
+                assert_eq!(cell.bg, TRANSPARENT);
+            } else {
+                assert_eq!(cell.bg, TermColor::Rgb(pixel_x * 100, (pixel_y + 1) * 50, 7));
+            }
+        }
+    }
+}